@@ -1,38 +1,273 @@
 use anyhow::{Context, Result};
-use std::process::Command;
-use log::{debug, error};
+use aws_sdk_s3::Client as S3Client;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::sync::{Arc, Mutex};
+use log::{debug, error, info, warn};
+use tempfile::NamedTempFile;
+use tokio::io::AsyncReadExt;
 
+use crate::cancel::{CancelToken, Cancelled};
+use crate::compression::Compression;
+use crate::crypto::{self, EncryptionRecipient};
+use crate::manifest::SnapshotManifest;
+use crate::postgres;
+use crate::s3::{self, FinishWrite, MultipartUpload};
+use crate::toc::{self, RestoreProgress, TocTracker};
+
+/// Escape a field for use in a `.pgpass` line
+fn escape_passfile_field(field: &str) -> String {
+    field.replace('\\', "\\\\").replace(':', "\\:")
+}
+
+/// Build a `.pgpass` line matching `host`, `port` and `username` on any database
+pub fn passfile_entry(host: &str, port: u16, username: Option<&str>, password: &str) -> String {
+    format!(
+        "{}:{}:*:{}:{}\n",
+        escape_passfile_field(host),
+        port,
+        username.map_or_else(|| "*".to_string(), escape_passfile_field),
+        escape_passfile_field(password),
+    )
+}
+
+/// `PGSSLMODE` to set for the client tools, `None` to keep the one in the environment. SSL being asked
+/// for upgrades a weaker mode to `require` but keeps `verify-ca`/`verify-full`; otherwise SSL is only
+/// disabled when nothing chose a mode.
+pub fn pgsslmode(ssl: bool, current: Option<&str>) -> Option<&'static str> {
+    match (ssl, current) {
+        (true, Some("require" | "verify-ca" | "verify-full")) => None,
+        (true, _) => Some("require"),
+        (false, None) => Some("disable"),
+        (false, Some(_)) => None,
+    }
+}
+
+/// Point a pg_dump/pg_restore/psql command at the server without exposing secrets on its
+/// command line or touching our own environment. The password goes into a private temporary
+/// passfile; the returned handle must be kept alive until the child process exits. Without a
+/// password, an inherited `PGPASSWORD` is passed through.
+pub fn apply_connection(
+    cmd: &mut Command,
+    host: &str,
+    port: u16,
+    username: Option<&str>,
+    password: Option<&str>,
+    ssl: bool,
+) -> Result<Option<NamedTempFile>> {
+    cmd.arg("--host").arg(host)
+        .arg("--port").arg(port.to_string())
+        .arg("--no-password");
+
+    if let Some(mode) = pgsslmode(ssl, std::env::var("PGSSLMODE").ok().as_deref()) {
+        cmd.env("PGSSLMODE", mode);
+    }
+
+    if let Some(user) = username {
+        cmd.arg("--username").arg(user);
+    }
+
+    let Some(pass) = password else {
+        return Ok(None);
+    };
+
+    // NamedTempFile is created with 0600 permissions, which libpq requires for passfiles
+    let mut passfile = NamedTempFile::new().context("Failed to create temporary passfile")?;
+    passfile.write_all(passfile_entry(host, port, username, pass).as_bytes())
+        .context("Failed to write temporary passfile")?;
+    passfile.flush()?;
+    // An inherited PGPASSWORD would take precedence over the passfile
+    cmd.env("PGPASSFILE", passfile.path()).env_remove("PGPASSWORD");
+
+    Ok(Some(passfile))
+}
+
+/// Output format of pg_dump
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum DumpFormat {
+    /// pg_dump's compressed custom archive, restored with pg_restore
+    #[default]
+    Custom,
+    /// One file per table in a directory, supports parallel jobs
+    Directory,
+    /// Tar archive, restored with pg_restore
+    Tar,
+    /// Plain SQL script, restored with psql
+    Plain,
+}
+
+impl DumpFormat {
+    /// Value for pg_dump's `--format` flag
+    pub fn as_arg(&self) -> &'static str {
+        match self {
+            DumpFormat::Custom => "custom",
+            DumpFormat::Directory => "directory",
+            DumpFormat::Tar => "tar",
+            DumpFormat::Plain => "plain",
+        }
+    }
+
+    /// Conventional file extension for dumps in this format
+    pub fn extension(&self) -> &'static str {
+        match self {
+            DumpFormat::Custom => ".dump",
+            DumpFormat::Directory => "",
+            DumpFormat::Tar => ".tar",
+            DumpFormat::Plain => ".sql",
+        }
+    }
+
+    /// Whether the dump is an archive that has to be restored with pg_restore rather than psql
+    pub fn is_archive(&self) -> bool {
+        !matches!(self, DumpFormat::Plain)
+    }
+
+    /// Whether a restore of this format can drop the objects already in the database first. psql runs a plain
+    /// SQL script as it is, so only a script dumped with `pg_dump --clean` drops them itself.
+    pub fn can_clean(&self) -> bool {
+        self.is_archive()
+    }
+
+    /// Whether a dump in this format can be restored from a stream at all, without seeking in a local file.
+    /// pg_restore reads the members of a tar archive in table of contents order, which a stream can't go back for.
+    pub fn is_streamable(&self) -> bool {
+        matches!(self, DumpFormat::Custom | DumpFormat::Plain)
+    }
+
+    /// Whether the dump starting with `head` should be restored from a stream rather than downloaded first.
+    /// Custom archives only are if their table of contents, which `head` has to hold all of, records where
+    /// each entry's data is.
+    pub fn streamable_dump(head: &[u8]) -> bool {
+        match DumpFormat::from_header(head) {
+            DumpFormat::Custom => toc::data_offsets_known(head) == Some(true),
+            format => format.is_streamable(),
+        }
+    }
+
+    /// Detect the format of an existing dump from its first bytes
+    pub fn detect(path: &Path) -> Result<DumpFormat> {
+        if path.is_dir() {
+            return Ok(DumpFormat::Directory);
+        }
+
+        let mut header = Vec::with_capacity(512);
+        std::fs::File::open(path)
+            .with_context(|| format!("Failed to open dump file {}", path.display()))?
+            .take(512)
+            .read_to_end(&mut header)?;
+
+        Ok(DumpFormat::from_header(&header))
+    }
+
+    /// Detect the format of a dump from its leading bytes
+    pub fn from_header(header: &[u8]) -> DumpFormat {
+        if header.starts_with(b"PGDMP") {
+            DumpFormat::Custom
+        } else if header.len() >= 262 && &header[257..262] == b"ustar" {
+            DumpFormat::Tar
+        } else {
+            DumpFormat::Plain
+        }
+    }
+}
+
+/// Options controlling what pg_dump writes and how
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct DumpOptions {
+    pub format: DumpFormat,
+    /// Compression level 0-9, where supported by the format
+    pub compress: Option<u32>,
+    /// Number of parallel jobs, only valid for the directory format
+    pub jobs: Option<u32>,
+    pub schemas: Vec<String>,
+    pub exclude_schemas: Vec<String>,
+    pub tables: Vec<String>,
+    pub exclude_tables: Vec<String>,
+    pub no_owner: bool,
+    pub no_acl: bool,
+}
+
+impl DumpOptions {
+    /// Check the options for combinations pg_dump would reject
+    pub fn validate(&self) -> Result<()> {
+        if let Some(level) = self.compress {
+            if level > 9 {
+                anyhow::bail!("Compression level must be between 0 and 9, got {}", level);
+            }
+        }
+
+        if let Some(jobs) = self.jobs {
+            if self.format != DumpFormat::Directory {
+                anyhow::bail!("Parallel jobs are only supported with the directory format");
+            }
+            if jobs == 0 {
+                anyhow::bail!("Number of jobs must be at least 1");
+            }
+        }
+
+        Ok(())
+    }
+
+    /// pg_dump arguments for these options
+    pub fn args(&self) -> Vec<String> {
+        let mut args = vec!["--format".to_string(), self.format.as_arg().to_string()];
+
+        if let Some(level) = self.compress {
+            args.push("--compress".to_string());
+            args.push(level.to_string());
+        }
+
+        if let Some(jobs) = self.jobs {
+            args.push("--jobs".to_string());
+            args.push(jobs.to_string());
+        }
+
+        for (flag, values) in [
+            ("--schema", &self.schemas),
+            ("--exclude-schema", &self.exclude_schemas),
+            ("--table", &self.tables),
+            ("--exclude-table", &self.exclude_tables),
+        ] {
+            for value in values {
+                args.push(flag.to_string());
+                args.push(value.clone());
+            }
+        }
+
+        if self.no_owner {
+            args.push("--no-owner".to_string());
+        }
+
+        if self.no_acl {
+            args.push("--no-acl".to_string());
+        }
+
+        args
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
 pub async fn dump_database(
     name: &str,
     output: &str,
+    options: &DumpOptions,
     host: &str,
     port: u16,
     username: Option<&str>,
     password: Option<&str>,
     ssl: bool,
 ) -> Result<()> {
-
-    // Add PGSSLMODE environment variable if SSL is enabled
-    if ssl {
-        std::env::set_var("PGSSLMODE", "require");
-    }
+    options.validate()?;
 
     debug!("Building pg_dump command");
     let mut cmd = Command::new("pg_dump");
     cmd.arg("--dbname").arg(name)
         .arg("--file").arg(output)
-        .arg("--host").arg(host)
-        .arg("--port").arg(port.to_string());
-
-    if let Some(user) = username {
-        cmd.arg("--username").arg(user);
-    }
+        .args(options.args());
+    let _passfile = apply_connection(&mut cmd, host, port, username, password, ssl)?;
 
-    if let Some(pass) = password {
-        cmd.arg("--password").arg(pass);
-    }
-
-    debug!("Executing pg_dump command");
+    debug!("Executing pg_dump command: {:?}", cmd);
     let output = cmd
         .output()
         .context("Failed to execute pg_dump")?;
@@ -46,6 +281,10 @@ pub async fn dump_database(
     Ok(())
 }
 
+/// Restore `input` into database `name`, using pg_restore for archives and psql for plain SQL dumps.
+/// With `clean`, pg_restore drops objects already in the database before recreating them. Plain SQL dumps
+/// are refused with `clean` before psql runs, since psql can't drop anything the script doesn't.
+#[allow(clippy::too_many_arguments)]
 pub fn restore_database(
     name: &str,
     input: &str,
@@ -54,49 +293,538 @@ pub fn restore_database(
     username: Option<&str>,
     password: Option<&str>,
     ssl: bool,
+    clean: bool,
+) -> Result<()> {
+    restore_database_with_progress(name, input, host, port, username, password, ssl, clean, &CancelToken::new(), |_| {})
+}
+
+/// Like `restore_database`, calling `on_progress` as pg_restore works through the archive's table of contents.
+/// Plain SQL dumps have no table of contents, so they only report completion.
+/// Cancelling `cancel` kills the restore process and returns a `Cancelled` error.
+#[allow(clippy::too_many_arguments)]
+pub fn restore_database_with_progress<F: FnMut(&RestoreProgress)>(
+    name: &str,
+    input: &str,
+    host: &str,
+    port: u16,
+    username: Option<&str>,
+    password: Option<&str>,
+    ssl: bool,
+    clean: bool,
+    cancel: &CancelToken,
+    on_progress: F,
+) -> Result<()> {
+    let format = DumpFormat::detect(Path::new(input))?;
+    debug!("Detected {:?} dump format for {}", format, input);
+
+    let tracker = if format.is_archive() {
+        TocTracker::new(toc::read_toc(input)?)
+    } else {
+        TocTracker::new(Vec::new())
+    };
+
+    run_restore(name, format, RestoreSource::File(input), tracker, host, port, username, password, ssl, clean, cancel, on_progress)
+}
+
+/// Number of tables the dump at `input` creates, read from its table of contents. `None` for plain SQL
+/// dumps, which have no table of contents to count.
+pub fn expected_tables(input: &str) -> Result<Option<usize>> {
+    if !DumpFormat::detect(Path::new(input))?.is_archive() {
+        return Ok(None);
+    }
+    Ok(Some(toc::table_count(&toc::read_toc(input)?)))
+}
+
+/// Restore a database from a dump read from `reader`, piping it into pg_restore or psql instead of
+/// reading it from a file, so the dump never has to fit on local disk. The format is detected from the
+/// first bytes. The table of contents can't be read ahead of time, so `on_progress` only reports the
+/// object being worked on; callers should track progress by the bytes they hand out from `reader`.
+#[allow(clippy::too_many_arguments)]
+pub fn restore_stream_with_progress<R: Read + Send + 'static, F: FnMut(&RestoreProgress)>(
+    name: &str,
+    mut reader: R,
+    host: &str,
+    port: u16,
+    username: Option<&str>,
+    password: Option<&str>,
+    ssl: bool,
+    clean: bool,
+    cancel: &CancelToken,
+    on_progress: F,
 ) -> Result<()> {
-    // Add PGSSLMODE environment variable if SSL is enabled
-    if ssl {
-        // Set PGSSLMODE to require
-        log::info!("Setting PGSSLMODE to require");
-        std::env::set_var("PGSSLMODE", "require");
-    }else {
-        // Set PGSSLMODE to disable
-        log::info!("Setting PGSSLMODE to disable");
-        std::env::set_var("PGSSLMODE", "disable");
+    let mut header = Vec::with_capacity(512);
+    (&mut reader).take(512).read_to_end(&mut header).context("Failed to read dump header")?;
+
+    let format = DumpFormat::from_header(&header);
+    if !format.is_streamable() {
+        anyhow::bail!("{:?} dumps can't be restored from a stream", format);
     }
+    debug!("Detected {:?} dump format for streamed restore", format);
 
-    debug!("Building pg_restore command");
+    let source = RestoreSource::Stdin(Box::new(Read::chain(std::io::Cursor::new(header), reader)));
+    run_restore(name, format, source, TocTracker::new(Vec::new()), host, port, username, password, ssl, clean, cancel, on_progress)
+}
 
-    let mut cmd = Command::new("pg_restore");
-    cmd.arg("--host").arg(host)
-        .arg("--port").arg(port.to_string())
-        .arg("-C").arg("-c").arg("--if-exists")
-        .arg("--dbname").arg(name)
-        .arg(input);
+/// Where pg_restore or psql reads the dump from
+enum RestoreSource<'a> {
+    File(&'a str),
+    Stdin(Box<dyn Read + Send>),
+}
 
-    if let Some(user) = username {
-        cmd.arg("--username").arg(user);
+#[allow(clippy::too_many_arguments)]
+fn run_restore<F: FnMut(&RestoreProgress)>(
+    name: &str,
+    format: DumpFormat,
+    source: RestoreSource,
+    mut tracker: TocTracker,
+    host: &str,
+    port: u16,
+    username: Option<&str>,
+    password: Option<&str>,
+    ssl: bool,
+    clean: bool,
+    cancel: &CancelToken,
+    mut on_progress: F,
+) -> Result<()> {
+    if clean && !format.can_clean() {
+        anyhow::bail!(
+            "Plain SQL dumps can't be restored over an existing database, because psql can't drop the objects already \
+             there. Restore into a new database or swap it in instead, or restore a custom format dump"
+        );
     }
 
-    if let Some(pass) = password {
-        std::env::set_var("PGPASSWORD", pass);
+    let program = if format.is_archive() { "pg_restore" } else { "psql" };
+    debug!("Building {} command", program);
+
+    let mut cmd = Command::new(program);
+    cmd.arg("--dbname").arg(name);
+    if format.is_archive() {
+        cmd.arg("--verbose");
+        if clean {
+            cmd.arg("--clean").arg("--if-exists");
+        }
+        // Without a file argument pg_restore reads the archive from stdin
+        if let RestoreSource::File(input) = &source {
+            cmd.arg(input);
+        }
+    } else {
+        if let RestoreSource::File(input) = &source {
+            cmd.arg("--file").arg(input);
+        }
+        cmd.arg("--set").arg("ON_ERROR_STOP=1")
+            .arg("--quiet");
     }
+    cmd.stdin(if matches!(source, RestoreSource::Stdin(_)) { Stdio::piped() } else { Stdio::null() })
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped());
+    let _passfile = apply_connection(&mut cmd, host, port, username, password, ssl)?;
 
-    // Create a debug-friendly representation of the command
-    let cmd_str = format!("pg_restore --host {} --port {} -C -c --if-exists --dbname {} {} {}",
-        host, port, name, username.map_or(String::new(), |u| format!(" --username {}", u)), input,
-    );
-    debug!("Executing pg_restore command: {} to database {}", cmd_str, name);
-    let output = cmd
-        .output()
-        .context("Failed to execute pg_restore")?;
+    debug!("Executing {} command: {:?} to database {}", program, cmd, name);
+    let mut child = cmd
+        .spawn()
+        .with_context(|| format!("Failed to execute {}", program))?;
 
-    if !output.status.success() {
-        let error_msg = String::from_utf8_lossy(&output.stderr);
-        error!("pg_restore failed: {}", error_msg);
-        anyhow::bail!("pg_restore failed: {}", error_msg);
+    let stderr = child.stderr.take().context("Failed to capture restore output")?;
+
+    // Feed the dump on its own thread while this one reads stderr
+    let feeder = match source {
+        RestoreSource::Stdin(mut reader) => {
+            let mut stdin = child.stdin.take().context("Failed to open restore input")?;
+            Some(std::thread::spawn(move || std::io::copy(&mut reader, &mut stdin)))
+        }
+        RestoreSource::File(_) => None,
+    };
+
+    let child = Arc::new(Mutex::new(child));
+
+    // Reading stderr blocks this thread, so watch for cancellation on another one
+    let watcher = {
+        let child = Arc::clone(&child);
+        let cancel = cancel.clone();
+        std::thread::spawn(move || loop {
+            {
+                let mut child = child.lock().unwrap();
+                if !matches!(child.try_wait(), Ok(None)) {
+                    break;
+                }
+                if cancel.is_cancelled() {
+                    info!("Restore cancelled, killing {}", program);
+                    let _ = child.kill();
+                    break;
+                }
+            }
+            std::thread::sleep(std::time::Duration::from_millis(100));
+        })
+    };
+
+    let mut error_lines = Vec::new();
+    for line in BufReader::new(stderr).lines() {
+        let line = line?;
+        if tracker.observe(&line) {
+            on_progress(tracker.progress());
+        } else {
+            error_lines.push(line);
+        }
+    }
+
+    let _ = watcher.join();
+    let status = child.lock().unwrap().wait().with_context(|| format!("Failed to wait for {}", program))?;
+    if cancel.is_cancelled() {
+        return Err(Cancelled.into());
+    }
+
+    // A failed read of the dump is the root cause of whatever the restore reported. A broken pipe
+    // only means the restore exited early, and its own errors explain why.
+    if let Some(feeder) = feeder {
+        match feeder.join() {
+            Ok(Err(e)) if e.kind() != std::io::ErrorKind::BrokenPipe => {
+                error!("Failed to read dump for {}: {}", program, e);
+                anyhow::bail!("Failed to read dump: {}", e);
+            }
+            Err(_) => anyhow::bail!("Restore input thread panicked"),
+            _ => {}
+        }
+    }
+
+    if !status.success() {
+        let error_msg = error_lines.join("\n");
+        error!("{} failed: {}", program, error_msg);
+        anyhow::bail!("{} failed: {}", program, error_msg);
     }
 
+    let total = tracker.progress().total;
+    on_progress(&RestoreProgress { completed: total, total, current: None });
+
     Ok(())
 }
+
+/// Where a restore writes to. Defaults to `New` so that overwriting a database is always an explicit choice.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum RestoreMode {
+    /// Restore over the target database, dropping objects that already exist. Archives only, not plain SQL dumps
+    Existing,
+    /// Create the target database and restore into it
+    #[default]
+    New,
+    /// Restore into a scratch database, then swap it in for the target
+    Swap,
+}
+
+impl RestoreMode {
+    /// Cycle to the next mode
+    pub fn next(&self) -> RestoreMode {
+        match self {
+            RestoreMode::New => RestoreMode::Swap,
+            RestoreMode::Swap => RestoreMode::Existing,
+            RestoreMode::Existing => RestoreMode::New,
+        }
+    }
+
+    /// Work out which databases a restore into `target` touches
+    pub fn plan(&self, target: &str) -> RestorePlan {
+        let database = match self {
+            RestoreMode::Swap => format!("{}-restore-{}", target, random_word::get(random_word::Lang::En)),
+            RestoreMode::Existing | RestoreMode::New => target.to_string(),
+        };
+
+        RestorePlan { mode: *self, target: target.to_string(), database }
+    }
+}
+
+impl std::fmt::Display for RestoreMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RestoreMode::Existing => write!(f, "Existing database"),
+            RestoreMode::New => write!(f, "New database"),
+            RestoreMode::Swap => write!(f, "Scratch database, swapped in"),
+        }
+    }
+}
+
+/// The databases involved in restoring into a target
+#[derive(Clone, Debug, PartialEq)]
+pub struct RestorePlan {
+    pub mode: RestoreMode,
+    /// Database that holds the restored data once the restore is finished
+    pub target: String,
+    /// Database pg_restore or psql writes into
+    pub database: String,
+}
+
+impl RestorePlan {
+    /// Name the current target database is kept under after a swap
+    pub fn previous(&self) -> String {
+        format!("{}-previous", self.target)
+    }
+
+    /// Name an older `<target>-previous` is moved to during a swap, until the swap has committed and it can be dropped
+    pub fn retired(&self) -> String {
+        format!("{}-retired", self.target)
+    }
+
+    /// Whether existing objects have to be dropped before restoring
+    pub fn clean(&self) -> bool {
+        self.mode == RestoreMode::Existing
+    }
+
+    /// Create the database the restore writes into, if the mode needs one
+    pub async fn prepare(&self, client: &tokio_postgres::Client) -> Result<()> {
+        match self.mode {
+            RestoreMode::Existing => {
+                if !postgres::database_exists(client, &self.database).await? {
+                    anyhow::bail!("Database '{}' does not exist", self.database);
+                }
+            }
+            RestoreMode::New => {
+                if postgres::database_exists(client, &self.database).await? {
+                    anyhow::bail!("Database '{}' already exists", self.database);
+                }
+                postgres::create_database(client, &self.database).await?;
+            }
+            RestoreMode::Swap => {
+                postgres::validate_identifier(&self.previous())?;
+                postgres::validate_identifier(&self.retired())?;
+                postgres::create_database(client, &self.database).await?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Sanity-check the restored database before it is put in place. `restored` must be connected to
+    /// `self.database`. `expected_tables` is the number of tables in the dump's table of contents, if it has one;
+    /// the restored database must have at least that many. Returns the number of tables found.
+    pub async fn validate(&self, restored: &tokio_postgres::Client, expected_tables: Option<usize>) -> Result<i64> {
+        let tables = postgres::count_user_tables(restored).await?;
+        if let Some(expected) = expected_tables {
+            if (tables as usize) < expected {
+                anyhow::bail!("Restored database '{}' contains {} tables, the dump has {}", self.database, tables, expected);
+            }
+        }
+
+        info!("Restored database '{}' contains {} tables", self.database, tables);
+        Ok(tables)
+    }
+
+    /// Swap the restored scratch database in for the target, if the mode needs it.
+    /// Only one previous generation is kept: an older `<target>-previous` is renamed aside in the swap
+    /// transaction and only dropped once the swap has committed.
+    pub async fn finish(&self, client: &tokio_postgres::Client) -> Result<()> {
+        if self.mode != RestoreMode::Swap {
+            return Ok(());
+        }
+
+        if !postgres::database_exists(client, &self.target).await? {
+            return postgres::rename_database(client, &self.database, &self.target).await;
+        }
+
+        let previous = self.previous();
+        let retired = if postgres::database_exists(client, &previous).await? {
+            let retired = self.retired();
+            // Left behind by an earlier swap that committed but couldn't drop it
+            if postgres::database_exists(client, &retired).await? {
+                postgres::drop_database_with_force(client, &retired).await?;
+            }
+            Some(retired)
+        } else {
+            None
+        };
+
+        postgres::swap_databases(client, &self.target, &self.database, &previous, retired.as_deref()).await?;
+
+        if let Some(retired) = retired {
+            info!("Dropping old previous database, retired as '{}'", retired);
+            if let Err(e) = postgres::drop_database_with_force(client, &retired).await {
+                error!("Failed to drop '{}' after the swap, it can be dropped by hand: {}", retired, e);
+            }
+        }
+        Ok(())
+    }
+
+    /// Undo a failed restore by dropping the database it created. The target database is never touched.
+    pub async fn rollback(&self, client: &tokio_postgres::Client) {
+        if self.mode == RestoreMode::Existing {
+            return;
+        }
+
+        match postgres::database_exists(client, &self.database).await {
+            Ok(true) => {
+                info!("Rolling back restore, dropping '{}'", self.database);
+                if let Err(e) = postgres::drop_database_with_force(client, &self.database).await {
+                    error!("Failed to drop '{}' during rollback: {}", self.database, e);
+                }
+            }
+            Ok(false) => {}
+            Err(e) => error!("Failed to check for '{}' during rollback: {}", self.database, e),
+        }
+    }
+}
+
+/// How a backup is stored in S3, on top of what pg_dump writes
+#[derive(Clone, Debug, Default)]
+pub struct UploadOptions {
+    pub compression: Compression,
+    pub encryption: Option<EncryptionRecipient>,
+}
+
+impl UploadOptions {
+    /// Suffix for default object keys, e.g. `.zst.age`
+    pub fn suffix(&self) -> String {
+        let encrypted = if self.encryption.is_some() { crypto::ENCRYPTED_SUFFIX } else { "" };
+        format!("{}{}", self.compression.suffix(), encrypted)
+    }
+
+    /// Whether the dump is changed on its way to S3
+    pub fn transforms(&self) -> bool {
+        self.compression != Compression::None || self.encryption.is_some()
+    }
+
+    /// Metadata to store with the object
+    pub fn metadata(&self) -> Vec<(&'static str, &'static str)> {
+        let encryption = self.encryption.as_ref().map(|_| crypto::ENCRYPTION_METADATA);
+        encryption.into_iter().chain(self.compression.metadata()).collect()
+    }
+
+    /// Compress, then encrypt, everything written to `output`
+    pub fn wrap(&self, output: Box<dyn FinishWrite>) -> Result<Box<dyn FinishWrite>> {
+        let output = match &self.encryption {
+            Some(encryption) => encryption.encrypt(output)?,
+            None => output,
+        };
+        self.compression.compress(output)
+    }
+}
+
+/// First line of `<program> --version`, e.g. `pg_dump (PostgreSQL) 16.2`
+async fn tool_version(program: &str) -> Option<String> {
+    let output = tokio::process::Command::new(program).arg("--version").output().await.ok()?;
+    let version = String::from_utf8_lossy(&output.stdout);
+    output.status.success().then(|| version.lines().next().unwrap_or_default().trim().to_string())
+}
+
+/// `server_version` of the server holding database `name`
+async fn server_version(
+    name: &str,
+    host: &str,
+    port: u16,
+    username: Option<&str>,
+    password: Option<&str>,
+    ssl: bool,
+) -> Result<String> {
+    let mut cmd = tokio::process::Command::new("psql");
+    cmd.arg("--dbname").arg(name)
+        .args(["--no-psqlrc", "--tuples-only", "--no-align", "--command", "SHOW server_version"])
+        .stdin(Stdio::null());
+    let _passfile = apply_connection(cmd.as_std_mut(), host, port, username, password, ssl)?;
+
+    let output = cmd.output().await.context("Failed to execute psql")?;
+    if !output.status.success() {
+        anyhow::bail!("psql failed: {}", String::from_utf8_lossy(&output.stderr).trim());
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+/// Run pg_dump and stream its output into `bucket`/`key` with a multipart upload, compressing and
+/// encrypting it as `upload_options` says, then write the snapshot's manifest next to it.
+/// Returns the manifest.
+#[allow(clippy::too_many_arguments)]
+pub async fn backup_to_s3(
+    client: &S3Client,
+    bucket: &str,
+    key: &str,
+    name: &str,
+    options: &DumpOptions,
+    upload_options: &UploadOptions,
+    host: &str,
+    port: u16,
+    username: Option<&str>,
+    password: Option<&str>,
+    ssl: bool,
+) -> Result<SnapshotManifest> {
+    options.validate()?;
+    if options.format == DumpFormat::Directory {
+        anyhow::bail!("The directory format cannot be streamed to S3, use custom, tar or plain");
+    }
+
+    let started_at = chrono::Utc::now();
+    let timer = std::time::Instant::now();
+    let server_version = match server_version(name, host, port, username, password, ssl).await {
+        Ok(version) => Some(version),
+        Err(e) => {
+            warn!("Could not read the server version of '{}': {}", name, e);
+            None
+        }
+    };
+    let pg_dump_version = tool_version("pg_dump").await;
+
+    debug!("Building pg_dump command for S3 backup");
+    let mut cmd = tokio::process::Command::new("pg_dump");
+    cmd.arg("--dbname").arg(name)
+        .args(options.args())
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true);
+    let _passfile = apply_connection(cmd.as_std_mut(), host, port, username, password, ssl)?;
+
+    debug!("Executing pg_dump command");
+    let mut child = cmd.spawn().context("Failed to execute pg_dump")?;
+    let mut stdout = child.stdout.take().context("Failed to capture pg_dump output")?;
+    let mut stderr = child.stderr.take().context("Failed to capture pg_dump errors")?;
+
+    // Drain stderr concurrently so pg_dump never blocks on a full pipe
+    let stderr_task = tokio::spawn(async move {
+        let mut buf = String::new();
+        let _ = stderr.read_to_string(&mut buf).await;
+        buf
+    });
+
+    let mut upload = MultipartUpload::start_with_metadata(client, bucket, key, &upload_options.metadata()).await?;
+    let result = if upload_options.transforms() {
+        if let Some(encryption) = &upload_options.encryption {
+            debug!("Encrypting backup to {}", encryption);
+        }
+        upload.write_through(&mut stdout, |output| upload_options.wrap(output)).await
+    } else {
+        upload.write_from(&mut stdout).await
+    };
+    let uploaded = match result {
+        Ok(bytes) => bytes,
+        Err(e) => {
+            upload.abort().await;
+            return Err(e);
+        }
+    };
+
+    let status = child.wait().await.context("Failed to wait for pg_dump")?;
+    let error_msg = stderr_task.await.unwrap_or_default();
+    if !status.success() {
+        upload.abort().await;
+        error!("pg_dump failed: {}", error_msg);
+        anyhow::bail!("pg_dump failed: {}", error_msg);
+    }
+
+    let sha256 = upload.sha256();
+    upload.complete().await?;
+    info!("Uploaded {} bytes from database '{}' to s3://{}/{}", uploaded, name, bucket, key);
+
+    let manifest = SnapshotManifest {
+        host: host.to_string(),
+        port,
+        database: name.to_string(),
+        server_version,
+        pg_dump_version,
+        format: options.format.as_arg().to_string(),
+        compression: upload_options.compression.to_string(),
+        encrypted: upload_options.encryption.is_some(),
+        sha256,
+        size: uploaded,
+        started_at: started_at.to_rfc3339(),
+        duration_secs: timer.elapsed().as_secs_f64(),
+    };
+    s3::write_manifest(client, bucket, key, &manifest)
+        .await
+        .context("Backup uploaded, but its manifest could not be written")?;
+
+    Ok(manifest)
+}
@@ -0,0 +1,215 @@
+use anyhow::{anyhow, Context, Result};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use md5::{Digest, Md5};
+use sha2::Sha256;
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+/// Suffix of the `<key>.sha256` sidecar objects holding a snapshot's SHA-256
+pub const SIDECAR_SUFFIX: &str = ".sha256";
+
+/// Checksums S3 reported for an object, used to verify a downloaded copy
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ObjectChecksums {
+    /// ETag as returned by S3, including the quotes
+    pub etag: Option<String>,
+    /// Base64 `x-amz-checksum-sha256`, with a `-N` suffix for multipart uploads
+    pub sha256: Option<String>,
+    /// Base64 `x-amz-checksum-crc32c`, with a `-N` suffix for multipart uploads
+    pub crc32c: Option<String>,
+    /// Hex SHA-256 from a `<key>.sha256` sidecar object, or from the snapshot's manifest
+    pub sidecar_sha256: Option<String>,
+    /// Size of every part but the last, for objects uploaded in several parts
+    pub part_size: Option<u64>,
+}
+
+impl ObjectChecksums {
+    /// Whether any of the checksums is multipart, so `part_size` is needed to verify it
+    pub fn needs_part_size(&self) -> bool {
+        let multipart = |value: &Option<String>| value.as_deref().and_then(|v| split_parts(v).1).is_some_and(|n| n > 1);
+        multipart(&self.etag) || multipart(&self.sha256) || multipart(&self.crc32c)
+    }
+}
+
+/// Split a `value-N` multipart checksum into its value and part count
+fn split_parts(value: &str) -> (&str, Option<usize>) {
+    let value = value.trim_matches('"');
+    match value.rsplit_once('-') {
+        Some((value, parts)) if parts.parse::<usize>().is_ok() => (value, parts.parse().ok()),
+        _ => (value, None),
+    }
+}
+
+/// MD5 digest and part count of an ETag. ETags of SSE-KMS encrypted objects aren't MD5 digests, so
+/// anything that doesn't look like one yields `None` and is not checked.
+pub fn parse_etag(etag: &str) -> Option<(Vec<u8>, Option<usize>)> {
+    let (digest, parts) = split_parts(etag);
+    let digest = hex::decode(digest).ok().filter(|d| d.len() == 16)?;
+    Some((digest, parts))
+}
+
+/// Decode a base64 S3 additional checksum and its part count
+fn parse_checksum(value: &str) -> Result<(Vec<u8>, Option<usize>)> {
+    let (digest, parts) = split_parts(value);
+    let digest = BASE64.decode(digest).with_context(|| format!("Invalid checksum {}", value))?;
+    Ok((digest, parts))
+}
+
+/// Running digests of one part, or of the whole file
+#[derive(Default)]
+struct PartDigests {
+    md5: Md5,
+    sha256: Sha256,
+    crc32c: u32,
+}
+
+impl PartDigests {
+    fn update(&mut self, data: &[u8]) {
+        self.md5.update(data);
+        self.sha256.update(data);
+        self.crc32c = crc32c::crc32c_append(self.crc32c, data);
+    }
+}
+
+/// Digests of a snapshot computed as it is read, whole and split into parts the way S3 splits multipart uploads
+pub struct StreamDigests {
+    part_size: u64,
+    whole: PartDigests,
+    part: PartDigests,
+    part_len: u64,
+    md5_parts: Vec<Vec<u8>>,
+    sha256_parts: Vec<Vec<u8>>,
+    crc32c_parts: Vec<u32>,
+}
+
+impl StreamDigests {
+    /// `part_size` is the size of every part but the last, from `ObjectChecksums::part_size`
+    pub fn new(part_size: Option<u64>) -> Self {
+        Self {
+            part_size: part_size.filter(|s| *s > 0).unwrap_or(u64::MAX),
+            whole: PartDigests::default(),
+            part: PartDigests::default(),
+            part_len: 0,
+            md5_parts: Vec::new(),
+            sha256_parts: Vec::new(),
+            crc32c_parts: Vec::new(),
+        }
+    }
+
+    pub fn update(&mut self, mut data: &[u8]) {
+        while !data.is_empty() {
+            let take = data.len().min((self.part_size - self.part_len) as usize);
+            self.part.update(&data[..take]);
+            self.whole.update(&data[..take]);
+            self.part_len += take as u64;
+            data = &data[take..];
+
+            if self.part_len == self.part_size {
+                self.finish_part();
+            }
+        }
+    }
+
+    fn finish_part(&mut self) {
+        let done = std::mem::take(&mut self.part);
+        self.md5_parts.push(done.md5.finalize().to_vec());
+        self.sha256_parts.push(done.sha256.finalize().to_vec());
+        self.crc32c_parts.push(done.crc32c);
+        self.part_len = 0;
+    }
+
+    /// Check everything read so far against the checksums S3 reported for the object.
+    /// Returns the names of the checksums that were verified, which is empty if there was nothing to check.
+    pub fn verify(mut self, expected: &ObjectChecksums) -> Result<Vec<&'static str>> {
+        if expected.needs_part_size() && expected.part_size.is_none() {
+            return Err(anyhow!("Part size of the multipart object is unknown"));
+        }
+        if self.part_len > 0 || self.md5_parts.is_empty() {
+            self.finish_part();
+        }
+
+        let whole_md5 = self.whole.md5.finalize().to_vec();
+        let whole_sha256 = self.whole.sha256.finalize().to_vec();
+        let parts = self.md5_parts.len();
+        let mut verified = Vec::new();
+
+        if let Some((etag, etag_parts)) = expected.etag.as_deref().and_then(parse_etag) {
+            let actual = match etag_parts {
+                // A multipart ETag is the MD5 of the concatenated part MD5s
+                Some(_) => Md5::digest(self.md5_parts.concat()).to_vec(),
+                None => whole_md5,
+            };
+            compare("ETag", &etag, &actual, etag_parts, parts)?;
+            verified.push("ETag");
+        }
+
+        if let Some(value) = &expected.sha256 {
+            let (sha256, sha256_parts) = parse_checksum(value)?;
+            let actual = match sha256_parts {
+                Some(_) => Sha256::digest(self.sha256_parts.concat()).to_vec(),
+                None => whole_sha256.clone(),
+            };
+            compare("SHA-256", &sha256, &actual, sha256_parts, parts)?;
+            verified.push("SHA-256");
+        }
+
+        if let Some(value) = &expected.crc32c {
+            let (crc32c, crc32c_parts) = parse_checksum(value)?;
+            let actual = match crc32c_parts {
+                Some(_) => {
+                    let joined: Vec<u8> = self.crc32c_parts.iter().flat_map(|c| c.to_be_bytes()).collect();
+                    crc32c::crc32c(&joined).to_be_bytes().to_vec()
+                }
+                None => self.whole.crc32c.to_be_bytes().to_vec(),
+            };
+            compare("CRC32C", &crc32c, &actual, crc32c_parts, parts)?;
+            verified.push("CRC32C");
+        }
+
+        if let Some(value) = &expected.sidecar_sha256 {
+            // Sidecars are written by sha256sum, so only the first field is the digest
+            let hex_digest = value.split_whitespace().next().unwrap_or_default();
+            let sha256 = hex::decode(hex_digest).with_context(|| format!("Invalid sidecar checksum {}", hex_digest))?;
+            compare("Sidecar SHA-256", &sha256, &whole_sha256, None, parts)?;
+            verified.push("Sidecar SHA-256");
+        }
+
+        Ok(verified)
+    }
+}
+
+/// Compare a digest computed locally against the one S3 reported, naming the checksum in the error
+fn compare(name: &str, expected: &[u8], actual: &[u8], parts: Option<usize>, actual_parts: usize) -> Result<()> {
+    if let Some(parts) = parts {
+        if parts != actual_parts {
+            anyhow::bail!("{} mismatch: object has {} parts, downloaded data splits into {}", name, parts, actual_parts);
+        }
+    }
+    if expected != actual {
+        anyhow::bail!("{} mismatch: expected {}, got {}", name, hex::encode(expected), hex::encode(actual));
+    }
+    Ok(())
+}
+
+/// Check a downloaded file against the checksums S3 reported for the object.
+/// Returns the names of the checksums that were verified, which is empty if there was nothing to check.
+pub fn verify_file(path: &Path, expected: &ObjectChecksums) -> Result<Vec<&'static str>> {
+    if expected.needs_part_size() && expected.part_size.is_none() {
+        return Err(anyhow!("Part size of the multipart object is unknown"));
+    }
+
+    let mut file = File::open(path).with_context(|| format!("Failed to open {}", path.display()))?;
+    let mut digests = StreamDigests::new(expected.part_size);
+    let mut buffer = vec![0u8; 1024 * 1024];
+    loop {
+        let read = file.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        digests.update(&buffer[..read]);
+    }
+
+    digests.verify(expected)
+}
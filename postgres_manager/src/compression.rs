@@ -0,0 +1,136 @@
+use anyhow::Result;
+use std::io::Read;
+
+use crate::crypto::{self, EncryptionKey, ENCRYPTED_SUFFIX};
+use crate::s3::FinishWrite;
+
+/// Object metadata entry naming the compression of a snapshot, under any encryption
+pub const COMPRESSION_METADATA: &str = "compression";
+
+/// Compression applied to a whole snapshot object, on top of whatever pg_dump did
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum Compression {
+    #[default]
+    None,
+    Gzip,
+    Zstd,
+}
+
+impl std::fmt::Display for Compression {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Compression::None => write!(f, "none"),
+            Compression::Gzip => write!(f, "gzip"),
+            Compression::Zstd => write!(f, "zstd"),
+        }
+    }
+}
+
+impl Compression {
+    /// Key suffix for objects compressed this way
+    pub fn suffix(&self) -> &'static str {
+        match self {
+            Compression::None => "",
+            Compression::Gzip => ".gz",
+            Compression::Zstd => ".zst",
+        }
+    }
+
+    /// Metadata entry to store with objects compressed this way. Compressed snapshots get no `Content-Encoding`,
+    /// as HTTP clients would decompress them behind our back and ranged downloads would no longer line up.
+    pub fn metadata(&self) -> Option<(&'static str, &'static str)> {
+        match self {
+            Compression::None => None,
+            Compression::Gzip => Some((COMPRESSION_METADATA, "gzip")),
+            Compression::Zstd => Some((COMPRESSION_METADATA, "zstd")),
+        }
+    }
+
+    /// Detect compression from an object's key suffix, looking past an encryption suffix, or its
+    /// `compression` metadata
+    pub fn detect(key: &str, metadata: Option<&str>) -> Compression {
+        let key = key.strip_suffix(ENCRYPTED_SUFFIX).unwrap_or(key);
+        if key.ends_with(".gz") || key.ends_with(".gzip") {
+            return Compression::Gzip;
+        }
+        if key.ends_with(".zst") || key.ends_with(".zstd") {
+            return Compression::Zstd;
+        }
+
+        match metadata.map(|e| e.trim().to_ascii_lowercase()).as_deref() {
+            Some("gzip") => Compression::Gzip,
+            Some("zstd") => Compression::Zstd,
+            _ => Compression::None,
+        }
+    }
+
+    /// Detect compression from the leading bytes of the data
+    pub fn from_header(header: &[u8]) -> Compression {
+        if header.starts_with(&[0x1f, 0x8b]) {
+            Compression::Gzip
+        } else if header.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]) {
+            Compression::Zstd
+        } else {
+            Compression::None
+        }
+    }
+
+    /// Wrap `output` so everything written to it is compressed
+    pub fn compress(&self, output: Box<dyn FinishWrite>) -> Result<Box<dyn FinishWrite>> {
+        Ok(match self {
+            Compression::None => output,
+            Compression::Gzip => Box::new(flate2::write::GzEncoder::new(output, flate2::Compression::default())),
+            Compression::Zstd => Box::new(zstd::stream::write::Encoder::new(output, zstd::DEFAULT_COMPRESSION_LEVEL)?),
+        })
+    }
+
+    /// Wrap `input` so reading from it yields the decompressed data
+    pub fn decompress<R: Read + Send + 'static>(&self, input: R) -> Result<Box<dyn Read + Send>> {
+        Ok(match self {
+            Compression::None => Box::new(input),
+            Compression::Gzip => Box::new(flate2::read::MultiGzDecoder::new(input)),
+            Compression::Zstd => Box::new(zstd::stream::read::Decoder::new(input)?),
+        })
+    }
+}
+
+/// Decompress `input` with the compression `hint` names, or the one its leading bytes show if the hint is `None`
+pub fn decompress_if_compressed<R: Read + Send + 'static>(mut input: R, hint: Compression) -> Result<Box<dyn Read + Send>> {
+    let mut header = Vec::with_capacity(4);
+    (&mut input).take(4).read_to_end(&mut header)?;
+    let compression = match hint {
+        Compression::None => Compression::from_header(&header),
+        hint => hint,
+    };
+
+    compression.decompress(std::io::Cursor::new(header).chain(input))
+}
+
+/// Decrypt and decompress as much of a snapshot as its first bytes `head` hold, with `hint` as in
+/// `decompress_if_compressed`. Decoding stops at the first error, which the cut-off end of `head` causes.
+pub fn decode_head(head: Vec<u8>, key: Option<&EncryptionKey>, hint: Compression) -> Result<Vec<u8>> {
+    let reader = crypto::decrypt_if_encrypted(std::io::Cursor::new(head), key)?;
+    let mut reader = decompress_if_compressed(reader, hint)?;
+
+    let mut decoded = Vec::new();
+    let mut buf = [0u8; 64 * 1024];
+    loop {
+        match reader.read(&mut buf) {
+            Ok(0) | Err(_) => break,
+            Ok(n) => decoded.extend_from_slice(&buf[..n]),
+        }
+    }
+    Ok(decoded)
+}
+
+impl FinishWrite for flate2::write::GzEncoder<Box<dyn FinishWrite>> {
+    fn finish(self: Box<Self>) -> std::io::Result<()> {
+        flate2::write::GzEncoder::finish(*self)?.finish()
+    }
+}
+
+impl FinishWrite for zstd::stream::write::Encoder<'static, Box<dyn FinishWrite>> {
+    fn finish(self: Box<Self>) -> std::io::Result<()> {
+        zstd::stream::write::Encoder::finish(*self)?.finish()
+    }
+}
@@ -1,5 +1,11 @@
+use crate::crypto::EncryptionKey;
+use crate::history;
+use crate::retention::RetentionPolicy;
+use crate::s3::DownloadOptions;
 use crate::ui::models::{S3Config, PostgresConfig};
+use anyhow::Result;
 use std::env;
+use std::path::{Path, PathBuf};
 
 /// Load environment variables from .env file or from the file specified in DOTENV_PATH
 pub fn load_env() {
@@ -38,6 +44,64 @@ pub fn load_s3_config() -> S3Config {
     }
 }
 
+/// Load snapshot download tuning from environment variables
+pub fn load_download_options() -> DownloadOptions {
+    let defaults = DownloadOptions::default();
+    DownloadOptions {
+        chunk_size: env::var("S3_DOWNLOAD_CHUNK_MB")
+            .ok()
+            .and_then(|v| v.parse::<u64>().ok())
+            .filter(|mb| *mb > 0)
+            .map_or(defaults.chunk_size, |mb| mb * 1024 * 1024),
+        concurrency: env::var("S3_DOWNLOAD_CONCURRENCY")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .filter(|n| *n > 0)
+            .unwrap_or(defaults.concurrency),
+        max_retries: env::var("S3_DOWNLOAD_RETRIES")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(defaults.max_retries),
+    }
+}
+
+/// Whether snapshots are restored straight from S3 rather than downloaded first
+pub fn load_stream_restore() -> bool {
+    get_env_bool("RESTORE_STREAM", false)
+}
+
+/// Load the age key used to encrypt backups and decrypt snapshots, from `file` if given, else from the
+/// file named by `BACKUP_ENCRYPTION_KEY_FILE`, else from the identity in `BACKUP_ENCRYPTION_KEY`
+pub fn load_encryption_key(file: Option<&str>) -> Result<Option<EncryptionKey>> {
+    if let Some(file) = file.map(str::to_string).or_else(|| env::var("BACKUP_ENCRYPTION_KEY_FILE").ok()) {
+        return EncryptionKey::from_file(Path::new(&file)).map(Some);
+    }
+    match env::var("BACKUP_ENCRYPTION_KEY") {
+        Ok(key) => EncryptionKey::parse(&key).map(Some),
+        Err(_) => Ok(None),
+    }
+}
+
+/// Load the snapshot retention policy from environment variables
+pub fn load_retention_policy() -> RetentionPolicy {
+    let defaults = RetentionPolicy::default();
+    let count = |key: &str, default: usize| env::var(key).ok().and_then(|v| v.parse().ok()).unwrap_or(default);
+    RetentionPolicy {
+        hourly: count("RETENTION_HOURLY", defaults.hourly),
+        daily: count("RETENTION_DAILY", defaults.daily),
+        weekly: count("RETENTION_WEEKLY", defaults.weekly),
+        monthly: count("RETENTION_MONTHLY", defaults.monthly),
+    }
+}
+
+/// Path of the file finished jobs are recorded in, by default in the user's state directory
+pub fn load_history_path() -> PathBuf {
+    match env::var_os("JOBS_HISTORY_FILE").filter(|path| !path.is_empty()) {
+        Some(path) => PathBuf::from(path),
+        None => history::default_path(env::var_os("XDG_STATE_HOME").map(PathBuf::from), env::var_os("HOME").map(PathBuf::from)),
+    }
+}
+
 /// Load PostgreSQL configuration from environment variables
 pub fn load_postgres_config() -> PostgresConfig {
     PostgresConfig {
@@ -46,6 +110,8 @@ pub fn load_postgres_config() -> PostgresConfig {
         username: Some(get_env_with_default("PG_USERNAME", "postgres")),
         password: Some(get_env_with_default("PG_PASSWORD", "")),
         use_ssl: get_env_bool("PG_USE_SSL", false),
+        verify_ssl: get_env_bool("PG_VERIFY_SSL", false),
+        root_cert_path: env::var("PG_ROOT_CERT_PATH").ok().filter(|path| !path.is_empty()),
         db_name: Some(get_env_with_default("PG_DB_NAME", "postgres")),
     }
 }
@@ -0,0 +1,140 @@
+use anyhow::{anyhow, Context, Result};
+use std::io::Read;
+use std::path::Path;
+use std::str::FromStr;
+
+use crate::s3::FinishWrite;
+
+/// Key suffix of snapshots encrypted with age
+pub const ENCRYPTED_SUFFIX: &str = ".age";
+
+/// Object metadata entry marking a snapshot as encrypted, for keys that don't end in `ENCRYPTED_SUFFIX`
+pub const ENCRYPTION_METADATA: (&str, &str) = ("encryption", "age");
+
+/// Leading bytes of every age file
+const AGE_MAGIC: &[u8] = b"age-encryption.org/";
+
+/// Whether a snapshot key names an encrypted snapshot
+pub fn is_encrypted_key(key: &str) -> bool {
+    key.ends_with(ENCRYPTED_SUFFIX)
+}
+
+/// Whether data starting with `header` is age encrypted
+pub fn is_encrypted_header(header: &[u8]) -> bool {
+    header.starts_with(AGE_MAGIC)
+}
+
+/// An age X25519 public key backups are encrypted to. Only the matching identity can decrypt them.
+#[derive(Clone, Debug)]
+pub struct EncryptionRecipient {
+    recipient: age::x25519::Recipient,
+}
+
+impl EncryptionRecipient {
+    /// Parse an `age1...` public key, as printed by `age-keygen -y`
+    pub fn parse(recipient: &str) -> Result<Self> {
+        let recipient = age::x25519::Recipient::from_str(recipient.trim())
+            .map_err(|e| anyhow!("Invalid age recipient: {}", e))?;
+        Ok(Self { recipient })
+    }
+
+    /// Wrap `output` so everything written to it is encrypted
+    pub fn encrypt(&self, output: Box<dyn FinishWrite>) -> Result<Box<dyn FinishWrite>> {
+        let encryptor = age::Encryptor::with_recipients(std::iter::once(&self.recipient as &dyn age::Recipient))
+            .map_err(|e| anyhow!("Failed to set up encryption: {}", e))?;
+        Ok(Box::new(encryptor.wrap_output(output)?))
+    }
+}
+
+impl std::fmt::Display for EncryptionRecipient {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.recipient)
+    }
+}
+
+/// An age X25519 identity used to encrypt backups and decrypt snapshots
+#[derive(Clone)]
+pub struct EncryptionKey {
+    identity: age::x25519::Identity,
+}
+
+impl std::fmt::Debug for EncryptionKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("EncryptionKey")
+            .field("recipient", &self.recipient())
+            .finish()
+    }
+}
+
+impl EncryptionKey {
+    /// Parse an `AGE-SECRET-KEY-1...` identity, or the contents of an identity file as written by age-keygen
+    pub fn parse(contents: &str) -> Result<Self> {
+        let line = contents
+            .lines()
+            .map(str::trim)
+            .find(|l| !l.is_empty() && !l.starts_with('#'))
+            .ok_or_else(|| anyhow!("No age identity found"))?;
+
+        let identity = age::x25519::Identity::from_str(line)
+            .map_err(|e| anyhow!("Invalid age identity: {}", e))?;
+        Ok(Self { identity })
+    }
+
+    /// Read an identity file
+    pub fn from_file(path: &Path) -> Result<Self> {
+        let contents = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read encryption key file {}", path.display()))?;
+        Self::parse(&contents)
+    }
+
+    /// Public key backups are encrypted to
+    pub fn recipient(&self) -> String {
+        self.identity.to_public().to_string()
+    }
+
+    /// Public key of this identity, to encrypt to
+    pub fn to_recipient(&self) -> EncryptionRecipient {
+        EncryptionRecipient { recipient: self.identity.to_public() }
+    }
+
+    /// Wrap `output` so everything written to it is encrypted to this identity
+    pub fn encrypt(&self, output: Box<dyn FinishWrite>) -> Result<Box<dyn FinishWrite>> {
+        self.to_recipient().encrypt(output)
+    }
+
+    /// Wrap `input` so reading from it yields the plaintext. Reads the age header from `input`.
+    pub fn decrypt<R: Read + Send + 'static>(&self, input: R) -> Result<Box<dyn Read + Send>> {
+        let decryptor = age::Decryptor::new(input)
+            .map_err(|e| anyhow!("Failed to read encrypted snapshot: {}", e))?;
+        let reader = decryptor
+            .decrypt(std::iter::once(&self.identity as &dyn age::Identity))
+            .map_err(|e| anyhow!("Failed to decrypt snapshot, is the right key configured? {}", e))?;
+        Ok(Box::new(reader))
+    }
+}
+
+/// Decrypt `input` if it is age encrypted, otherwise pass it through unchanged
+pub fn decrypt_if_encrypted<R: Read + Send + 'static>(mut input: R, key: Option<&EncryptionKey>) -> Result<Box<dyn Read + Send>> {
+    let mut header = Vec::with_capacity(AGE_MAGIC.len());
+    (&mut input).take(AGE_MAGIC.len() as u64).read_to_end(&mut header)?;
+    let input = std::io::Cursor::new(header).chain(input);
+
+    if !is_encrypted_header(input.get_ref().0.get_ref()) {
+        return Ok(Box::new(input));
+    }
+    match key {
+        Some(key) => key.decrypt(input),
+        None => Err(missing_key()),
+    }
+}
+
+/// Error for an encrypted snapshot without a key to decrypt it
+pub fn missing_key() -> anyhow::Error {
+    anyhow!("Snapshot is encrypted, set BACKUP_ENCRYPTION_KEY or BACKUP_ENCRYPTION_KEY_FILE to decrypt it")
+}
+
+impl FinishWrite for age::stream::StreamWriter<Box<dyn FinishWrite>> {
+    fn finish(self: Box<Self>) -> std::io::Result<()> {
+        age::stream::StreamWriter::finish(*self)?.finish()
+    }
+}
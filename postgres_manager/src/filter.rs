@@ -0,0 +1,125 @@
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, NaiveDate, Utc};
+
+use crate::retention::snapshot_series;
+use crate::sort::SnapshotSort;
+use crate::ui::models::BackupMetadata;
+
+/// Filter for the snapshot list, parsed from a search such as `app since:2024-01-01 >100MB`
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SnapshotFilter {
+    /// Words that must each match the key fuzzily, lowercased
+    pub terms: Vec<String>,
+    /// Database name parsed from the key
+    pub database: Option<String>,
+    /// Only snapshots modified at or after this time
+    pub since: Option<DateTime<Utc>>,
+    /// Only snapshots modified before this time
+    pub until: Option<DateTime<Utc>>,
+    pub min_size: Option<u64>,
+    pub max_size: Option<u64>,
+}
+
+impl SnapshotFilter {
+    /// Parse a search. Words are matched fuzzily against the key; `db:<name>`, `since:<date>`,
+    /// `until:<date>` (inclusive) and `>`/`<` followed by a size like `100MB` filter on the database
+    /// name, the modification date and the size.
+    pub fn parse(input: &str) -> Result<Self> {
+        let mut filter = Self::default();
+
+        for token in input.split_whitespace() {
+            if let Some(name) = token.strip_prefix("db:") {
+                filter.database = Some(name.to_string());
+            } else if let Some(date) = token.strip_prefix("since:") {
+                filter.since = Some(parse_date(date)?);
+            } else if let Some(date) = token.strip_prefix("until:") {
+                filter.until = Some(parse_date_end(date)?);
+            } else if let Some(size) = token.strip_prefix('>') {
+                filter.min_size = Some(parse_size(size)?);
+            } else if let Some(size) = token.strip_prefix('<') {
+                filter.max_size = Some(parse_size(size)?);
+            } else {
+                filter.terms.push(token.to_lowercase());
+            }
+        }
+
+        Ok(filter)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+
+    pub fn matches(&self, snapshot: &BackupMetadata) -> bool {
+        let modified = snapshot.last_modified.secs();
+        let size = snapshot.size.max(0) as u64;
+
+        self.terms.iter().all(|term| fuzzy_match(term, &snapshot.key))
+            && self.database.as_deref().is_none_or(|db| database_name(&snapshot.key) == db)
+            && self.since.is_none_or(|since| modified >= since.timestamp())
+            && self.until.is_none_or(|until| modified < until.timestamp())
+            && self.min_size.is_none_or(|min| size >= min)
+            && self.max_size.is_none_or(|max| size <= max)
+    }
+}
+
+/// Whether the characters of `needle` appear in `haystack` in order, ignoring case
+pub fn fuzzy_match(needle: &str, haystack: &str) -> bool {
+    let mut haystack = haystack.chars().flat_map(char::to_lowercase);
+    needle.chars().flat_map(char::to_lowercase).all(|c| haystack.any(|h| h == c))
+}
+
+/// Name of the database a snapshot was taken from, parsed from a `<prefix>/<name>-<timestamp>.<ext>` key
+pub fn database_name(key: &str) -> &str {
+    let series = snapshot_series(key);
+    series.rsplit('/').next().unwrap_or(series)
+}
+
+/// Newest of `snapshots` taken from database `database`, the one `snapshots restore --latest` restores
+pub fn latest_of_database(snapshots: Vec<BackupMetadata>, database: &str) -> Option<BackupMetadata> {
+    let filter = SnapshotFilter { database: Some(database.to_string()), ..Default::default() };
+    let mut snapshots: Vec<_> = snapshots.into_iter().filter(|s| filter.matches(s)).collect();
+    SnapshotSort::default().apply(&mut snapshots);
+    snapshots.into_iter().next()
+}
+
+/// Parse a `YYYY-MM-DD` date as the start of that day in UTC, or an RFC 3339 timestamp
+pub fn parse_date(value: &str) -> Result<DateTime<Utc>> {
+    if let Ok(date) = NaiveDate::parse_from_str(value, "%Y-%m-%d") {
+        return Ok(date.and_hms_opt(0, 0, 0).unwrap_or_default().and_utc());
+    }
+    DateTime::parse_from_rfc3339(value)
+        .map(|t| t.with_timezone(&Utc))
+        .map_err(|_| anyhow!("Invalid date '{}', use YYYY-MM-DD", value))
+}
+
+/// Like `parse_date`, but a plain date means the end of that day
+fn parse_date_end(value: &str) -> Result<DateTime<Utc>> {
+    let time = parse_date(value)?;
+    Ok(match NaiveDate::parse_from_str(value, "%Y-%m-%d") {
+        Ok(_) => time + chrono::Duration::days(1),
+        Err(_) => time,
+    })
+}
+
+/// Parse a size such as `512`, `100MB` or `1.5G`. KB, MB, GB and TB are powers of 1000, like the sizes in the
+/// list and details pane; KiB, MiB, GiB and TiB are powers of 1024.
+pub fn parse_size(value: &str) -> Result<u64> {
+    let split = value.find(|c: char| !(c.is_ascii_digit() || c == '.')).unwrap_or(value.len());
+    let (number, unit) = value.split_at(split);
+    let number: f64 = number.parse().map_err(|_| anyhow!("Invalid size '{}'", value))?;
+
+    let multiplier: u64 = match unit.to_ascii_lowercase().as_str() {
+        "" | "b" => 1,
+        "k" | "kb" => 1000,
+        "m" | "mb" => 1000 * 1000,
+        "g" | "gb" => 1000 * 1000 * 1000,
+        "t" | "tb" => 1000 * 1000 * 1000 * 1000,
+        "kib" => 1024,
+        "mib" => 1024 * 1024,
+        "gib" => 1024 * 1024 * 1024,
+        "tib" => 1024 * 1024 * 1024 * 1024,
+        _ => return Err(anyhow!("Invalid size unit '{}', use B, KB, MB, GB, TB or KiB, MiB, GiB, TiB", unit)),
+    };
+    Ok((number * multiplier as f64) as u64)
+}
@@ -0,0 +1,166 @@
+use anyhow::{Context, Result};
+use chrono::{DateTime, SecondsFormat, Utc};
+use log::warn;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use crate::cancel::Cancelled;
+
+/// How a recorded job ended
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HistoryState {
+    Succeeded,
+    Failed,
+    Cancelled,
+}
+
+impl fmt::Display for HistoryState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HistoryState::Succeeded => write!(f, "succeeded"),
+            HistoryState::Failed => write!(f, "failed"),
+            HistoryState::Cancelled => write!(f, "cancelled"),
+        }
+    }
+}
+
+/// A finished job, one JSON object per line of the history file
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct HistoryEntry {
+    /// dump, backup, download, restore, copy, rename, delete, prune, clone, create, rename-database, set-owner or drop
+    pub kind: String,
+    /// Snapshot key, database or file the job worked on
+    pub subject: String,
+    /// Where the result went, e.g. the database restored into or the new key
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub target: Option<String>,
+    pub state: HistoryState,
+    /// RFC 3339 times the job started and finished
+    pub started_at: String,
+    pub finished_at: String,
+    pub duration_secs: f64,
+    /// Size of the snapshot or dump the job moved
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bytes: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
+    /// Why the job failed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl HistoryEntry {
+    /// An entry for a job that started at `started_at` and just ended with `result`
+    pub fn finished(kind: &str, subject: &str, started_at: DateTime<Utc>, result: Result<(), &anyhow::Error>) -> Self {
+        let finished_at = Utc::now();
+        let (state, error) = match result {
+            Ok(()) => (HistoryState::Succeeded, None),
+            Err(e) if e.is::<Cancelled>() => (HistoryState::Cancelled, None),
+            Err(e) => (HistoryState::Failed, Some(format!("{:#}", e))),
+        };
+        Self {
+            kind: kind.to_string(),
+            subject: subject.to_string(),
+            target: None,
+            state,
+            started_at: started_at.to_rfc3339_opts(SecondsFormat::Secs, true),
+            finished_at: finished_at.to_rfc3339_opts(SecondsFormat::Secs, true),
+            duration_secs: (finished_at - started_at).num_milliseconds() as f64 / 1000.0,
+            bytes: None,
+            message: None,
+            error,
+        }
+    }
+}
+
+impl fmt::Display for HistoryEntry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let finished = DateTime::parse_from_rfc3339(&self.finished_at)
+            .map(|t| t.with_timezone(&Utc).format("%Y-%m-%d %H:%M").to_string())
+            .unwrap_or_else(|_| self.finished_at.clone());
+        write!(f, "{} {} {}", finished, self.kind, self.subject)?;
+        if let Some(target) = &self.target {
+            write!(f, " -> {}", target)?;
+        }
+        write!(f, " {} in {:.0}s", self.state, self.duration_secs)?;
+        if let Some(bytes) = self.bytes {
+            write!(f, " ({})", humansize::format_size(bytes, humansize::DECIMAL))?;
+        }
+        if let Some(error) = &self.error {
+            write!(f, ": {}", error)?;
+        }
+        Ok(())
+    }
+}
+
+/// Where the history goes unless `JOBS_HISTORY_FILE` says otherwise: `postgres_manager/history.jsonl` in the user's
+/// state directory, `state_home` (`$XDG_STATE_HOME`) or else `.local/state` in `home`. Relative directories are
+/// ignored, as the XDG spec asks, and without either the file goes in the working directory.
+pub fn default_path(state_home: Option<PathBuf>, home: Option<PathBuf>) -> PathBuf {
+    let state_dir = state_home
+        .filter(|dir| dir.is_absolute())
+        .or_else(|| home.filter(|dir| dir.is_absolute()).map(|home| home.join(".local").join("state")));
+    match state_dir {
+        Some(dir) => dir.join("postgres_manager").join("history.jsonl"),
+        None => PathBuf::from("postgres_manager_history.jsonl"),
+    }
+}
+
+/// The local file finished jobs are recorded in, so they can be looked up after the program exits
+#[derive(Clone, Debug, PartialEq)]
+pub struct JobHistory {
+    path: PathBuf,
+}
+
+impl JobHistory {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self { path: path.into() }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Add an entry to the end of the file, creating it and its directory if needed. The history names databases
+    /// and snapshots and records errors, so a new file is only readable by its owner.
+    pub fn append(&self, entry: &HistoryEntry) -> Result<()> {
+        if let Some(dir) = self.path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            std::fs::create_dir_all(dir).with_context(|| format!("Failed to create job history directory {}", dir.display()))?;
+        }
+        let mut options = std::fs::OpenOptions::new();
+        options.create(true).append(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+        let mut file = options
+            .open(&self.path)
+            .with_context(|| format!("Failed to open job history {}", self.path.display()))?;
+        writeln!(file, "{}", serde_json::to_string(entry)?)
+            .with_context(|| format!("Failed to write job history {}", self.path.display()))
+    }
+
+    /// The last `limit` entries, newest first. A missing file is an empty history and unreadable lines are skipped.
+    pub fn load(&self, limit: usize) -> Result<Vec<HistoryEntry>> {
+        let contents = match std::fs::read_to_string(&self.path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e).with_context(|| format!("Failed to read job history {}", self.path.display())),
+        };
+
+        Ok(contents
+            .lines()
+            .rev()
+            .filter(|line| !line.trim().is_empty())
+            .filter_map(|line| match serde_json::from_str(line) {
+                Ok(entry) => Some(entry),
+                Err(e) => {
+                    warn!("Skipping unreadable job history line in {}: {}", self.path.display(), e);
+                    None
+                }
+            })
+            .take(limit)
+            .collect())
+    }
+}
@@ -0,0 +1,589 @@
+use anyhow::{bail, Result};
+use chrono::{DateTime, SecondsFormat, Utc};
+use log::{error, info};
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+use tokio::sync::oneshot;
+use tokio::task::JoinHandle;
+
+use crate::backup::RestoreMode;
+use crate::cancel::{CancelToken, Cancelled};
+use crate::history::{HistoryEntry, HistoryState};
+use crate::postgres;
+use crate::retention::PrunePlan;
+use crate::s3;
+use crate::service::{self, RestoreOutcome, RestoreRequest, ServiceEvent, SnapshotService};
+use crate::ui::models::{BackupMetadata, DatabaseAction, KeyAction, PostgresConfig};
+
+pub type JobId = u64;
+
+/// What a background job does
+#[derive(Clone, Debug, PartialEq)]
+pub enum JobKind {
+    /// Download and verify a snapshot, keeping the file
+    Download,
+    /// Restore a snapshot into `target`
+    Restore { mode: RestoreMode, target: String },
+    /// Copy or rename a snapshot and its sidecars to `new_key`
+    Copy { new_key: String, action: KeyAction },
+    /// Delete a snapshot and its sidecars
+    Delete,
+    /// Delete the snapshots a retention policy doesn't keep
+    Prune,
+    /// Copy a database to `new_name`
+    Clone { new_name: String },
+    /// Create an empty database
+    CreateDatabase,
+    /// Rename a database to `new_name`
+    RenameDatabase { new_name: String },
+    /// Hand a database over to `owner`
+    SetOwner { owner: String },
+    /// Drop a database
+    DropDatabase,
+}
+
+impl JobKind {
+    /// Lowercase name the job is recorded under in the history
+    pub fn name(&self) -> &'static str {
+        match self {
+            JobKind::Download => "download",
+            JobKind::Restore { .. } => "restore",
+            JobKind::Copy { action: KeyAction::Copy, .. } => "copy",
+            JobKind::Copy { action: KeyAction::Rename, .. } => "rename",
+            JobKind::Delete => "delete",
+            JobKind::Prune => "prune",
+            JobKind::Clone { .. } => "clone",
+            JobKind::CreateDatabase => "create",
+            JobKind::RenameDatabase { .. } => "rename-database",
+            JobKind::SetOwner { .. } => "set-owner",
+            JobKind::DropDatabase => "drop",
+        }
+    }
+
+    /// Where the job puts its result, if anywhere
+    pub fn target(&self) -> Option<&str> {
+        match self {
+            JobKind::Restore { target, .. } => Some(target),
+            JobKind::Copy { new_key, .. } => Some(new_key),
+            JobKind::Clone { new_name } | JobKind::RenameDatabase { new_name } => Some(new_name),
+            JobKind::SetOwner { owner } => Some(owner),
+            JobKind::Download | JobKind::Delete | JobKind::Prune | JobKind::CreateDatabase | JobKind::DropDatabase => None,
+        }
+    }
+
+    /// Whether the job changes the databases on the server rather than snapshots
+    pub fn is_database(&self) -> bool {
+        matches!(
+            self,
+            JobKind::Clone { .. } | JobKind::CreateDatabase | JobKind::RenameDatabase { .. } | JobKind::SetOwner { .. } | JobKind::DropDatabase
+        )
+    }
+}
+
+impl std::fmt::Display for JobKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            JobKind::Download => write!(f, "Download"),
+            JobKind::Restore { .. } => write!(f, "Restore"),
+            JobKind::Copy { action, .. } => write!(f, "{}", action),
+            JobKind::Delete => write!(f, "Delete"),
+            JobKind::Prune => write!(f, "Prune"),
+            JobKind::Clone { .. } => write!(f, "Clone"),
+            JobKind::CreateDatabase => write!(f, "Create"),
+            JobKind::RenameDatabase { .. } => write!(f, "Rename"),
+            JobKind::SetOwner { .. } => write!(f, "Set owner"),
+            JobKind::DropDatabase => write!(f, "Drop"),
+        }
+    }
+}
+
+/// Whether a job is still running and how it ended, with a message saying why
+#[derive(Clone, Debug, PartialEq)]
+pub enum JobStatus {
+    Running,
+    Succeeded(String),
+    Failed(String),
+    Cancelled(String),
+}
+
+/// How a job task ended, and the local copy of the snapshot it left behind
+type JobResult = (JobStatus, Option<PathBuf>);
+
+/// An operation on snapshots running on its own task
+#[derive(Debug)]
+pub struct Job {
+    pub id: JobId,
+    pub kind: JobKind,
+    /// Snapshot key the job works on, or what it works on otherwise
+    pub subject: String,
+    /// Size of the snapshots the job moves or deletes
+    pub bytes: Option<u64>,
+    pub status: JobStatus,
+    /// Latest progress reported by the service
+    pub stage: Option<ServiceEvent>,
+    /// Whether cancelling would still stop the job
+    pub cancellable: bool,
+    /// Local copy of the snapshot once the job has finished with one
+    pub download: Option<PathBuf>,
+    /// File the job may download the snapshot to while it runs
+    download_path: Option<PathBuf>,
+    pub started_at: DateTime<Utc>,
+    pub finished_at: Option<DateTime<Utc>>,
+    /// When `stage` last moved on to a different step, to estimate how long the step has left
+    stage_started: Instant,
+    cancel: CancelToken,
+    events: UnboundedReceiver<ServiceEvent>,
+    result: oneshot::Receiver<JobResult>,
+    task: JoinHandle<()>,
+}
+
+impl Job {
+    pub fn is_running(&self) -> bool {
+        self.status == JobStatus::Running
+    }
+
+    /// Short name of the step the job is at
+    pub fn stage_label(&self) -> &'static str {
+        match (&self.status, &self.stage) {
+            (JobStatus::Succeeded(_), _) => "done",
+            (JobStatus::Failed(_), _) => "failed",
+            (JobStatus::Cancelled(_), _) => "cancelled",
+            (JobStatus::Running, None) => "running",
+            (JobStatus::Running, Some(ServiceEvent::Downloading(_) | ServiceEvent::Downloaded(_))) => "downloading",
+            (JobStatus::Running, Some(ServiceEvent::Verifying)) => "verifying",
+            (JobStatus::Running, Some(ServiceEvent::Restoring { .. })) => "restoring",
+        }
+    }
+
+    /// Share of the current step done, for steps that report it
+    pub fn progress(&self) -> Option<f32> {
+        match self.stage.as_ref()? {
+            ServiceEvent::Downloading(progress) => Some(progress.fraction()),
+            ServiceEvent::Restoring { fraction, .. } => Some(*fraction),
+            _ => None,
+        }
+    }
+
+    /// Download speed in bytes per second while downloading
+    pub fn rate(&self) -> Option<f64> {
+        match self.stage.as_ref()? {
+            ServiceEvent::Downloading(progress) if progress.rate > 0.0 => Some(progress.rate),
+            _ => None,
+        }
+    }
+
+    /// Estimated time until the current step is done
+    pub fn eta(&self) -> Option<Duration> {
+        if !self.is_running() {
+            return None;
+        }
+        estimate_remaining(self.stage.as_ref()?, self.stage_started.elapsed())
+    }
+
+    /// One line describing the job for the jobs panel
+    pub fn summary(&self) -> String {
+        let mut line = format!("#{} {} {}", self.id, self.kind, self.subject);
+        match &self.kind {
+            JobKind::Restore { target, .. } => line.push_str(&format!(" into '{}'", target)),
+            JobKind::Copy { new_key, .. } => line.push_str(&format!(" to {}", new_key)),
+            JobKind::Clone { new_name } | JobKind::RenameDatabase { new_name } => line.push_str(&format!(" to '{}'", new_name)),
+            JobKind::SetOwner { owner } => line.push_str(&format!(" to '{}'", owner)),
+            JobKind::Download | JobKind::Delete | JobKind::Prune | JobKind::CreateDatabase | JobKind::DropDatabase => {}
+        }
+        line.push_str(&format!(" - {}", self.stage_label()));
+
+        match &self.status {
+            JobStatus::Running => {
+                if let Some(progress) = self.progress() {
+                    line.push_str(&format!(" {:.0}%", progress * 100.0));
+                }
+                if let Some(rate) = self.rate() {
+                    line.push_str(&format!(" {}/s", humansize::format_size(rate as u64, humansize::DECIMAL)));
+                }
+                if let Some(eta) = self.eta() {
+                    line.push_str(&format!(" ETA {}", format_duration(eta)));
+                }
+            }
+            JobStatus::Succeeded(message) | JobStatus::Failed(message) | JobStatus::Cancelled(message) => {
+                line.push_str(&format!(" after {}: {}", format_duration(self.elapsed()), message));
+            }
+        }
+        line
+    }
+
+    /// How long the job ran, or has been running so far
+    pub fn elapsed(&self) -> Duration {
+        (self.finished_at.unwrap_or_else(Utc::now) - self.started_at).to_std().unwrap_or_default()
+    }
+
+    /// The job as recorded in the history once it has finished
+    pub fn history_entry(&self) -> Option<HistoryEntry> {
+        let (state, message, error) = match &self.status {
+            JobStatus::Running => return None,
+            JobStatus::Succeeded(message) => (HistoryState::Succeeded, Some(message.clone()), None),
+            JobStatus::Failed(error) => (HistoryState::Failed, None, Some(error.clone())),
+            JobStatus::Cancelled(message) => (HistoryState::Cancelled, Some(message.clone()), None),
+        };
+        Some(HistoryEntry {
+            kind: self.kind.name().to_string(),
+            subject: self.subject.clone(),
+            target: self.kind.target().map(str::to_string),
+            state,
+            started_at: self.started_at.to_rfc3339_opts(SecondsFormat::Secs, true),
+            finished_at: self.finished_at?.to_rfc3339_opts(SecondsFormat::Secs, true),
+            duration_secs: self.elapsed().as_secs_f64(),
+            bytes: self.bytes,
+            message,
+            error,
+        })
+    }
+
+    /// Whether cancelling would still stop the job, and why not otherwise
+    pub fn check_cancellable(&self) -> Result<()> {
+        if !self.is_running() {
+            bail!("Job #{} has already finished", self.id);
+        }
+        if !self.cancellable {
+            match self.kind {
+                // Restores over an existing database can't be stopped once pg_restore runs
+                JobKind::Restore { .. } => bail!("Restoring over an existing database cannot be cancelled"),
+                _ => bail!("{} of {} cannot be cancelled", self.kind, self.subject),
+            }
+        }
+        Ok(())
+    }
+
+    fn update(&mut self, event: ServiceEvent) {
+        if let ServiceEvent::Restoring { cancellable, .. } = &event {
+            self.cancellable = *cancellable;
+        }
+        if self.stage.as_ref().map(std::mem::discriminant) != Some(std::mem::discriminant(&event)) {
+            self.stage_started = Instant::now();
+        }
+        self.stage = Some(event);
+    }
+
+    fn finish(&mut self, status: JobStatus, download: Option<PathBuf>) {
+        info!("Job #{} on {} finished: {:?}", self.id, self.subject, status);
+        self.status = status;
+        self.download = download;
+        self.cancellable = false;
+        self.finished_at = Some(Utc::now());
+    }
+}
+
+/// Time left for a step that has made `event`'s progress in `elapsed`. Downloads go by their current speed,
+/// restores by how fast they have progressed so far.
+pub fn estimate_remaining(event: &ServiceEvent, elapsed: Duration) -> Option<Duration> {
+    match event {
+        ServiceEvent::Downloading(progress) if progress.rate > 0.0 => {
+            let remaining = progress.total.saturating_sub(progress.transferred);
+            Some(Duration::from_secs_f64(remaining as f64 / progress.rate))
+        }
+        ServiceEvent::Restoring { fraction, .. } if *fraction > 0.0 && *fraction < 1.0 => {
+            Some(elapsed.mul_f64((1.0 - *fraction as f64) / *fraction as f64))
+        }
+        _ => None,
+    }
+}
+
+/// Format a duration as `1h02m`, `3m05s` or `42s`
+pub fn format_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    match secs {
+        0..=59 => format!("{}s", secs),
+        60..=3599 => format!("{}m{:02}s", secs / 60, secs % 60),
+        _ => format!("{}h{:02}m", secs / 3600, secs % 3600 / 60),
+    }
+}
+
+/// Background downloads and restores. Jobs run on their own tasks; `poll` picks up their progress.
+#[derive(Debug, Default)]
+pub struct JobManager {
+    jobs: Vec<Job>,
+    next_id: JobId,
+}
+
+impl JobManager {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Every job, oldest first
+    pub fn jobs(&self) -> &[Job] {
+        &self.jobs
+    }
+
+    pub fn get(&self, id: JobId) -> Option<&Job> {
+        self.jobs.iter().find(|job| job.id == id)
+    }
+
+    /// Number of jobs still running
+    pub fn running(&self) -> usize {
+        self.jobs.iter().filter(|job| job.is_running()).count()
+    }
+
+    /// Download and verify `snapshot` in the background, keeping the file in the service's download directory.
+    /// Refused while another job may be downloading to the same file.
+    pub fn spawn_download(&mut self, service: SnapshotService, snapshot: BackupMetadata) -> Result<JobId> {
+        let download_path = self.claim_download_path(&service, &snapshot.key)?;
+        let (subject, bytes) = (snapshot.key.clone(), Some(snapshot.size.max(0) as u64));
+        Ok(self.spawn(JobKind::Download, subject, bytes, Some(download_path), true, move |events, cancel| async move {
+            let result = async {
+                let path = service.download_snapshot(&snapshot, &events, &cancel).await?;
+                service.verify_snapshot(&snapshot, &path, &events).await?;
+                Ok::<_, anyhow::Error>(path)
+            }
+            .await;
+            match result {
+                Ok(path) => (JobStatus::Succeeded(format!("Downloaded to {}", path.display())), Some(path)),
+                Err(e) => (failed_status(&snapshot.key, e), None),
+            }
+        }))
+    }
+
+    /// Restore a snapshot in the background. Only one restore may write into a database at a time, and none
+    /// while another job may be downloading the snapshot to the same file.
+    pub fn spawn_restore(&mut self, service: SnapshotService, request: RestoreRequest) -> Result<JobId> {
+        let busy = self.jobs.iter().any(|job| {
+            job.is_running() && matches!(&job.kind, JobKind::Restore { target, .. } if *target == request.target)
+        });
+        if busy {
+            bail!("A restore into '{}' is already running", request.target);
+        }
+        let download_path = self.claim_download_path(&service, &request.snapshot.key)?;
+
+        let kind = JobKind::Restore { mode: request.mode, target: request.target.clone() };
+        let (subject, bytes) = (request.snapshot.key.clone(), Some(request.snapshot.size.max(0) as u64));
+        Ok(self.spawn(kind, subject, bytes, Some(download_path), true, move |events, cancel| async move {
+            match service.restore(&request, &events, &cancel).await {
+                Ok(RestoreOutcome::Restored { target }) => (JobStatus::Succeeded(format!("Restored into database '{}'", target)), None),
+                Ok(RestoreOutcome::Cancelled { dropped }) => (JobStatus::Cancelled(format!("Cancelled, dropped '{}'", dropped)), None),
+                Err(e) => (failed_status(&request.snapshot.key, e), None),
+            }
+        }))
+    }
+
+    /// Copy or rename a snapshot along with its sidecars in the background
+    pub fn spawn_copy(&mut self, service: SnapshotService, snapshot: BackupMetadata, new_key: String, action: KeyAction) -> JobId {
+        let kind = JobKind::Copy { new_key: new_key.clone(), action };
+        let (subject, bytes) = (snapshot.key.clone(), Some(snapshot.size.max(0) as u64));
+        self.spawn(kind, subject, bytes, None, false, move |_, _| async move {
+            let done = match action {
+                KeyAction::Copy => "Copied",
+                KeyAction::Rename => "Renamed",
+            };
+            match service.copy_snapshot(&snapshot, &new_key, action).await {
+                Ok(()) => (JobStatus::Succeeded(format!("{} to {}", done, new_key)), None),
+                Err(e) => (failed_status(&snapshot.key, e), None),
+            }
+        })
+    }
+
+    /// Delete a snapshot along with its sidecars in the background
+    pub fn spawn_delete(&mut self, service: SnapshotService, snapshot: BackupMetadata) -> JobId {
+        let (subject, bytes) = (snapshot.key.clone(), Some(snapshot.size.max(0) as u64));
+        self.spawn(JobKind::Delete, subject, bytes, None, false, move |_, _| async move {
+            match service.delete_snapshot(&snapshot).await {
+                Ok(()) => (JobStatus::Succeeded(format!("Deleted {}", snapshot.key)), None),
+                Err(e) => (failed_status(&snapshot.key, e), None),
+            }
+        })
+    }
+
+    /// Delete the snapshots of a confirmed prune in the background
+    pub fn spawn_prune(&mut self, service: SnapshotService, plan: PrunePlan) -> JobId {
+        let subject = format!("{} snapshots", plan.delete.len());
+        let bytes = Some(plan.delete_size());
+        self.spawn(JobKind::Prune, subject.clone(), bytes, None, false, move |_, _| async move {
+            match service.prune(&plan).await {
+                Ok(()) => (JobStatus::Succeeded(format!("Deleted {}", subject)), None),
+                Err(e) => (failed_status(&subject, e), None),
+            }
+        })
+    }
+
+    /// File `service` downloads `key` to, unless a running job may already be downloading to it. Two jobs writing
+    /// the same file would corrupt each other's download.
+    fn claim_download_path(&self, service: &SnapshotService, key: &str) -> Result<PathBuf> {
+        let path = s3::download_path(&service.download_dir, key);
+        if let Some(job) = self.download_job(&path) {
+            bail!("Job #{} is already downloading {} to {}", job.id, job.subject, path.display());
+        }
+        Ok(path)
+    }
+
+    /// Running job that may be downloading to `path`
+    fn download_job(&self, path: &Path) -> Option<&Job> {
+        self.jobs.iter().find(|job| job.is_running() && job.download_path.as_deref() == Some(path))
+    }
+
+    fn spawn<F, Fut>(
+        &mut self,
+        kind: JobKind,
+        subject: String,
+        bytes: Option<u64>,
+        download_path: Option<PathBuf>,
+        cancellable: bool,
+        run: F,
+    ) -> JobId
+    where
+        F: FnOnce(UnboundedSender<ServiceEvent>, CancelToken) -> Fut,
+        Fut: Future<Output = JobResult> + Send + 'static,
+    {
+        self.next_id += 1;
+        let id = self.next_id;
+        let (events_tx, events) = mpsc::unbounded_channel();
+        let (result_tx, result) = oneshot::channel();
+        let cancel = CancelToken::new();
+
+        let job = run(events_tx, cancel.clone());
+        let task = tokio::spawn(async move {
+            let _ = result_tx.send(job.await);
+        });
+        info!("Started job #{} ({:?}) on {}", id, kind, subject);
+
+        self.jobs.push(Job {
+            id,
+            kind,
+            subject,
+            bytes,
+            status: JobStatus::Running,
+            stage: None,
+            cancellable,
+            download: None,
+            download_path,
+            started_at: Utc::now(),
+            finished_at: None,
+            stage_started: Instant::now(),
+            cancel,
+            events,
+            result,
+            task,
+        });
+        id
+    }
+
+    /// Clone the database `name` in the background, connecting with `pg_config`
+    pub fn spawn_clone(&mut self, pg_config: PostgresConfig, name: String) -> JobId {
+        let new_name = postgres::clone_name(&name);
+        let kind = JobKind::Clone { new_name: new_name.clone() };
+        let done = format!("Cloned {} to {}", name, new_name);
+        self.spawn_database(pg_config, kind, name, done, |client, name| async move {
+            postgres::clone_database(&client, &name).await
+        })
+    }
+
+    /// Create, rename or change the owner of a database in the background, with `input` as the name of the new
+    /// database, the new name or the new owner
+    pub fn spawn_database_action(&mut self, pg_config: PostgresConfig, action: &DatabaseAction, input: String) -> JobId {
+        match action {
+            DatabaseAction::Create => {
+                let done = format!("Created database '{}'", input);
+                self.spawn_database(pg_config, JobKind::CreateDatabase, input, done, |client, name| async move {
+                    postgres::create_database(&client, &name).await
+                })
+            }
+            DatabaseAction::Rename(name) => {
+                let kind = JobKind::RenameDatabase { new_name: input.clone() };
+                let done = format!("Renamed database '{}' to '{}'", name, input);
+                self.spawn_database(pg_config, kind, name.clone(), done, |client, name| async move {
+                    postgres::rename_database(&client, &name, &input).await
+                })
+            }
+            DatabaseAction::SetOwner(name) => {
+                let kind = JobKind::SetOwner { owner: input.clone() };
+                let done = format!("Database '{}' is now owned by '{}'", name, input);
+                self.spawn_database(pg_config, kind, name.clone(), done, |client, name| async move {
+                    postgres::set_database_owner(&client, &name, &input).await
+                })
+            }
+        }
+    }
+
+    /// Drop the database `name` in the background
+    pub fn spawn_drop_database(&mut self, pg_config: PostgresConfig, name: String) -> JobId {
+        let done = format!("Dropped database '{}'", name);
+        self.spawn_database(pg_config, JobKind::DropDatabase, name, done, |client, name| async move {
+            postgres::drop_database(&client, &name).await
+        })
+    }
+
+    /// Connect with `pg_config` and run `run` on the database `name` in the background, succeeding with `done`.
+    /// Waiting on a database that other sessions hold on to then only holds up the job.
+    fn spawn_database<F, Fut>(&mut self, pg_config: PostgresConfig, kind: JobKind, name: String, done: String, run: F) -> JobId
+    where
+        F: FnOnce(tokio_postgres::Client, String) -> Fut + Send + 'static,
+        Fut: Future<Output = Result<()>> + Send,
+    {
+        self.spawn(kind, name.clone(), None, None, false, move |_, _| async move {
+            let result = async {
+                let client = service::connect_pg(&pg_config, &service::pg_connection_config(&pg_config, None)).await?;
+                run(client, name.clone()).await
+            }
+            .await;
+            match result {
+                Ok(()) => (JobStatus::Succeeded(done), None),
+                Err(e) => (failed_status(&name, e), None),
+            }
+        })
+    }
+
+    /// Apply the progress jobs have reported since the last call, returning the ids of jobs that finished meanwhile
+    pub fn poll(&mut self) -> Vec<JobId> {
+        let mut finished = Vec::new();
+        for job in self.jobs.iter_mut().filter(|job| job.is_running()) {
+            while let Ok(event) = job.events.try_recv() {
+                job.update(event);
+            }
+            match job.result.try_recv() {
+                Ok((status, download)) => job.finish(status, download),
+                Err(oneshot::error::TryRecvError::Empty) => continue,
+                Err(oneshot::error::TryRecvError::Closed) => {
+                    error!("Job #{} on {} stopped without a result", job.id, job.subject);
+                    job.finish(JobStatus::Failed("Job stopped unexpectedly".to_string()), None);
+                }
+            }
+            finished.push(job.id);
+        }
+        finished
+    }
+
+    /// Ask a running job to stop
+    pub fn cancel(&self, id: JobId) -> Result<()> {
+        let Some(job) = self.get(id) else {
+            bail!("No job #{}", id);
+        };
+        job.check_cancellable()?;
+        info!("Cancelling job #{} on {}", id, job.subject);
+        job.cancel.cancel();
+        Ok(())
+    }
+
+    /// Forget the jobs that have finished
+    pub fn clear_finished(&mut self) {
+        self.jobs.retain(Job::is_running);
+    }
+
+    /// Cancel every running job and wait for all of them to wind down, returning the ids of the jobs that finished
+    pub async fn shutdown(&mut self) -> Vec<JobId> {
+        for job in self.jobs.iter().filter(|job| job.is_running()) {
+            job.cancel.cancel();
+        }
+        for job in self.jobs.iter_mut().filter(|job| job.is_running()) {
+            let _ = (&mut job.task).await;
+        }
+        self.poll()
+    }
+}
+
+fn failed_status(subject: &str, e: anyhow::Error) -> JobStatus {
+    if e.is::<Cancelled>() {
+        // The partial download is kept so the next attempt resumes where this one stopped
+        return JobStatus::Cancelled("Cancelled".to_string());
+    }
+    error!("Job on {} failed: {:#}", subject, e);
+    JobStatus::Failed(format!("{:#}", e))
+}
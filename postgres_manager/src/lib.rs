@@ -2,7 +2,21 @@
 pub mod ui;
 pub mod config;
 pub mod backup;
+pub mod cancel;
+pub mod checksum;
+pub mod compression;
+pub mod crypto;
+pub mod filter;
+pub mod history;
+pub mod jobs;
+pub mod manifest;
+pub mod output;
 pub mod postgres;
+pub mod retention;
+pub mod s3;
+pub mod service;
+pub mod sort;
+pub mod toc;
 
 // Re-export other modules as needed
 pub use crate::ui::browser::run_tui;
@@ -1,7 +1,8 @@
-use postgres_manager::{backup, ui, config};
+use postgres_manager::{backup, ui, cancel, config, compression, crypto, filter, history, output, retention, s3, service, sort};
+use postgres_manager::ui::models::S3Config;
 
 use anyhow::Result;
-use clap::{Parser, Subcommand, command, arg};
+use clap::{Args, Parser, Subcommand};
 use postgres_manager::postgres;
 use tokio_postgres::config::SslMode;
 use tokio_postgres::Config as PgConfig;
@@ -62,6 +63,55 @@ struct Cli {
 
     #[arg(long, default_value = "true", env = "S3_PATH_STYLE", help = "S3 Force path-style")]
     path_style: bool,
+
+    #[arg(long, env = "BACKUP_ENCRYPTION_KEY_FILE", help = "age identity file used to encrypt backups and decrypt snapshots")]
+    encryption_key_file: Option<String>,
+}
+
+#[derive(Args)]
+struct DumpArgs {
+    #[arg(long, value_enum, default_value_t = backup::DumpFormat::Custom, help = "pg_dump output format")]
+    format: backup::DumpFormat,
+
+    #[arg(long, value_parser = clap::value_parser!(u32).range(0..=9), help = "Compression level (0-9)")]
+    compress: Option<u32>,
+
+    #[arg(short, long, help = "Number of parallel dump jobs (directory format only)")]
+    jobs: Option<u32>,
+
+    #[arg(long = "schema", help = "Only dump the named schema (repeatable)")]
+    schemas: Vec<String>,
+
+    #[arg(long = "exclude-schema", help = "Do not dump the named schema (repeatable)")]
+    exclude_schemas: Vec<String>,
+
+    #[arg(long = "table", help = "Only dump the named table (repeatable)")]
+    tables: Vec<String>,
+
+    #[arg(long = "exclude-table", help = "Do not dump the named table (repeatable)")]
+    exclude_tables: Vec<String>,
+
+    #[arg(long, help = "Skip restoration of object ownership")]
+    no_owner: bool,
+
+    #[arg(long, help = "Skip dumping access privileges (grant/revoke)")]
+    no_acl: bool,
+}
+
+impl From<&DumpArgs> for backup::DumpOptions {
+    fn from(args: &DumpArgs) -> Self {
+        backup::DumpOptions {
+            format: args.format,
+            compress: args.compress,
+            jobs: args.jobs,
+            schemas: args.schemas.clone(),
+            exclude_schemas: args.exclude_schemas.clone(),
+            tables: args.tables.clone(),
+            exclude_tables: args.exclude_tables.clone(),
+            no_owner: args.no_owner,
+            no_acl: args.no_acl,
+        }
+    }
 }
 
 #[derive(Subcommand)]
@@ -118,6 +168,30 @@ enum Commands {
 
         #[arg(help = "Output file path")]
         output: String,
+
+        #[command(flatten)]
+        dump: DumpArgs,
+    },
+
+    #[command(about = "Back up a database to S3")]
+    Backup {
+        #[arg(help = "Name of the database to back up")]
+        name: String,
+
+        #[arg(long, help = "Object key to upload to. Defaults to '<prefix>/<name>-<timestamp>.<ext>'")]
+        key: Option<String>,
+
+        #[command(flatten)]
+        dump: DumpArgs,
+
+        #[arg(long, value_enum, default_value_t = compression::Compression::None, help = "Compress the uploaded object")]
+        compression: compression::Compression,
+
+        #[arg(long, help = "Encrypt the backup to --encryption-recipient, or to the configured age key")]
+        encrypt: bool,
+
+        #[arg(long, env = "BACKUP_ENCRYPTION_RECIPIENT", help = "age public key (age1...) --encrypt encrypts to, so the machine taking backups needs no identity")]
+        encryption_recipient: Option<String>,
     },
 
     #[command(about = "Restore a database from dump")]
@@ -127,14 +201,92 @@ enum Commands {
 
         #[arg(help = "Input dump file path")]
         input: String,
+
+        #[arg(long, value_enum, default_value_t = backup::RestoreMode::Existing, help = "Restore over the existing database, into a new one, or into a scratch database that is swapped in")]
+        mode: backup::RestoreMode,
+    },
+
+    #[command(about = "Delete S3 snapshots outside the retention policy")]
+    Prune {
+        #[arg(long, env = "RETENTION_HOURLY", help = "Number of hourly snapshots to keep per database")]
+        hourly: Option<usize>,
+
+        #[arg(long, env = "RETENTION_DAILY", help = "Number of daily snapshots to keep per database")]
+        daily: Option<usize>,
+
+        #[arg(long, env = "RETENTION_WEEKLY", help = "Number of weekly snapshots to keep per database")]
+        weekly: Option<usize>,
+
+        #[arg(long, env = "RETENTION_MONTHLY", help = "Number of monthly snapshots to keep per database")]
+        monthly: Option<usize>,
+
+        #[arg(long, help = "Print the snapshots that would be deleted without deleting them")]
+        dry_run: bool,
+    },
+
+    #[command(subcommand, about = "Work with S3 snapshots without the TUI")]
+    Snapshots(SnapshotsCommand),
+
+    #[command(about = "Show finished dumps, backups, restores, clones and prunes")]
+    History {
+        #[arg(long, default_value = "20", help = "Print at most this many jobs, newest first")]
+        limit: usize,
+
+        #[arg(long, value_enum, default_value_t = output::OutputFormat::Table, help = "Print a table, JSON or CSV")]
+        format: output::OutputFormat,
     },
 
     /// Browse and restore S3 snapshots using TUI
     BrowseSnapshots,
 }
 
-async fn connect(cli: &Cli) -> Result<Option<tokio_postgres::Client>> {
-    if !cli.host.is_some() && !cli.port.is_some() && !cli.username.is_some() && !cli.password.is_some() {
+#[derive(Subcommand)]
+enum SnapshotsCommand {
+    #[command(about = "List S3 snapshots")]
+    List {
+        #[arg(long, default_value = "modified:desc", help = "Sort by modified, size or key, optionally followed by :asc or :desc")]
+        sort: sort::SnapshotSort,
+
+        #[arg(long, value_enum, default_value_t = output::OutputFormat::Table, help = "Print a table, JSON or CSV")]
+        format: output::OutputFormat,
+
+        #[arg(long, help = "Print at most this many snapshots")]
+        limit: Option<usize>,
+
+        #[arg(long, value_parser = filter::parse_date, help = "Only snapshots modified at or after this date (YYYY-MM-DD or RFC 3339)")]
+        since: Option<chrono::DateTime<chrono::Utc>>,
+
+        #[arg(long, conflicts_with_all = ["sort", "limit"], help = "Only print the newest snapshot, failing if there is none")]
+        latest: bool,
+    },
+
+    #[command(about = "Download a snapshot and restore it without the TUI")]
+    Restore {
+        #[arg(required_unless_present = "latest", help = "Key of the snapshot to restore")]
+        key: Option<String>,
+
+        #[arg(long, conflicts_with = "key", requires = "database", help = "Restore the newest snapshot of --database under the prefix")]
+        latest: bool,
+
+        #[arg(long, conflicts_with = "key", help = "Database whose snapshots --latest picks from, as named in their keys")]
+        database: Option<String>,
+
+        #[arg(long, help = "Database to restore into")]
+        target: String,
+
+        #[arg(long, value_enum, default_value_t = backup::RestoreMode::New, help = "Restore into a new database, into a scratch database that is swapped in, or over the existing database")]
+        mode: backup::RestoreMode,
+
+        #[arg(long, help = "Stream the snapshot into the restore instead of downloading it first")]
+        stream: bool,
+
+        #[arg(long, value_enum, default_value_t = output::ProgressFormat::Text, help = "Print progress as text lines or JSON lines")]
+        progress: output::ProgressFormat,
+    },
+}
+
+async fn connect(cli: &Cli, dbname: Option<&str>) -> Result<Option<tokio_postgres::Client>> {
+    if cli.host.is_none() && cli.port.is_none() && cli.username.is_none() && cli.password.is_none() {
         // If no PostgreSQL settings are provided, return None
         return Ok(None);
     }
@@ -146,7 +298,7 @@ async fn connect(cli: &Cli) -> Result<Option<tokio_postgres::Client>> {
     }
 
     // Set default host and port if not provided
-    config.host(&cli.host.clone().unwrap_or_else(|| "localhost".to_string()));
+    config.host(cli.host.clone().unwrap_or_else(|| "localhost".to_string()));
     config.port(cli.port.unwrap_or(5432));
 
     if let Some(ref user) = cli.username {
@@ -157,6 +309,10 @@ async fn connect(cli: &Cli) -> Result<Option<tokio_postgres::Client>> {
         config.password(password);
     }
 
+    if let Some(dbname) = dbname {
+        config.dbname(dbname);
+    }
+
     let result = if cli.use_ssl {
         postgres::connect_ssl(&config, cli.verify_ssl, cli.root_cert_path.as_deref()).await
     } else {
@@ -172,6 +328,29 @@ async fn connect(cli: &Cli) -> Result<Option<tokio_postgres::Client>> {
     }
   }
 
+/// Build the S3 configuration with CLI args taking precedence over env vars
+fn s3_config(cli: &Cli) -> S3Config {
+    let env_s3_config = config::load_s3_config();
+    S3Config {
+        bucket: cli.bucket.clone().unwrap_or(env_s3_config.bucket),
+        region: cli.region.clone().unwrap_or(env_s3_config.region),
+        prefix: cli.prefix.clone().unwrap_or(env_s3_config.prefix),
+        endpoint_url: cli.endpoint_url.clone().unwrap_or(env_s3_config.endpoint_url),
+        access_key_id: cli.access_key_id.clone().unwrap_or(env_s3_config.access_key_id),
+        secret_access_key: cli.secret_access_key.clone().unwrap_or(env_s3_config.secret_access_key),
+        path_style: if cli.bucket.is_some() { cli.path_style } else { env_s3_config.path_style },
+        error_message: None,
+    }
+}
+
+/// Add a finished job to the history file shared with the TUI
+fn record_history(entry: history::HistoryEntry) {
+    let history = history::JobHistory::new(config::load_history_path());
+    if let Err(e) = history.append(&entry) {
+        warn!("Failed to record {} of {} in the job history: {:#}", entry.kind, entry.subject, e);
+    }
+}
+
 #[tokio::main]
 async fn main() -> Result<()> {
     // Configure logging
@@ -191,12 +370,7 @@ async fn main() -> Result<()> {
     info!("Loaded environment variables");
 
     let cli: Cli = Cli::parse();
-    let client = connect(&cli).await?;
-
-    // Add PGSSLMODE environment variable if SSL is enabled
-    if cli.use_ssl {
-        std::env::set_var("PGSSLMODE", "require");
-    }
+    let client = connect(&cli, None).await?;
 
     match &cli.command {
         Commands::List => {
@@ -209,7 +383,7 @@ async fn main() -> Result<()> {
         }
         Commands::Create { name } => {
             if let Some(client) = client {
-                postgres::create_database(&client, &name).await?;
+                postgres::create_database(&client, name).await?;
             } else {
                 error!("PostgreSQL connection required for postgres::create_database");
                 return Ok(());
@@ -217,7 +391,7 @@ async fn main() -> Result<()> {
         }
         Commands::Drop { name } => {
             if let Some(client) = client {
-                postgres::drop_database(&client, &name).await?;
+                postgres::drop_database(&client, name).await?;
             } else {
                 error!("PostgreSQL connection required for postgres::drop_database");
                 return Ok(());
@@ -225,7 +399,13 @@ async fn main() -> Result<()> {
         }
         Commands::Clone { name } => {
             if let Some(client) = client {
-                postgres::clone_database(&client, &name).await?;
+                let started_at = chrono::Utc::now();
+                let result = postgres::clone_database(&client, name).await;
+                record_history(history::HistoryEntry {
+                    target: Some(postgres::clone_name(name)),
+                    ..history::HistoryEntry::finished("clone", name, started_at, result.as_ref().map(|_| ()))
+                });
+                result?;
             } else {
                 error!("PostgreSQL connection required for postgres::clone_database");
                 return Ok(());
@@ -233,7 +413,7 @@ async fn main() -> Result<()> {
         }
         Commands::DropForce { name } => {
             if let Some(client) = client {
-                postgres::drop_database_with_force(&client, &name).await?;
+                postgres::drop_database_with_force(&client, name).await?;
             } else {
                 error!("PostgreSQL connection required for postgres::drop_database_with_force");
                 return Ok(());
@@ -241,7 +421,7 @@ async fn main() -> Result<()> {
         }
         Commands::Rename { old_name, new_name } => {
             if let Some(client) = client {
-                postgres::rename_database(&client, &old_name, &new_name).await?;
+                postgres::rename_database(&client, old_name, new_name).await?;
             } else {
                 error!("PostgreSQL connection required for postgres::rename_database");
                 return Ok(());
@@ -249,46 +429,293 @@ async fn main() -> Result<()> {
         }
         Commands::SetOwner { name, owner } => {
             if let Some(client) = client {
-                postgres::set_database_owner(&client, &name, &owner).await?;
+                postgres::set_database_owner(&client, name, owner).await?;
             } else {
                 error!("PostgreSQL connection required for postgres::set_database_owner");
                 return Ok(());
             }
         }
-        Commands::Dump { name, output } => {
-            if let Some(_) = client {
+        Commands::Dump { name, output, dump } => {
+            if client.is_some() {
                 info!("Dumping database '{}' to '{}'", name, output);
-                backup::dump_database(
-                    &name,
-                    &output,
+                let started_at = chrono::Utc::now();
+                let result = backup::dump_database(
+                    name,
+                    output,
+                    &dump.into(),
                     &cli.host.clone().unwrap_or_else(|| "localhost".to_string()),
                     cli.port.unwrap_or(5432),
                     cli.username.as_deref(),
                     cli.password.as_deref(),
                     cli.use_ssl,
                 )
-                .await?
+                .await;
+                record_history(history::HistoryEntry {
+                    target: Some(output.clone()),
+                    bytes: std::fs::metadata(output).ok().filter(|m| m.is_file()).map(|m| m.len()),
+                    ..history::HistoryEntry::finished("dump", name, started_at, result.as_ref().map(|_| ()))
+                });
+                result?
             } else {
                 error!("PostgreSQL connection required for postgres::dump_database");
                 return Ok(());
             }
         }
-        Commands::Restore { name, input } => {
-            if let Some(_) = client {
-                backup::restore_database(
-                    &name,
-                    &input,
+        Commands::Backup { name, key, dump, compression, encrypt, encryption_recipient } => {
+            if client.is_some() {
+                let s3_config = s3_config(&cli);
+                if s3_config.bucket.is_empty() {
+                    error!("S3 bucket required for backup::backup_to_s3");
+                    return Ok(());
+                }
+
+                let encryption = match (*encrypt, encryption_recipient) {
+                    (false, _) => None,
+                    (true, Some(recipient)) => Some(crypto::EncryptionRecipient::parse(recipient)?),
+                    (true, None) => match config::load_encryption_key(cli.encryption_key_file.as_deref())? {
+                        Some(key) => Some(key.to_recipient()),
+                        None => anyhow::bail!("--encrypt needs a public key from --encryption-recipient or an identity from --encryption-key-file or BACKUP_ENCRYPTION_KEY"),
+                    },
+                };
+
+                let options: backup::DumpOptions = dump.into();
+                let upload = backup::UploadOptions { compression: *compression, encryption };
+                let key = key.clone().unwrap_or_else(|| {
+                    let file_name = format!("{}-{}{}{}", name, chrono::Utc::now().format("%Y%m%d%H%M%S"), options.format.extension(), upload.suffix());
+                    s3::object_key(&s3_config.prefix, &file_name)
+                });
+                info!("Backing up database '{}' to s3://{}/{}", name, s3_config.bucket, key);
+
+                let s3_client = s3::build_client(&s3_config);
+                let started_at = chrono::Utc::now();
+                let result = backup::backup_to_s3(
+                    &s3_client,
+                    &s3_config.bucket,
+                    &key,
+                    name,
+                    &options,
+                    &upload,
                     &cli.host.clone().unwrap_or_else(|| "localhost".to_string()),
                     cli.port.unwrap_or(5432),
                     cli.username.as_deref(),
                     cli.password.as_deref(),
                     cli.use_ssl,
-                )?
+                )
+                .await;
+                record_history(history::HistoryEntry {
+                    target: Some(key.clone()),
+                    bytes: result.as_ref().ok().map(|manifest| manifest.size),
+                    ..history::HistoryEntry::finished("backup", name, started_at, result.as_ref().map(|_| ()))
+                });
+                let manifest = result?;
+                println!(
+                    "Uploaded s3://{}/{} ({}, sha256 {}) in {:.1}s",
+                    s3_config.bucket,
+                    key,
+                    humansize::format_size(manifest.size, humansize::DECIMAL),
+                    manifest.sha256,
+                    manifest.duration_secs,
+                );
+            } else {
+                error!("PostgreSQL connection required for backup::backup_to_s3");
+                return Ok(());
+            }
+        }
+        Commands::Restore { name, input, mode } => {
+            if let Some(client) = client {
+                let plan = mode.plan(name);
+                info!("Restoring '{}' into database '{}' ({})", input, plan.database, plan.mode);
+                let started_at = chrono::Utc::now();
+                let record = |result: Result<(), &anyhow::Error>| record_history(history::HistoryEntry {
+                    target: Some(plan.target.clone()),
+                    bytes: std::fs::metadata(input).ok().filter(|m| m.is_file()).map(|m| m.len()),
+                    ..history::HistoryEntry::finished("restore", input, started_at, result)
+                });
+                if let Err(e) = plan.prepare(&client).await {
+                    record(Err(&e));
+                    return Err(e);
+                }
+
+                let result = async {
+                    backup::restore_database(
+                        &plan.database,
+                        input,
+                        &cli.host.clone().unwrap_or_else(|| "localhost".to_string()),
+                        cli.port.unwrap_or(5432),
+                        cli.username.as_deref(),
+                        cli.password.as_deref(),
+                        cli.use_ssl,
+                        plan.clean(),
+                    )?;
+
+                    let restored = connect(&cli, Some(&plan.database)).await?
+                        .ok_or_else(|| anyhow::anyhow!("Failed to connect to restored database '{}'", plan.database))?;
+                    plan.validate(&restored, backup::expected_tables(input)?).await?;
+                    drop(restored);
+
+                    plan.finish(&client).await
+                }
+                .await;
+                record(result.as_ref().map(|_| ()));
+
+                if let Err(e) = result {
+                    error!("Restore into '{}' failed: {}", plan.target, e);
+                    plan.rollback(&client).await;
+                    return Err(e);
+                }
+                println!("Restored '{}' into database '{}'", input, plan.target);
             } else {
                 error!("PostgreSQL connection required for postgres::restore_database");
                 return Ok(());
             }
         }
+        Commands::Prune { hourly, daily, weekly, monthly, dry_run } => {
+            let s3_config = s3_config(&cli);
+            if s3_config.bucket.is_empty() {
+                error!("S3 bucket required for s3::delete_objects");
+                return Ok(());
+            }
+
+            let defaults = retention::RetentionPolicy::default();
+            let policy = retention::RetentionPolicy {
+                hourly: hourly.unwrap_or(defaults.hourly),
+                daily: daily.unwrap_or(defaults.daily),
+                weekly: weekly.unwrap_or(defaults.weekly),
+                monthly: monthly.unwrap_or(defaults.monthly),
+            };
+
+            let s3_client = s3::build_client(&s3_config);
+            let snapshots = s3::list_snapshots(&s3_client, &s3_config.bucket, &s3_config.prefix).await?;
+            let plan = retention::plan(&snapshots, &policy)?;
+            info!("Pruning s3://{}/{} keeping {}: {} to delete, {} to keep", s3_config.bucket, s3_config.prefix, policy, plan.delete.len(), plan.keep.len());
+
+            for snapshot in &plan.delete {
+                println!("{} {}", if *dry_run { "Would delete" } else { "Deleting" }, snapshot.key);
+            }
+            let freed = humansize::format_size(plan.delete_size(), humansize::DECIMAL);
+            if *dry_run {
+                println!("Would delete {} snapshots ({}), keeping {}", plan.delete.len(), freed, plan.keep.len());
+            } else {
+                let started_at = chrono::Utc::now();
+                let result = s3::delete_objects(&s3_client, &s3_config.bucket, &plan.delete_keys()).await;
+                let subject = format!("s3://{}/{}", s3_config.bucket, s3_config.prefix);
+                record_history(history::HistoryEntry {
+                    bytes: Some(plan.delete_size()),
+                    message: Some(format!("Deleted {} snapshots", plan.delete.len())),
+                    ..history::HistoryEntry::finished("prune", &subject, started_at, result.as_ref().map(|_| ()))
+                });
+                result?;
+                println!("Deleted {} snapshots ({}), kept {}", plan.delete.len(), freed, plan.keep.len());
+            }
+        }
+        Commands::Snapshots(SnapshotsCommand::List { sort, format, limit, since, latest }) => {
+            let s3_config = s3_config(&cli);
+            if s3_config.bucket.is_empty() {
+                anyhow::bail!("S3 bucket required for s3::list_backups");
+            }
+
+            let s3_client = s3::build_client(&s3_config);
+            let snapshots = s3::list_backups(&s3_client, &s3_config.bucket, &s3_config.prefix).await?;
+            let since = filter::SnapshotFilter { since: *since, ..Default::default() };
+            let mut snapshots: Vec<_> = snapshots.into_iter().filter(|s| since.matches(s)).collect();
+
+            let (sort, limit) = if *latest { (sort::SnapshotSort::default(), Some(1)) } else { (*sort, *limit) };
+            sort.apply(&mut snapshots);
+            if let Some(limit) = limit {
+                snapshots.truncate(limit);
+            }
+            if *latest && snapshots.is_empty() {
+                anyhow::bail!("No snapshots found under s3://{}/{}", s3_config.bucket, s3_config.prefix);
+            }
+
+            print!("{}", output::format_snapshots(&snapshots, *format)?);
+        }
+        Commands::Snapshots(SnapshotsCommand::Restore { key, latest, database, target, mode, stream, progress }) => {
+            let s3_config = s3_config(&cli);
+            if s3_config.bucket.is_empty() {
+                anyhow::bail!("S3 bucket required for snapshots restore");
+            }
+            let s3_client = s3::build_client(&s3_config);
+
+            let snapshot = if *latest {
+                let database = database.as_deref().unwrap_or_default();
+                let snapshots = s3::list_backups(&s3_client, &s3_config.bucket, &s3_config.prefix).await?;
+                filter::latest_of_database(snapshots, database).ok_or_else(|| {
+                    anyhow::anyhow!("No snapshots of database '{}' found under s3://{}/{}", database, s3_config.bucket, s3_config.prefix)
+                })?
+            } else {
+                let key = key.as_deref().unwrap_or_default();
+                s3::list_snapshots(&s3_client, &s3_config.bucket, key).await?
+                    .into_iter()
+                    .find(|s| s.key == key)
+                    .ok_or_else(|| anyhow::anyhow!("Snapshot s3://{}/{} not found", s3_config.bucket, key))?
+            };
+
+            let target = target.clone();
+            let pg_config = ui::models::PostgresConfig {
+                host: Some(cli.host.clone().unwrap_or_else(|| "localhost".to_string())),
+                port: cli.port,
+                username: cli.username.clone(),
+                password: cli.password.clone(),
+                use_ssl: cli.use_ssl,
+                verify_ssl: cli.verify_ssl,
+                root_cert_path: cli.root_cert_path.clone(),
+                db_name: Some(target.clone()),
+            };
+
+            let mut service = service::SnapshotService::new(s3_client, s3_config.bucket.clone(), pg_config);
+            service.download_options = config::load_download_options();
+            service.stream_restore = *stream || config::load_stream_restore();
+            service.encryption_key = config::load_encryption_key(cli.encryption_key_file.as_deref())?;
+
+            info!("Restoring s3://{}/{} ({})", s3_config.bucket, snapshot.key, mode);
+            let key = snapshot.key.clone();
+            let bytes = snapshot.size.max(0) as u64;
+            let started_at = chrono::Utc::now();
+            let request = service::RestoreRequest { snapshot, mode: *mode, target: target.clone() };
+            let (events_tx, mut events) = tokio::sync::mpsc::unbounded_channel();
+            let cancel = cancel::CancelToken::new();
+            let task_cancel = cancel.clone();
+            let task = tokio::spawn(async move { service.restore(&request, &events_tx, &task_cancel).await });
+
+            let mut printer = output::ProgressPrinter::new(*progress);
+            loop {
+                tokio::select! {
+                    event = events.recv() => match event {
+                        Some(event) => printer.print(output::ProgressEvent::from_service(&key, &event))?,
+                        None => break,
+                    },
+                    // Ctrl-C cancels like Esc in the TUI, so a scratch database isn't left behind
+                    _ = tokio::signal::ctrl_c(), if !cancel.is_cancelled() => {
+                        warn!("Interrupted, cancelling restore of {}", key);
+                        cancel.cancel();
+                    },
+                }
+            }
+
+            let result = task.await?;
+            let finished = match &result {
+                Ok(service::RestoreOutcome::Restored { .. }) => history::HistoryEntry::finished("restore", &key, started_at, Ok(())),
+                Ok(service::RestoreOutcome::Cancelled { .. }) => history::HistoryEntry::finished("restore", &key, started_at, Err(&cancel::Cancelled.into())),
+                Err(e) => history::HistoryEntry::finished("restore", &key, started_at, Err(e)),
+            };
+            record_history(history::HistoryEntry { target: Some(target), bytes: Some(bytes), ..finished });
+
+            match result {
+                Ok(service::RestoreOutcome::Restored { target }) => {
+                    printer.print(output::ProgressEvent::finished(true, format!("Restored {} into database '{}'", key, target)))?;
+                }
+                Ok(service::RestoreOutcome::Cancelled { dropped }) => anyhow::bail!("Restore of {} was cancelled, dropped '{}'", key, dropped),
+                Err(e) => {
+                    printer.print(output::ProgressEvent::finished(false, format!("{:#}", e)))?;
+                    return Err(e);
+                }
+            }
+        }
+        Commands::History { limit, format } => {
+            let entries = history::JobHistory::new(config::load_history_path()).load(*limit)?;
+            print!("{}", output::format_history(&entries, *format)?);
+        }
         Commands::BrowseSnapshots => {
             // Use the new UI module to browse snapshots
             let res = ui::run_tui(
@@ -0,0 +1,46 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+/// Key suffix of the JSON manifest written next to each backup
+pub const MANIFEST_SUFFIX: &str = ".manifest.json";
+
+/// Where a snapshot came from and how it was made, stored as a `<key>.manifest.json` sidecar
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SnapshotManifest {
+    /// Server the database was dumped from
+    pub host: String,
+    pub port: u16,
+    pub database: String,
+    /// `server_version` of the source server
+    pub server_version: Option<String>,
+    /// Output of `pg_dump --version`
+    pub pg_dump_version: Option<String>,
+    /// pg_dump output format, e.g. `custom`
+    pub format: String,
+    /// Compression applied on top of the dump, e.g. `zstd`
+    pub compression: String,
+    pub encrypted: bool,
+    /// Hex SHA-256 of the object as stored in S3
+    pub sha256: String,
+    /// Size of the object in bytes
+    pub size: u64,
+    /// RFC 3339 time the backup started
+    pub started_at: String,
+    pub duration_secs: f64,
+}
+
+impl SnapshotManifest {
+    /// Key of the manifest for the snapshot at `key`
+    pub fn key(snapshot_key: &str) -> String {
+        format!("{}{}", snapshot_key, MANIFEST_SUFFIX)
+    }
+
+    pub fn to_json(&self) -> Result<String> {
+        serde_json::to_string_pretty(self).context("Failed to serialize manifest")
+    }
+
+    pub fn from_json(json: &str) -> Result<Self> {
+        serde_json::from_str(json).context("Invalid snapshot manifest")
+    }
+}
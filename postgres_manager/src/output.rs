@@ -0,0 +1,223 @@
+use anyhow::Result;
+use chrono::{DateTime, SecondsFormat, Utc};
+use serde::Serialize;
+use std::time::{Duration, Instant};
+
+use crate::history::HistoryEntry;
+use crate::service::ServiceEvent;
+use crate::ui::models::BackupMetadata;
+
+/// How non-interactive commands print their results
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum OutputFormat {
+    #[default]
+    Table,
+    Json,
+    Csv,
+}
+
+/// A snapshot as printed by `snapshots list`
+#[derive(Debug, Serialize)]
+struct SnapshotRow<'a> {
+    key: &'a str,
+    size: i64,
+    last_modified: String,
+}
+
+impl<'a> From<&'a BackupMetadata> for SnapshotRow<'a> {
+    fn from(snapshot: &'a BackupMetadata) -> Self {
+        let modified: DateTime<Utc> = DateTime::from_timestamp(snapshot.last_modified.secs(), 0).unwrap_or_default();
+        Self {
+            key: &snapshot.key,
+            size: snapshot.size,
+            last_modified: modified.to_rfc3339_opts(SecondsFormat::Secs, true),
+        }
+    }
+}
+
+/// Render `snapshots` in `format`, one snapshot per line for the table and CSV
+pub fn format_snapshots(snapshots: &[BackupMetadata], format: OutputFormat) -> Result<String> {
+    let rows: Vec<SnapshotRow> = snapshots.iter().map(SnapshotRow::from).collect();
+
+    Ok(match format {
+        OutputFormat::Table => {
+            let width = rows.iter().map(|r| r.key.len()).max().unwrap_or(0).max("KEY".len());
+            let mut out = format!("{:<width$}  {:>10}  {}\n", "KEY", "SIZE", "LAST MODIFIED");
+            for (row, snapshot) in rows.iter().zip(snapshots) {
+                let size = humansize::format_size(snapshot.size.max(0) as u64, humansize::DECIMAL);
+                out.push_str(&format!("{:<width$}  {:>10}  {}\n", row.key, size, row.last_modified));
+            }
+            out
+        }
+        OutputFormat::Json => serde_json::to_string_pretty(&rows)? + "\n",
+        OutputFormat::Csv => {
+            let mut out = String::from("key,size,last_modified\n");
+            for row in &rows {
+                out.push_str(&format!("{},{},{}\n", csv_field(row.key), row.size, row.last_modified));
+            }
+            out
+        }
+    })
+}
+
+/// Render job history `entries` in `format`, one job per line for the table and CSV
+pub fn format_history(entries: &[HistoryEntry], format: OutputFormat) -> Result<String> {
+    let size = |entry: &HistoryEntry| entry.bytes.map(|b| humansize::format_size(b, humansize::DECIMAL)).unwrap_or_default();
+
+    Ok(match format {
+        OutputFormat::Table => {
+            let mut out = format!("{:<20}  {:<8}  {:<9}  {:>10}  {:>8}  {}\n", "FINISHED", "KIND", "STATE", "SIZE", "DURATION", "SUBJECT");
+            for entry in entries {
+                let mut subject = entry.subject.clone();
+                if let Some(target) = &entry.target {
+                    subject.push_str(&format!(" -> {}", target));
+                }
+                if let Some(error) = &entry.error {
+                    subject.push_str(&format!(": {}", error));
+                }
+                let duration = format!("{:.1}s", entry.duration_secs);
+                out.push_str(&format!("{:<20}  {:<8}  {:<9}  {:>10}  {:>8}  {}\n", entry.finished_at, entry.kind, entry.state, size(entry), duration, subject));
+            }
+            out
+        }
+        OutputFormat::Json => serde_json::to_string_pretty(entries)? + "\n",
+        OutputFormat::Csv => {
+            let mut out = String::from("finished_at,kind,state,subject,target,bytes,duration_secs,error\n");
+            for entry in entries {
+                out.push_str(&format!(
+                    "{},{},{},{},{},{},{},{}\n",
+                    entry.finished_at,
+                    entry.kind,
+                    entry.state,
+                    csv_field(&entry.subject),
+                    csv_field(entry.target.as_deref().unwrap_or_default()),
+                    entry.bytes.map(|b| b.to_string()).unwrap_or_default(),
+                    entry.duration_secs,
+                    csv_field(entry.error.as_deref().unwrap_or_default()),
+                ));
+            }
+            out
+        }
+    })
+}
+
+/// Quote a CSV field if it contains a delimiter, quote or line break
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+/// How headless commands print progress
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum ProgressFormat {
+    /// One human readable line per step
+    #[default]
+    Text,
+    /// One JSON object per line
+    Json,
+}
+
+/// A step of an operation as printed by `ProgressPrinter`
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct ProgressEvent {
+    pub time: String,
+    /// download, verify, decode, restore, success or error
+    pub stage: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub key: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub progress: Option<f32>,
+    /// Bytes per second
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rate: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
+}
+
+impl ProgressEvent {
+    fn new(stage: &'static str) -> Self {
+        Self {
+            time: Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true),
+            stage,
+            key: None,
+            progress: None,
+            rate: None,
+            message: None,
+        }
+    }
+
+    /// The step a service event reports for the snapshot `key`
+    pub fn from_service(key: &str, event: &ServiceEvent) -> Self {
+        let (stage, progress, rate, message) = match event {
+            ServiceEvent::Downloading(p) => ("download", Some(p.fraction()), Some(p.rate), None),
+            ServiceEvent::Downloaded(path) => ("download", Some(1.0), None, Some(path.display().to_string())),
+            ServiceEvent::Verifying => ("verify", None, None, None),
+            ServiceEvent::Restoring { fraction, current, .. } => ("restore", Some(*fraction), None, current.clone()),
+        };
+        Self { key: Some(key.to_string()), progress, rate, message, ..Self::new(stage) }
+    }
+
+    /// The final success or error line of an operation
+    pub fn finished(success: bool, message: String) -> Self {
+        Self { message: Some(message), ..Self::new(if success { "success" } else { "error" }) }
+    }
+
+    /// Whether this event only updates the progress of the same step as `other`
+    fn same_step(&self, other: &Self) -> bool {
+        self.stage == other.stage && self.key == other.key && self.message == other.message
+    }
+}
+
+impl std::fmt::Display for ProgressEvent {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} {}", self.time, self.stage)?;
+        if let Some(key) = &self.key {
+            write!(f, " {}", key)?;
+        }
+        if let Some(progress) = self.progress {
+            write!(f, " {:.0}%", progress * 100.0)?;
+        }
+        if let Some(rate) = self.rate {
+            write!(f, " {}/s", humansize::format_size(rate as u64, humansize::DECIMAL))?;
+        }
+        if let Some(message) = &self.message {
+            write!(f, ": {}", message)?;
+        }
+        Ok(())
+    }
+}
+
+/// Progress of the same step is printed at most this often
+const PROGRESS_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Prints progress events to stdout, leaving out updates that come faster than `PROGRESS_INTERVAL`
+#[derive(Debug)]
+pub struct ProgressPrinter {
+    format: ProgressFormat,
+    last: Option<(ProgressEvent, Instant)>,
+}
+
+impl ProgressPrinter {
+    pub fn new(format: ProgressFormat) -> Self {
+        Self { format, last: None }
+    }
+
+    pub fn print(&mut self, event: ProgressEvent) -> Result<()> {
+        if let Some((last, at)) = &self.last {
+            let finished = event.progress.is_some_and(|p| p >= 1.0) && last.progress.is_none_or(|p| p < 1.0);
+            if event.same_step(last) && !finished && (at.elapsed() < PROGRESS_INTERVAL || event.progress == last.progress) {
+                return Ok(());
+            }
+        }
+
+        match self.format {
+            ProgressFormat::Text => println!("{}", event),
+            ProgressFormat::Json => println!("{}", serde_json::to_string(&event)?),
+        }
+        self.last = Some((event, Instant::now()));
+        Ok(())
+    }
+}
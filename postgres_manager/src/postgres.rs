@@ -1,4 +1,4 @@
-use anyhow::{Context, Result};
+use anyhow::{bail, Context, Result};
 
 use tokio_postgres::Config as PgConfig;
 
@@ -6,6 +6,29 @@ use log::{error, info};
 use native_tls::TlsConnector;
 use postgres_native_tls::MakeTlsConnector;
 
+/// Longest identifier PostgreSQL keeps without truncating (NAMEDATALEN - 1 bytes)
+pub const MAX_IDENTIFIER_LEN: usize = 63;
+
+/// Check that `name` can be used as a PostgreSQL identifier without being rejected or silently truncated
+pub fn validate_identifier(name: &str) -> Result<()> {
+  if name.is_empty() {
+      bail!("Identifier must not be empty");
+  }
+  if name.contains('\0') {
+      bail!("Identifier '{}' must not contain NUL characters", name.escape_debug());
+  }
+  if name.len() > MAX_IDENTIFIER_LEN {
+      bail!("Identifier '{}' is longer than {} bytes", name, MAX_IDENTIFIER_LEN);
+  }
+  Ok(())
+}
+
+/// Validate `name` and quote it for use in SQL, doubling embedded quotes like `quote_ident` does
+pub fn quote_identifier(name: &str) -> Result<String> {
+  validate_identifier(name)?;
+  Ok(format!("\"{}\"", name.replace('"', "\"\"")))
+}
+
 pub async fn connect_ssl(config: &PgConfig, verify: bool, root_cert_path: Option<&str>) -> Result<tokio_postgres::Client> {
   let mut builder = TlsConnector::builder();
   if !verify {
@@ -56,9 +79,44 @@ pub async fn list_databases(client: &tokio_postgres::Client) -> Result<()> {
   Ok(())
 }
 
+/// A database with what the TUI database panel shows about it
+#[derive(Clone, Debug, PartialEq)]
+pub struct DatabaseInfo {
+  pub name: String,
+  pub owner: String,
+  /// On-disk size in bytes, `None` if we may not connect to the database to measure it
+  pub size: Option<i64>,
+  /// Other sessions currently connected to the database
+  pub connections: i64,
+}
+
+/// List every non-template database with its owner, size and the number of other sessions connected to it
+pub async fn database_info(client: &tokio_postgres::Client) -> Result<Vec<DatabaseInfo>> {
+  let rows = client
+      .query(
+          "SELECT d.datname, pg_catalog.pg_get_userbyid(d.datdba), \
+           CASE WHEN has_database_privilege(d.oid, 'CONNECT') THEN pg_catalog.pg_database_size(d.oid) END, \
+           (SELECT count(*) FROM pg_catalog.pg_stat_activity a WHERE a.datid = d.oid AND a.pid <> pg_backend_pid()) \
+           FROM pg_catalog.pg_database d WHERE NOT d.datistemplate ORDER BY d.datname;",
+          &[],
+      )
+      .await
+      .context("Failed to list databases")?;
+
+  Ok(rows
+      .iter()
+      .map(|row| DatabaseInfo { name: row.get(0), owner: row.get(1), size: row.get(2), connections: row.get(3) })
+      .collect())
+}
+
+/// Name `clone_database` gives the copy of `name`
+pub fn clone_name(name: &str) -> String {
+  format!("{}-clone", name)
+}
+
 pub async fn create_database(client: &tokio_postgres::Client, name: &str) -> Result<()> {
   client
-      .execute(&format!("CREATE DATABASE \"{}\";", name), &[])
+      .execute(&format!("CREATE DATABASE {};", quote_identifier(name)?), &[])
       .await
       .context("Failed to create database")?;
 
@@ -66,10 +124,25 @@ pub async fn create_database(client: &tokio_postgres::Client, name: &str) -> Res
   Ok(())
 }
 
+/// Statement copying `name` to `clone_name(name)`, owned by `owner` rather than by whoever runs it
+pub fn clone_database_sql(name: &str, owner: &str) -> Result<String> {
+  Ok(format!(
+      "CREATE DATABASE {} WITH TEMPLATE {} OWNER {};",
+      quote_identifier(&clone_name(name))?, quote_identifier(name)?, quote_identifier(owner)?,
+  ))
+}
+
+/// Copy `name` to `clone_name(name)`, keeping its owner. Fails while other sessions are connected to `name`.
 pub async fn clone_database(client: &tokio_postgres::Client, name: &str) -> Result<()> {
-  let new_name = format!("{}-clone", name);
+  let new_name = clone_name(name);
+  let owner: String = client
+      .query_opt("SELECT pg_catalog.pg_get_userbyid(datdba) FROM pg_catalog.pg_database WHERE datname = $1;", &[&name])
+      .await
+      .context("Failed to look up database owner")?
+      .ok_or_else(|| anyhow::anyhow!("Database '{}' does not exist", name))?
+      .get(0);
   client
-      .execute(&format!("CREATE DATABASE \"{}\" WITH TEMPLATE \"{}\" OWNER \"{}\" ;", new_name, name, name), &[])
+      .execute(&clone_database_sql(name, &owner)?, &[])
       .await
       .context("Failed to clone database")?;
 
@@ -79,7 +152,7 @@ pub async fn clone_database(client: &tokio_postgres::Client, name: &str) -> Resu
 
 pub async fn drop_database(client: &tokio_postgres::Client, name: &str) -> Result<()> {
   client
-      .execute(&format!("DROP DATABASE \"{}\" WITH (FORCE);", name), &[])
+      .execute(&format!("DROP DATABASE {} WITH (FORCE);", quote_identifier(name)?), &[])
       .await
       .context("Failed to drop database")?;
 
@@ -89,7 +162,7 @@ pub async fn drop_database(client: &tokio_postgres::Client, name: &str) -> Resul
 
 pub async fn drop_database_with_force(client: &tokio_postgres::Client, name: &str) -> Result<()> {
   client
-      .execute(&format!("DROP DATABASE \"{}\" WITH (FORCE);", name), &[])
+      .execute(&format!("DROP DATABASE {} WITH (FORCE);", quote_identifier(name)?), &[])
       .await
       .context("Failed to drop database")?;
 
@@ -99,7 +172,7 @@ pub async fn drop_database_with_force(client: &tokio_postgres::Client, name: &st
 
 pub async fn rename_database(client: &tokio_postgres::Client, old_name: &str, new_name: &str) -> Result<()> {
   client
-      .execute(&format!("ALTER DATABASE \"{}\" RENAME TO \"{}\";", old_name, new_name), &[])
+      .execute(&format!("ALTER DATABASE {} RENAME TO {};", quote_identifier(old_name)?, quote_identifier(new_name)?), &[])
       .await
       .context("Failed to rename database")?;
 
@@ -109,10 +182,123 @@ pub async fn rename_database(client: &tokio_postgres::Client, old_name: &str, ne
 
 pub async fn set_database_owner(client: &tokio_postgres::Client, name: &str, owner: &str) -> Result<()> {
   client
-      .execute(&format!("ALTER DATABASE \"{}\" OWNER TO \"{}\";", name, owner), &[])
+      .execute(&format!("ALTER DATABASE {} OWNER TO {};", quote_identifier(name)?, quote_identifier(owner)?), &[])
       .await
       .context("Failed to set database owner")?;
 
   info!("Database '{}' owner set to '{}' successfully", name, owner);
   Ok(())
 }
+
+pub async fn database_exists(client: &tokio_postgres::Client, name: &str) -> Result<bool> {
+  let row = client
+      .query_one("SELECT EXISTS (SELECT 1 FROM pg_database WHERE datname = $1);", &[&name])
+      .await
+      .context("Failed to check whether database exists")?;
+
+  Ok(row.get(0))
+}
+
+/// Terminate every other session connected to `name`. Returns the number of sessions signalled.
+pub async fn terminate_connections(client: &tokio_postgres::Client, name: &str) -> Result<u64> {
+  let rows = client
+      .query(
+          "SELECT pg_terminate_backend(pid) FROM pg_stat_activity WHERE datname = $1 AND pid <> pg_backend_pid();",
+          &[&name],
+      )
+      .await
+      .context("Failed to terminate connections")?;
+
+  Ok(rows.len() as u64)
+}
+
+pub async fn set_allow_connections(client: &tokio_postgres::Client, name: &str, allow: bool) -> Result<()> {
+  client
+      .execute(&format!("ALTER DATABASE {} WITH ALLOW_CONNECTIONS {};", quote_identifier(name)?, allow), &[])
+      .await
+      .context("Failed to change whether database allows connections")?;
+
+  Ok(())
+}
+
+/// Keep terminating sessions on `names` until none are left, giving up after a few seconds
+async fn disconnect_all(client: &tokio_postgres::Client, names: &[&str]) -> Result<()> {
+  for _ in 0..50 {
+      let mut remaining = 0;
+      for name in names {
+          remaining += terminate_connections(client, name).await?;
+      }
+      if remaining == 0 {
+          return Ok(());
+      }
+      tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+  }
+
+  bail!("Timed out waiting for sessions on {} to disconnect", names.join(", "))
+}
+
+/// Rename `live` to `previous` and `replacement` to `live` in a single transaction. If `previous` already
+/// exists, pass `retired` to have it renamed out of the way in the same transaction.
+/// New connections to `live` are refused and existing sessions on the databases involved are terminated first;
+/// if the swap fails, `live` accepts connections again and every database keeps its name.
+pub async fn swap_databases(
+  client: &tokio_postgres::Client,
+  live: &str,
+  replacement: &str,
+  previous: &str,
+  retired: Option<&str>,
+) -> Result<()> {
+  let retire_sql = match retired {
+      Some(retired) => format!("ALTER DATABASE {} RENAME TO {}; ", quote_identifier(previous)?, quote_identifier(retired)?),
+      None => String::new(),
+  };
+  let swap_sql = format!(
+      "BEGIN; {retire_sql}ALTER DATABASE {live} RENAME TO {previous}; ALTER DATABASE {replacement} RENAME TO {live}; COMMIT;",
+      live = quote_identifier(live)?,
+      replacement = quote_identifier(replacement)?,
+      previous = quote_identifier(previous)?,
+  );
+
+  set_allow_connections(client, live, false).await?;
+
+  let mut names = vec![live, replacement];
+  if retired.is_some() {
+      names.push(previous);
+  }
+  let result = match disconnect_all(client, &names).await {
+      Ok(_) => client.batch_execute(&swap_sql).await.context("Failed to swap databases"),
+      Err(e) => Err(e),
+  };
+
+  if let Err(e) = result {
+      error!("Swapping '{}' in as '{}' failed, keeping the live database: {}", replacement, live, e);
+      // Roll back an aborted transaction so the client is usable again
+      let _ = client.batch_execute("ROLLBACK;").await;
+      if let Err(allow_err) = set_allow_connections(client, live, true).await {
+          error!("Failed to re-enable connections to '{}': {}", live, allow_err);
+      }
+      return Err(e);
+  }
+
+  set_allow_connections(client, previous, true).await?;
+
+  info!("Database '{}' swapped in as '{}', previous database kept as '{}'", replacement, live, previous);
+  Ok(())
+}
+
+/// Count the tables outside the system schemas of the database `client` is connected to
+pub async fn count_user_tables(client: &tokio_postgres::Client) -> Result<i64> {
+  let row = client
+      .query_one(
+          "SELECT count(*) FROM pg_catalog.pg_class c \
+           JOIN pg_catalog.pg_namespace n ON n.oid = c.relnamespace \
+           WHERE c.relkind IN ('r', 'p') \
+           AND n.nspname NOT IN ('pg_catalog', 'information_schema') \
+           AND n.nspname NOT LIKE 'pg_toast%';",
+          &[],
+      )
+      .await
+      .context("Failed to count tables")?;
+
+  Ok(row.get(0))
+}
@@ -0,0 +1,144 @@
+use anyhow::{bail, Result};
+use aws_sdk_s3::primitives::DateTime as AwsDateTime;
+use chrono::{DateTime, Utc};
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, HashSet};
+
+use crate::s3::sidecar_owner;
+use crate::ui::models::BackupMetadata;
+
+/// Grandfather-father-son retention: how many hourly, daily, weekly and monthly snapshots to keep
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RetentionPolicy {
+    pub hourly: usize,
+    pub daily: usize,
+    pub weekly: usize,
+    pub monthly: usize,
+}
+
+impl Default for RetentionPolicy {
+    fn default() -> Self {
+        Self { hourly: 24, daily: 7, weekly: 4, monthly: 12 }
+    }
+}
+
+impl RetentionPolicy {
+    /// Whether the policy would delete every snapshot
+    pub fn keeps_nothing(&self) -> bool {
+        self.hourly == 0 && self.daily == 0 && self.weekly == 0 && self.monthly == 0
+    }
+}
+
+impl std::fmt::Display for RetentionPolicy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} hourly, {} daily, {} weekly, {} monthly", self.hourly, self.daily, self.weekly, self.monthly)
+    }
+}
+
+/// Snapshots a retention policy keeps and deletes, newest first
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct PrunePlan {
+    pub keep: Vec<BackupMetadata>,
+    pub delete: Vec<BackupMetadata>,
+    /// Checksum and manifest sidecars of deleted snapshots, deleted along with them
+    pub sidecars: Vec<String>,
+}
+
+impl PrunePlan {
+    /// Every object key the plan deletes
+    pub fn delete_keys(&self) -> Vec<String> {
+        self.delete.iter().map(|s| s.key.clone()).chain(self.sidecars.iter().cloned()).collect()
+    }
+
+    /// Total size of the deleted snapshots in bytes
+    pub fn delete_size(&self) -> u64 {
+        self.delete.iter().map(|s| s.size.max(0) as u64).sum()
+    }
+}
+
+/// Name of the backup series a snapshot belongs to. Keys written by `backup` look like
+/// `<prefix>/<name>-<YYYYmmddHHMMSS>.<ext>`, so that's everything before the timestamp; other keys are
+/// a series of their own.
+pub fn snapshot_series(key: &str) -> &str {
+    match key.rsplit_once('-') {
+        Some((series, rest)) if rest.bytes().take(14).filter(u8::is_ascii_digit).count() == 14 => series,
+        _ => key,
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Period {
+    Hour,
+    Day,
+    Week,
+    Month,
+}
+
+impl Period {
+    fn bucket(self, time: &AwsDateTime) -> String {
+        let time: DateTime<Utc> = DateTime::from_timestamp(time.secs(), time.subsec_nanos()).unwrap_or_default();
+        let format = match self {
+            Period::Hour => "%Y-%m-%d %H",
+            Period::Day => "%Y-%m-%d",
+            Period::Week => "%G-W%V",
+            Period::Month => "%Y-%m",
+        };
+        time.format(format).to_string()
+    }
+}
+
+/// Apply `policy` to each backup series in `snapshots` separately. For every period type the newest
+/// snapshot of each of the latest N periods that have one is kept; a snapshot kept for any reason is not
+/// deleted. Sidecars aren't snapshots themselves and go with the snapshot they belong to.
+pub fn plan(snapshots: &[BackupMetadata], policy: &RetentionPolicy) -> Result<PrunePlan> {
+    if policy.keeps_nothing() {
+        bail!("Retention policy keeps no snapshots, keep at least one hourly, daily, weekly or monthly snapshot");
+    }
+
+    let mut series: BTreeMap<&str, Vec<&BackupMetadata>> = BTreeMap::new();
+    for snapshot in snapshots.iter().filter(|s| sidecar_owner(&s.key).is_none()) {
+        series.entry(snapshot_series(&snapshot.key)).or_default().push(snapshot);
+    }
+
+    let mut kept = HashSet::new();
+    for group in series.values_mut() {
+        // Newest first, with the key breaking ties so backups written in the same second are handled consistently
+        group.sort_by(|a, b| b.last_modified.cmp(&a.last_modified).then_with(|| b.key.cmp(&a.key)));
+        for (period, count) in [
+            (Period::Hour, policy.hourly),
+            (Period::Day, policy.daily),
+            (Period::Week, policy.weekly),
+            (Period::Month, policy.monthly),
+        ] {
+            let mut periods = HashSet::new();
+            for snapshot in group.iter() {
+                if periods.len() == count {
+                    break;
+                }
+                if periods.insert(period.bucket(&snapshot.last_modified)) {
+                    kept.insert(snapshot.key.as_str());
+                }
+            }
+        }
+    }
+
+    let mut plan = PrunePlan::default();
+    for snapshot in snapshots.iter().filter(|s| sidecar_owner(&s.key).is_none()) {
+        if kept.contains(snapshot.key.as_str()) {
+            plan.keep.push(snapshot.clone());
+        } else {
+            plan.delete.push(snapshot.clone());
+        }
+    }
+    plan.keep.sort_by_key(|s| Reverse(s.last_modified));
+    plan.delete.sort_by_key(|s| Reverse(s.last_modified));
+
+    let deleted: HashSet<&str> = plan.delete.iter().map(|s| s.key.as_str()).collect();
+    plan.sidecars = snapshots
+        .iter()
+        .filter(|s| sidecar_owner(&s.key).is_some_and(|key| deleted.contains(key)))
+        .map(|s| s.key.clone())
+        .collect();
+
+    Ok(plan)
+}
@@ -0,0 +1,1017 @@
+use anyhow::{anyhow, Context, Result};
+use aws_sdk_s3::{Client as S3Client, config::Credentials};
+use aws_sdk_s3::primitives::ByteStream;
+use aws_sdk_s3::types::{ChecksumMode, CompletedMultipartUpload, CompletedPart, Delete, ObjectIdentifier};
+use bytes::Bytes;
+use futures_util::StreamExt;
+use log::{debug, info, warn};
+use sha2::{Digest, Sha256};
+use std::collections::HashSet;
+use std::io::{Read, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
+
+use crate::cancel::{CancelToken, Cancelled};
+use crate::checksum::{ObjectChecksums, StreamDigests, SIDECAR_SUFFIX};
+use crate::compression::COMPRESSION_METADATA;
+use crate::manifest::{SnapshotManifest, MANIFEST_SUFFIX};
+use crate::ui::models::{BackupMetadata, S3Config};
+
+/// Size of each multipart upload part. S3 requires at least 5 MiB for every part but the last.
+pub const PART_SIZE: usize = 8 * 1024 * 1024;
+
+/// `Content-Type` of uploaded snapshots
+pub const SNAPSHOT_CONTENT_TYPE: &str = "application/octet-stream";
+
+/// Build an S3 client from the given configuration
+pub fn build_client(config: &S3Config) -> S3Client {
+    let credentials = Credentials::new(
+        &config.access_key_id,
+        &config.secret_access_key,
+        None, None, "postgres-manager"
+    );
+
+    let mut config_builder = aws_sdk_s3::config::Builder::new()
+        .credentials_provider(credentials)
+        .region(aws_sdk_s3::config::Region::new(config.region.clone()));
+
+    if !config.endpoint_url.is_empty() {
+        let endpoint_url = if !config.endpoint_url.starts_with("http") {
+            format!("http://{}", config.endpoint_url)
+        } else {
+            config.endpoint_url.clone()
+        };
+
+        config_builder = config_builder.endpoint_url(endpoint_url);
+    }
+
+    if config.path_style {
+        config_builder = config_builder.force_path_style(true);
+    }
+
+    // Add behavior version which is required by AWS SDK
+    config_builder = config_builder.behavior_version(aws_sdk_s3::config::BehaviorVersion::latest());
+
+    S3Client::from_conf(config_builder.build())
+}
+
+/// Join the configured prefix and a file name into an object key
+pub fn object_key(prefix: &str, name: &str) -> String {
+    if prefix.is_empty() || prefix.ends_with('/') {
+        format!("{}{}", prefix, name)
+    } else {
+        format!("{}/{}", prefix, name)
+    }
+}
+
+/// A single page of a snapshot listing
+#[derive(Debug, Default)]
+pub struct SnapshotPage {
+    pub snapshots: Vec<BackupMetadata>,
+    /// Token for the next page, or `None` if this was the last page
+    pub next_token: Option<String>,
+}
+
+/// List one page of snapshots under `prefix`, continuing from `continuation_token` if given
+pub async fn list_snapshots_page(
+    client: &S3Client,
+    bucket: &str,
+    prefix: &str,
+    continuation_token: Option<String>,
+) -> Result<SnapshotPage> {
+    let mut list_objects_builder = client.list_objects_v2()
+        .bucket(bucket)
+        .set_continuation_token(continuation_token);
+
+    if !prefix.is_empty() {
+        list_objects_builder = list_objects_builder.prefix(prefix);
+    }
+
+    let resp = list_objects_builder.send().await
+        .map_err(|e| anyhow!("Failed to list objects: {}", e))?;
+
+    let snapshots = resp.contents()
+        .iter()
+        .filter_map(|obj| match (obj.key(), obj.size(), obj.last_modified()) {
+            (Some(key), Some(size), Some(last_modified)) => Some(BackupMetadata {
+                key: key.to_string(),
+                size,
+                last_modified: *last_modified,
+            }),
+            _ => None,
+        })
+        .collect();
+
+    let next_token = if resp.is_truncated().unwrap_or(false) {
+        resp.next_continuation_token().map(|s| s.to_string())
+    } else {
+        None
+    };
+
+    Ok(SnapshotPage { snapshots, next_token })
+}
+
+/// List every snapshot under `prefix`, following continuation tokens until the listing is complete
+pub async fn list_snapshots(client: &S3Client, bucket: &str, prefix: &str) -> Result<Vec<BackupMetadata>> {
+    let mut snapshots = Vec::new();
+    let mut token = None;
+
+    loop {
+        let page = list_snapshots_page(client, bucket, prefix, token).await?;
+        snapshots.extend(page.snapshots);
+        debug!("Listed {} snapshots so far", snapshots.len());
+
+        match page.next_token {
+            Some(next) => token = Some(next),
+            None => break,
+        }
+    }
+
+    Ok(snapshots)
+}
+
+/// List every snapshot under `prefix` like `list_snapshots`, leaving out checksum and manifest sidecars
+pub async fn list_backups(client: &S3Client, bucket: &str, prefix: &str) -> Result<Vec<BackupMetadata>> {
+    let snapshots = list_snapshots(client, bucket, prefix).await?;
+    Ok(snapshots.into_iter().filter(|s| sidecar_owner(&s.key).is_none()).collect())
+}
+
+/// List one page of snapshots like `list_snapshots_page`, leaving out checksum and manifest sidecars.
+/// A page may come back empty while there are more to load.
+pub async fn list_backups_page(
+    client: &S3Client,
+    bucket: &str,
+    prefix: &str,
+    continuation_token: Option<String>,
+) -> Result<SnapshotPage> {
+    let mut page = list_snapshots_page(client, bucket, prefix, continuation_token).await?;
+    page.snapshots.retain(|s| sidecar_owner(&s.key).is_none());
+    Ok(page)
+}
+
+/// Most keys S3 accepts in a single DeleteObjects request
+const DELETE_BATCH_SIZE: usize = 1000;
+
+/// Delete `keys` with as few DeleteObjects requests as possible. Returns the number of objects deleted.
+pub async fn delete_objects(client: &S3Client, bucket: &str, keys: &[String]) -> Result<usize> {
+    let mut deleted = 0;
+
+    for batch in keys.chunks(DELETE_BATCH_SIZE) {
+        let objects = batch
+            .iter()
+            .map(|key| ObjectIdentifier::builder().key(key).build())
+            .collect::<Result<Vec<_>, _>>()?;
+        let delete = Delete::builder().set_objects(Some(objects)).quiet(true).build()?;
+
+        let resp = client.delete_objects()
+            .bucket(bucket)
+            .delete(delete)
+            .send()
+            .await
+            .map_err(|e| anyhow!("Failed to delete objects: {}", e))?;
+
+        // Quiet mode only reports the keys that could not be deleted
+        let errors = resp.errors();
+        deleted += batch.len() - errors.len();
+        if let Some(error) = errors.first() {
+            return Err(anyhow!(
+                "Failed to delete {} of {} objects, first error on {}: {}",
+                errors.len(),
+                batch.len(),
+                error.key().unwrap_or_default(),
+                error.message().or(error.code()).unwrap_or("unknown error"),
+            ));
+        }
+        info!("Deleted {} objects from {}", deleted, bucket);
+    }
+
+    Ok(deleted)
+}
+
+/// Suffixes of the objects stored next to a snapshot
+pub const SIDECAR_SUFFIXES: [&str; 2] = [SIDECAR_SUFFIX, MANIFEST_SUFFIX];
+
+/// Key of the snapshot a sidecar object belongs to, or `None` if `key` is not a sidecar
+pub fn sidecar_owner(key: &str) -> Option<&str> {
+    SIDECAR_SUFFIXES.iter().find_map(|suffix| key.strip_suffix(suffix))
+}
+
+/// Keys the sidecars of the snapshot at `key` would have
+pub fn sidecar_keys(key: &str) -> Vec<String> {
+    SIDECAR_SUFFIXES.iter().map(|suffix| format!("{}{}", key, suffix)).collect()
+}
+
+/// Largest object a single CopyObject request can copy
+const MAX_COPY_SIZE: u64 = 5 * 1024 * 1024 * 1024;
+
+/// Size of the parts larger objects are copied in
+const COPY_PART_SIZE: u64 = 512 * 1024 * 1024;
+
+/// Characters escaped in a copy source, everything but unreserved characters and the path separator
+const COPY_SOURCE: &percent_encoding::AsciiSet = &percent_encoding::NON_ALPHANUMERIC
+    .remove(b'/')
+    .remove(b'-')
+    .remove(b'_')
+    .remove(b'.')
+    .remove(b'~');
+
+/// URL-encoded `bucket/key`, as CopyObject and UploadPartCopy expect
+pub fn copy_source(bucket: &str, key: &str) -> String {
+    format!("{}/{}", bucket, percent_encoding::utf8_percent_encode(key, COPY_SOURCE))
+}
+
+/// Whether an object exists
+pub async fn object_exists(client: &S3Client, bucket: &str, key: &str) -> Result<bool> {
+    match client.head_object().bucket(bucket).key(key).send().await {
+        Ok(_) => Ok(true),
+        Err(e) if e.as_service_error().is_some_and(|e| e.is_not_found()) => Ok(false),
+        Err(e) => Err(anyhow!("Failed to read metadata of {}: {}", key, e)),
+    }
+}
+
+/// Copy `from` to `to` within the bucket, keeping the object's metadata. Objects too large for a single
+/// CopyObject request are copied part by part.
+pub async fn copy_object(client: &S3Client, bucket: &str, from: &str, to: &str) -> Result<()> {
+    let head = client.head_object()
+        .bucket(bucket)
+        .key(from)
+        .send()
+        .await
+        .map_err(|e| anyhow!("Failed to read metadata of {}: {}", from, e))?;
+    let size = head.content_length().unwrap_or(0).max(0) as u64;
+    let source = copy_source(bucket, from);
+
+    if size <= MAX_COPY_SIZE {
+        client.copy_object()
+            .bucket(bucket)
+            .key(to)
+            .copy_source(&source)
+            .send()
+            .await
+            .map_err(|e| anyhow!("Failed to copy {} to {}: {}", from, to, e))?;
+        info!("Copied {} to {}", from, to);
+        return Ok(());
+    }
+
+    let metadata: Vec<(&str, &str)> = head.metadata()
+        .map(|m| m.iter().map(|(k, v)| (k.as_str(), v.as_str())).collect())
+        .unwrap_or_default();
+    let mut upload = MultipartUpload::start_with_metadata(client, bucket, to, &metadata).await?;
+
+    let mut first = 0;
+    while first < size {
+        let last = (first + COPY_PART_SIZE).min(size) - 1;
+        if let Err(e) = upload.copy_part(&source, first, last).await {
+            upload.abort().await;
+            return Err(e.context(format!("Failed to copy {} to {}", from, to)));
+        }
+        first = last + 1;
+    }
+    upload.complete().await?;
+    info!("Copied {} to {} in parts", from, to);
+    Ok(())
+}
+
+/// A writer that has to be told when the input ends, e.g. to write an encryption or compression trailer
+pub trait FinishWrite: Write + Send {
+    fn finish(self: Box<Self>) -> std::io::Result<()>;
+}
+
+/// Collects the output of a `FinishWrite` chain for `MultipartUpload::write_through` to upload
+#[derive(Clone, Default)]
+struct PartWriter(Arc<std::sync::Mutex<Vec<u8>>>);
+
+impl PartWriter {
+    /// Take up to `PART_SIZE` bytes once at least `min` are buffered
+    fn take(&self, min: usize) -> Option<Vec<u8>> {
+        let mut buffer = self.0.lock().unwrap();
+        if buffer.is_empty() || buffer.len() < min {
+            return None;
+        }
+        let len = buffer.len().min(PART_SIZE);
+        Some(buffer.drain(..len).collect())
+    }
+}
+
+impl Write for PartWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+impl FinishWrite for PartWriter {
+    fn finish(self: Box<Self>) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// An in-progress S3 multipart upload
+pub struct MultipartUpload {
+    client: S3Client,
+    bucket: String,
+    key: String,
+    upload_id: String,
+    parts: Vec<CompletedPart>,
+    /// Digest of the data sent with `upload_part`
+    sha256: Sha256,
+}
+
+impl MultipartUpload {
+    /// Start a new multipart upload for `bucket`/`key`
+    pub async fn start(client: &S3Client, bucket: &str, key: &str) -> Result<Self> {
+        Self::start_with_metadata(client, bucket, key, &[]).await
+    }
+
+    /// Start a new multipart upload for `bucket`/`key`, storing `metadata` with the object. Snapshots are
+    /// stored as opaque bytes, whatever their compression.
+    pub async fn start_with_metadata(client: &S3Client, bucket: &str, key: &str, metadata: &[(&str, &str)]) -> Result<Self> {
+        let resp = client.create_multipart_upload()
+            .bucket(bucket)
+            .key(key)
+            .content_type(SNAPSHOT_CONTENT_TYPE)
+            .set_metadata((!metadata.is_empty()).then(|| {
+                metadata.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect()
+            }))
+            .send()
+            .await
+            .context("Failed to create multipart upload")?;
+
+        let upload_id = resp.upload_id()
+            .ok_or_else(|| anyhow!("S3 did not return an upload id"))?
+            .to_string();
+        debug!("Started multipart upload {} for {}", upload_id, key);
+
+        Ok(Self {
+            client: client.clone(),
+            bucket: bucket.to_string(),
+            key: key.to_string(),
+            upload_id,
+            parts: Vec::new(),
+            sha256: Sha256::new(),
+        })
+    }
+
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    /// Hex SHA-256 of the data uploaded so far, which is the object's once every part is uploaded
+    pub fn sha256(&self) -> String {
+        hex::encode(self.sha256.clone().finalize())
+    }
+
+    /// Upload a single part
+    pub async fn upload_part(&mut self, data: Vec<u8>) -> Result<()> {
+        let part_number = self.parts.len() as i32 + 1;
+        self.sha256.update(&data);
+        let resp = self.client.upload_part()
+            .bucket(&self.bucket)
+            .key(&self.key)
+            .upload_id(&self.upload_id)
+            .part_number(part_number)
+            .body(ByteStream::from(data))
+            .send()
+            .await
+            .with_context(|| format!("Failed to upload part {}", part_number))?;
+
+        self.parts.push(
+            CompletedPart::builder()
+                .set_e_tag(resp.e_tag().map(|s| s.to_string()))
+                .part_number(part_number)
+                .build(),
+        );
+        Ok(())
+    }
+
+    /// Read `reader` to the end, uploading it in `PART_SIZE` parts. Returns the number of bytes uploaded.
+    pub async fn write_from<R: AsyncRead + Unpin>(&mut self, reader: &mut R) -> Result<u64> {
+        let mut total: u64 = 0;
+        loop {
+            let mut buf = Vec::with_capacity(PART_SIZE);
+            while buf.len() < PART_SIZE {
+                let n = (&mut *reader)
+                    .take((PART_SIZE - buf.len()) as u64)
+                    .read_to_end(&mut buf)
+                    .await?;
+                if n == 0 {
+                    break;
+                }
+            }
+
+            if buf.is_empty() {
+                break;
+            }
+
+            let len = buf.len();
+            self.upload_part(buf).await?;
+            total += len as u64;
+            debug!("Uploaded part {} ({} bytes total)", self.parts.len(), total);
+
+            if len < PART_SIZE {
+                break;
+            }
+        }
+
+        Ok(total)
+    }
+
+    /// Like `write_from`, but passes the data through the writer `wrap` builds (encryption, compression)
+    /// on its way to S3. Returns the number of bytes uploaded.
+    pub async fn write_through<R, F>(&mut self, reader: &mut R, wrap: F) -> Result<u64>
+    where
+        R: AsyncRead + Unpin,
+        F: FnOnce(Box<dyn FinishWrite>) -> Result<Box<dyn FinishWrite>>,
+    {
+        let parts = PartWriter::default();
+        let mut writer = wrap(Box::new(parts.clone()))?;
+        let mut total: u64 = 0;
+        let mut buf = vec![0u8; 64 * 1024];
+
+        loop {
+            let n = reader.read(&mut buf).await?;
+            if n == 0 {
+                break;
+            }
+            writer.write_all(&buf[..n])?;
+
+            while let Some(part) = parts.take(PART_SIZE) {
+                total += part.len() as u64;
+                self.upload_part(part).await?;
+            }
+        }
+
+        writer.finish()?;
+        while let Some(part) = parts.take(1) {
+            total += part.len() as u64;
+            self.upload_part(part).await?;
+        }
+        debug!("Uploaded {} parts ({} bytes total)", self.parts.len(), total);
+
+        Ok(total)
+    }
+
+    /// Copy bytes `first..=last` of the object at `source`, as built by `copy_source`, in as the next part
+    pub async fn copy_part(&mut self, source: &str, first: u64, last: u64) -> Result<()> {
+        let part_number = self.parts.len() as i32 + 1;
+        let resp = self.client.upload_part_copy()
+            .bucket(&self.bucket)
+            .key(&self.key)
+            .upload_id(&self.upload_id)
+            .part_number(part_number)
+            .copy_source(source)
+            .copy_source_range(format!("bytes={}-{}", first, last))
+            .send()
+            .await
+            .with_context(|| format!("Failed to copy part {}", part_number))?;
+
+        self.parts.push(
+            CompletedPart::builder()
+                .set_e_tag(resp.copy_part_result().and_then(|r| r.e_tag()).map(|s| s.to_string()))
+                .part_number(part_number)
+                .build(),
+        );
+        Ok(())
+    }
+
+    /// Complete the upload, making the object visible in the bucket
+    pub async fn complete(mut self) -> Result<()> {
+        // S3 rejects a multipart upload without parts, so send an empty one for empty input
+        if self.parts.is_empty() {
+            self.upload_part(Vec::new()).await?;
+        }
+
+        self.client.complete_multipart_upload()
+            .bucket(&self.bucket)
+            .key(&self.key)
+            .upload_id(&self.upload_id)
+            .multipart_upload(
+                CompletedMultipartUpload::builder()
+                    .set_parts(Some(self.parts))
+                    .build(),
+            )
+            .send()
+            .await
+            .context("Failed to complete multipart upload")?;
+
+        info!("Completed multipart upload of {}", self.key);
+        Ok(())
+    }
+
+    /// Abort the upload and discard any parts already sent
+    pub async fn abort(self) {
+        if let Err(e) = self.client.abort_multipart_upload()
+            .bucket(&self.bucket)
+            .key(&self.key)
+            .upload_id(&self.upload_id)
+            .send()
+            .await
+        {
+            warn!("Failed to abort multipart upload {}: {}", self.upload_id, e);
+        }
+    }
+}
+
+/// Tuning for ranged snapshot downloads
+#[derive(Clone, Debug, PartialEq)]
+pub struct DownloadOptions {
+    /// Bytes fetched per range request
+    pub chunk_size: u64,
+    /// Number of ranges fetched at the same time
+    pub concurrency: usize,
+    /// Times a failed range is retried before the download fails, on top of the first attempt
+    pub max_retries: u32,
+}
+
+impl Default for DownloadOptions {
+    fn default() -> Self {
+        Self {
+            chunk_size: 16 * 1024 * 1024,
+            concurrency: 1,
+            max_retries: 5,
+        }
+    }
+}
+
+/// Progress of a transfer
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct TransferProgress {
+    pub transferred: u64,
+    pub total: u64,
+    /// Bytes per second
+    pub rate: f64,
+}
+
+impl TransferProgress {
+    /// Fraction of the transfer done so far, between 0.0 and 1.0
+    pub fn fraction(&self) -> f32 {
+        if self.total == 0 {
+            1.0
+        } else {
+            (self.transferred as f64 / self.total as f64).min(1.0) as f32
+        }
+    }
+}
+
+/// Path of the file recording which chunks of a download at `file` are on disk
+fn progress_path(file: &Path) -> PathBuf {
+    let mut path = file.as_os_str().to_owned();
+    path.push(".progress");
+    PathBuf::from(path)
+}
+
+/// Remove a downloaded snapshot along with the progress file of a partial download of it
+pub async fn remove_download(path: &Path) {
+    for file in [path.to_path_buf(), progress_path(path)] {
+        match tokio::fs::remove_file(&file).await {
+            Ok(()) => debug!("Removed {}", file.display()),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => warn!("Failed to remove {}: {}", file.display(), e),
+        }
+    }
+}
+
+/// How long to wait before retrying a range whose attempt number `attempt` (counting from 0) failed,
+/// or `None` once `max_retries` retries have been used up
+pub fn retry_delay(attempt: u32, max_retries: u32) -> Option<Duration> {
+    if attempt >= max_retries {
+        return None;
+    }
+    Some(Duration::from_millis(500u64.saturating_mul(2u64.saturating_pow(attempt))).min(Duration::from_secs(30)))
+}
+
+/// Records which chunks of a download are on disk, in a `<file>.progress` file next to it,
+/// so an interrupted download can pick up where it stopped
+struct ResumeState {
+    path: PathBuf,
+    header: String,
+    done: HashSet<u64>,
+}
+
+impl ResumeState {
+    async fn load(file: &Path, header: String) -> Self {
+        let path = progress_path(file);
+
+        let mut done = HashSet::new();
+        if let Ok(contents) = tokio::fs::read_to_string(&path).await {
+            let mut lines = contents.lines();
+            // Progress recorded for a different object version or chunk size can't be reused
+            if lines.next() == Some(header.as_str()) {
+                done = lines.filter_map(|l| l.parse().ok()).collect();
+            }
+        }
+
+        Self { path, header, done }
+    }
+
+    async fn reset(&mut self) -> Result<()> {
+        self.done.clear();
+        tokio::fs::write(&self.path, format!("{}\n", self.header)).await?;
+        Ok(())
+    }
+
+    async fn mark(&mut self, chunk: u64) -> Result<()> {
+        let mut file = tokio::fs::OpenOptions::new().append(true).open(&self.path).await?;
+        file.write_all(format!("{}\n", chunk).as_bytes()).await?;
+        self.done.insert(chunk);
+        Ok(())
+    }
+
+    async fn finish(self) {
+        let _ = tokio::fs::remove_file(&self.path).await;
+    }
+}
+
+/// Local file in `dir` to download `key` to. The name only depends on the key, so a download that was
+/// interrupted resumes from the same file.
+pub fn download_path(dir: &Path, key: &str) -> PathBuf {
+    let name: String = key
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | '_') { c } else { '_' })
+        .collect();
+    dir.join(format!("pg-backup-{}", name))
+}
+
+/// Fetch bytes `start..=end` of an object into the same offsets of `path`
+#[allow(clippy::too_many_arguments)]
+async fn download_range(
+    client: &S3Client,
+    bucket: &str,
+    key: &str,
+    etag: Option<&str>,
+    path: &Path,
+    start: u64,
+    end: u64,
+    transferred: &AtomicU64,
+    written: &mut u64,
+    cancel: &CancelToken,
+) -> Result<()> {
+    let resp = client.get_object()
+        .bucket(bucket)
+        .key(key)
+        .range(format!("bytes={}-{}", start, end))
+        // Fail rather than stitch together two versions if the object is replaced mid-download
+        .set_if_match(etag.map(|e| e.to_string()))
+        .send()
+        .await
+        .map_err(|e| anyhow!("Failed to download bytes {}-{}: {}", start, end, e))?;
+
+    let mut file = tokio::fs::OpenOptions::new().write(true).open(path).await?;
+    file.seek(SeekFrom::Start(start)).await?;
+
+    let mut body = resp.body;
+    while let Some(chunk) = body.try_next().await? {
+        if cancel.is_cancelled() {
+            return Err(Cancelled.into());
+        }
+        file.write_all(&chunk).await?;
+        *written += chunk.len() as u64;
+        transferred.fetch_add(chunk.len() as u64, Ordering::Relaxed);
+    }
+    file.flush().await?;
+
+    if *written != end - start + 1 {
+        anyhow::bail!("Expected {} bytes for range {}-{}, got {}", end - start + 1, start, end, written);
+    }
+
+    Ok(())
+}
+
+/// Download one range, retrying with exponential backoff
+#[allow(clippy::too_many_arguments)]
+async fn download_range_with_retry(
+    client: &S3Client,
+    bucket: &str,
+    key: &str,
+    etag: Option<&str>,
+    path: &Path,
+    start: u64,
+    end: u64,
+    transferred: &AtomicU64,
+    options: &DownloadOptions,
+    cancel: &CancelToken,
+) -> Result<()> {
+    let mut attempt = 0;
+    loop {
+        let mut written = 0;
+        match download_range(client, bucket, key, etag, path, start, end, transferred, &mut written, cancel).await {
+            Ok(()) => return Ok(()),
+            Err(e) => {
+                // The range is fetched again from the start, so its bytes no longer count
+                transferred.fetch_sub(written, Ordering::Relaxed);
+
+                let backoff = match retry_delay(attempt, options.max_retries) {
+                    Some(backoff) if !e.is::<Cancelled>() => backoff,
+                    _ => return Err(e),
+                };
+                attempt += 1;
+                warn!("Range {}-{} of {} failed, retry {}/{} in {:?}: {}",
+                    start, end, key, attempt, options.max_retries, backoff, e);
+                tokio::time::sleep(backoff).await;
+            }
+        }
+    }
+}
+
+/// Download `bucket`/`key` to `path` with HTTP range requests.
+/// Chunks already on disk from an earlier, interrupted attempt are not fetched again; failed ranges are
+/// retried with backoff, and up to `options.concurrency` ranges are fetched in parallel.
+/// `on_progress` is called periodically. Returns the size of the object.
+pub async fn download_object<F: FnMut(TransferProgress)>(
+    client: &S3Client,
+    bucket: &str,
+    key: &str,
+    path: &Path,
+    options: &DownloadOptions,
+    cancel: &CancelToken,
+    mut on_progress: F,
+) -> Result<u64> {
+    let head = client.head_object()
+        .bucket(bucket)
+        .key(key)
+        .send()
+        .await
+        .map_err(|e| anyhow!("Failed to read metadata of {}: {}", key, e))?;
+
+    let total = head.content_length().unwrap_or(0).max(0) as u64;
+    let etag = head.e_tag().map(|e| e.to_string());
+    let chunk_size = options.chunk_size.max(1);
+    let chunk_count = total.div_ceil(chunk_size);
+
+    let header = format!("{} {} {}", etag.as_deref().unwrap_or("-"), total, chunk_size);
+    let mut state = ResumeState::load(path, header).await;
+    if state.done.is_empty() {
+        // Without a matching progress file whatever is on disk can't be trusted
+        state.reset().await?;
+        tokio::fs::File::create(path).await?;
+    } else {
+        info!("Resuming download of {} with {}/{} chunks on disk", key, state.done.len(), chunk_count);
+    }
+    tokio::fs::OpenOptions::new().write(true).open(path).await?.set_len(total).await?;
+
+    let range = |chunk: u64| (chunk * chunk_size, ((chunk + 1) * chunk_size).min(total) - 1);
+    let already = state.done.iter().map(|&c| { let (start, end) = range(c); end - start + 1 }).sum();
+    let transferred = AtomicU64::new(already);
+
+    let pending: Vec<u64> = (0..chunk_count).filter(|c| !state.done.contains(c)).collect();
+    let mut downloads = futures_util::stream::iter(pending.into_iter().map(|chunk| {
+        let (start, end) = range(chunk);
+        let (etag, transferred) = (etag.as_deref(), &transferred);
+        async move {
+            download_range_with_retry(client, bucket, key, etag, path, start, end, transferred, options, cancel)
+                .await
+                .map(|_| chunk)
+        }
+    }))
+    .buffer_unordered(options.concurrency.max(1));
+
+    let mut ticker = tokio::time::interval(Duration::from_millis(100));
+    let mut rate_sample = (Instant::now(), already);
+    let mut rate = 0.0;
+    loop {
+        tokio::select! {
+            next = downloads.next() => match next {
+                Some(Ok(chunk)) => state.mark(chunk).await?,
+                Some(Err(e)) => return Err(e),
+                None => break,
+            },
+            _ = ticker.tick() => {
+                if cancel.is_cancelled() {
+                    return Err(Cancelled.into());
+                }
+
+                let done = transferred.load(Ordering::Relaxed);
+                let elapsed = rate_sample.0.elapsed().as_secs_f64();
+                if elapsed >= 0.5 {
+                    rate = done.saturating_sub(rate_sample.1) as f64 / elapsed;
+                    rate_sample = (Instant::now(), done);
+                }
+                on_progress(TransferProgress { transferred: done, total, rate });
+            },
+        }
+    }
+
+    on_progress(TransferProgress { transferred: total, total, rate });
+    state.finish().await;
+    info!("Downloaded {} ({} bytes) to {}", key, total, path.display());
+
+    Ok(total)
+}
+
+/// Collect the checksums S3 has for an object: its ETag, any additional SHA-256/CRC32C checksums,
+/// and the digest from a `<key>.sha256` sidecar object if there is one
+pub async fn object_checksums(client: &S3Client, bucket: &str, key: &str) -> Result<ObjectChecksums> {
+    let head = client.head_object()
+        .bucket(bucket)
+        .key(key)
+        .checksum_mode(ChecksumMode::Enabled)
+        .send()
+        .await
+        .map_err(|e| anyhow!("Failed to read metadata of {}: {}", key, e))?;
+
+    let mut checksums = ObjectChecksums {
+        etag: head.e_tag().map(|v| v.to_string()),
+        sha256: head.checksum_sha256().map(|v| v.to_string()),
+        crc32c: head.checksum_crc32_c().map(|v| v.to_string()),
+        ..Default::default()
+    };
+
+    if checksums.needs_part_size() {
+        // Every part but the last has the size of the first
+        let first_part = client.head_object()
+            .bucket(bucket)
+            .key(key)
+            .part_number(1)
+            .send()
+            .await
+            .map_err(|e| anyhow!("Failed to read first part of {}: {}", key, e))?;
+        checksums.part_size = first_part.content_length().map(|l| l.max(0) as u64);
+    }
+
+    let sidecar_key = format!("{}{}", key, SIDECAR_SUFFIX);
+    match client.get_object().bucket(bucket).key(&sidecar_key).send().await {
+        Ok(resp) => {
+            let body = resp.body.collect().await
+                .map_err(|e| anyhow!("Failed to read {}: {}", sidecar_key, e))?;
+            checksums.sidecar_sha256 = Some(String::from_utf8_lossy(&body.into_bytes()).trim().to_string());
+        }
+        Err(e) if e.as_service_error().is_some_and(|e| e.is_no_such_key()) => {
+            debug!("No checksum sidecar {} for {}", sidecar_key, key);
+        }
+        // Without ListBucket permission a missing object is reported as access denied
+        Err(e) => warn!("Could not read checksum sidecar {}: {}", sidecar_key, e),
+    }
+
+    // Backups made by this tool record their SHA-256 in the manifest instead
+    if checksums.sidecar_sha256.is_none() {
+        match read_manifest(client, bucket, key).await {
+            Ok(manifest) => {
+                checksums.sidecar_sha256 = manifest.map(|m| m.sha256).filter(|sha256| !sha256.is_empty());
+            }
+            Err(e) => warn!("Could not read manifest of {}: {}", key, e),
+        }
+    }
+
+    Ok(checksums)
+}
+
+/// Store the manifest of the snapshot at `key` as its sidecar
+pub async fn write_manifest(client: &S3Client, bucket: &str, key: &str, manifest: &SnapshotManifest) -> Result<()> {
+    let manifest_key = SnapshotManifest::key(key);
+    client.put_object()
+        .bucket(bucket)
+        .key(&manifest_key)
+        .content_type("application/json")
+        .body(ByteStream::from(manifest.to_json()?.into_bytes()))
+        .send()
+        .await
+        .map_err(|e| anyhow!("Failed to write {}: {}", manifest_key, e))?;
+    debug!("Wrote manifest {}", manifest_key);
+    Ok(())
+}
+
+/// Read the manifest of the snapshot at `key`, if it has one
+pub async fn read_manifest(client: &S3Client, bucket: &str, key: &str) -> Result<Option<SnapshotManifest>> {
+    let manifest_key = SnapshotManifest::key(key);
+    match client.get_object().bucket(bucket).key(&manifest_key).send().await {
+        Ok(resp) => {
+            let body = resp.body.collect().await
+                .map_err(|e| anyhow!("Failed to read {}: {}", manifest_key, e))?;
+            SnapshotManifest::from_json(&String::from_utf8_lossy(&body.into_bytes())).map(Some)
+        }
+        Err(e) if e.as_service_error().is_some_and(|e| e.is_no_such_key()) => Ok(None),
+        Err(e) => Err(anyhow!("Failed to read {}: {}", manifest_key, e)),
+    }
+}
+
+/// Read the first `len` bytes of an object, e.g. to detect the format of a dump without downloading it
+pub async fn read_object_head(client: &S3Client, bucket: &str, key: &str, len: u64) -> Result<Vec<u8>> {
+    let resp = client.get_object()
+        .bucket(bucket)
+        .key(key)
+        .range(format!("bytes=0-{}", len.max(1) - 1))
+        .send()
+        .await
+        .map_err(|e| anyhow!("Failed to read start of {}: {}", key, e))?;
+
+    let body = resp.body.collect().await
+        .map_err(|e| anyhow!("Failed to read start of {}: {}", key, e))?;
+    Ok(body.into_bytes().to_vec())
+}
+
+/// Blocking reader over an object's body, for handing it to a child process from a blocking thread.
+/// The body is fetched on the async runtime and passed over a small channel, so only a few chunks are
+/// held in memory at a time.
+pub struct ObjectReader {
+    rx: tokio::sync::mpsc::Receiver<std::io::Result<Bytes>>,
+    chunk: Bytes,
+    size: u64,
+    compression: Option<String>,
+    consumed: Arc<AtomicU64>,
+    digests: Arc<Mutex<StreamDigests>>,
+}
+
+impl ObjectReader {
+    /// Size of the object
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    /// `compression` metadata stored with the object
+    pub fn compression(&self) -> Option<&str> {
+        self.compression.as_deref()
+    }
+
+    /// Counter of the bytes read so far, which stays readable after the reader is moved to another thread
+    pub fn consumed(&self) -> Arc<AtomicU64> {
+        Arc::clone(&self.consumed)
+    }
+
+    /// Digests of the bytes read so far, to verify the object against once it has been read to the end
+    pub fn digests(&self) -> Arc<Mutex<StreamDigests>> {
+        Arc::clone(&self.digests)
+    }
+}
+
+impl Read for ObjectReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        while self.chunk.is_empty() {
+            match self.rx.blocking_recv() {
+                Some(Ok(chunk)) => self.chunk = chunk,
+                Some(Err(e)) => return Err(e),
+                None => return Ok(0),
+            }
+        }
+
+        let n = buf.len().min(self.chunk.len());
+        buf[..n].copy_from_slice(&self.chunk.split_to(n));
+        self.digests.lock().unwrap().update(&buf[..n]);
+        self.consumed.fetch_add(n as u64, Ordering::Relaxed);
+        Ok(n)
+    }
+}
+
+/// Start streaming `bucket`/`key`. Must be called from within the tokio runtime, while the returned
+/// reader must only be read from outside of it, e.g. in `spawn_blocking`. `part_size` splits the reader's
+/// digests into parts to check multipart checksums, see `ObjectChecksums::part_size`.
+pub async fn open_object(client: &S3Client, bucket: &str, key: &str, part_size: Option<u64>) -> Result<ObjectReader> {
+    let resp = client.get_object()
+        .bucket(bucket)
+        .key(key)
+        .send()
+        .await
+        .map_err(|e| anyhow!("Failed to open {}: {}", key, e))?;
+
+    let size = resp.content_length().unwrap_or(0).max(0) as u64;
+    let compression = resp.metadata().and_then(|m| m.get(COMPRESSION_METADATA)).cloned();
+    let (tx, rx) = tokio::sync::mpsc::channel(8);
+    let mut body = resp.body;
+    let key = key.to_string();
+    tokio::spawn(async move {
+        loop {
+            let next = match body.try_next().await {
+                Ok(Some(chunk)) => Ok(chunk),
+                Ok(None) => break,
+                Err(e) => Err(std::io::Error::other(format!("Failed to read {}: {}", key, e))),
+            };
+            let failed = next.is_err();
+            // The reader is gone once the restore exits, so there is no one left to stream to
+            if tx.send(next).await.is_err() || failed {
+                break;
+            }
+        }
+    });
+
+    Ok(ObjectReader {
+        rx,
+        chunk: Bytes::new(),
+        size,
+        compression,
+        consumed: Arc::new(AtomicU64::new(0)),
+        digests: Arc::new(Mutex::new(StreamDigests::new(part_size))),
+    })
+}
+
+/// Whether a snapshot is encrypted, judging by its key suffix or the metadata stored with it
+pub async fn object_encrypted(client: &S3Client, bucket: &str, key: &str) -> Result<bool> {
+    if crate::crypto::is_encrypted_key(key) {
+        return Ok(true);
+    }
+
+    let head = client.head_object()
+        .bucket(bucket)
+        .key(key)
+        .send()
+        .await
+        .map_err(|e| anyhow!("Failed to read metadata of {}: {}", key, e))?;
+
+    let (name, value) = crate::crypto::ENCRYPTION_METADATA;
+    Ok(head.metadata().and_then(|m| m.get(name)).is_some_and(|v| v == value))
+}
@@ -0,0 +1,505 @@
+use anyhow::{anyhow, bail, Context, Result};
+use aws_sdk_s3::Client as S3Client;
+use log::{info, warn};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::mpsc::UnboundedSender;
+use tokio_postgres::Config as PgConfig;
+
+use crate::backup::{self, DumpFormat, RestoreMode, RestorePlan};
+use crate::cancel::{CancelToken, Cancelled};
+use crate::checksum::{self, ObjectChecksums, StreamDigests};
+use crate::compression::{self, Compression};
+use crate::crypto::{self, EncryptionKey};
+use crate::postgres;
+use crate::retention::PrunePlan;
+use crate::s3::{self, DownloadOptions, TransferProgress};
+use crate::toc::RestoreProgress;
+use crate::ui::models::{BackupMetadata, KeyAction, PostgresConfig};
+
+/// Bytes of a snapshot read to decide whether it can be streamed. A custom archive's table of contents
+/// has to fit in them for its data offsets to be checked.
+const STREAM_PROBE_LEN: u64 = 1024 * 1024;
+
+/// Progress reported by `SnapshotService` while it works
+#[derive(Clone, Debug, PartialEq)]
+pub enum ServiceEvent {
+    Downloading(TransferProgress),
+    Downloaded(PathBuf),
+    Verifying,
+    /// Share of the restore done and the object being restored. Only restores into a database created
+    /// for the purpose can be cancelled.
+    Restoring { fraction: f32, current: Option<String>, cancellable: bool },
+}
+
+/// What to restore and where
+#[derive(Clone, Debug, PartialEq)]
+pub struct RestoreRequest {
+    pub snapshot: BackupMetadata,
+    pub mode: RestoreMode,
+    /// Database that should hold the snapshot afterwards
+    pub target: String,
+}
+
+/// How a restore that didn't fail ended
+#[derive(Clone, Debug, PartialEq)]
+pub enum RestoreOutcome {
+    Restored { target: String },
+    /// Cancelled during the restore, after which the database it was writing into was dropped
+    Cancelled { dropped: String },
+}
+
+/// Where a restore reads a snapshot from
+enum SnapshotSource {
+    /// A snapshot downloaded to local disk
+    File(PathBuf),
+    /// An encrypted or compressed snapshot downloaded to local disk, decoded as the restore reads it
+    Encoded(PathBuf),
+    /// A snapshot streamed from S3 into the restore, with the checksums to verify it against once it is read
+    Stream(s3::ObjectReader, ObjectChecksums),
+}
+
+/// Reader counting the bytes read through it, to report how far into a downloaded snapshot a decoding restore is
+struct CountingReader<R> {
+    inner: R,
+    consumed: Arc<AtomicU64>,
+}
+
+impl<R: Read> Read for CountingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.consumed.fetch_add(n as u64, Ordering::Relaxed);
+        Ok(n)
+    }
+}
+
+/// Snapshot operations against S3 and PostgreSQL, independent of any UI. Long-running operations report
+/// progress as `ServiceEvent`s and stop early when their `CancelToken` is cancelled.
+#[derive(Clone, Debug)]
+pub struct SnapshotService {
+    client: S3Client,
+    bucket: String,
+    pg_config: PostgresConfig,
+    pub download_options: DownloadOptions,
+    /// Pipe snapshots straight from S3 into the restore instead of downloading them first
+    pub stream_restore: bool,
+    pub encryption_key: Option<EncryptionKey>,
+    /// Directory downloads are written to
+    pub download_dir: PathBuf,
+}
+
+/// Build a connection config from PostgreSQL settings, optionally for a specific database
+pub fn pg_connection_config(config: &PostgresConfig, dbname: Option<&str>) -> PgConfig {
+    let mut pg = PgConfig::new();
+    pg.host(config.host.clone().unwrap_or_default());
+    pg.port(config.port.unwrap_or(5432));
+    pg.user(config.username.clone().unwrap_or_default());
+    pg.password(config.password.clone().unwrap_or_default());
+    if let Some(dbname) = dbname {
+        pg.dbname(dbname);
+    }
+    pg
+}
+
+/// Connect with the SSL settings from `config`
+pub async fn connect_pg(config: &PostgresConfig, pg: &PgConfig) -> Result<tokio_postgres::Client> {
+    if config.use_ssl {
+        postgres::connect_ssl(pg, config.verify_ssl, config.root_cert_path.as_deref()).await
+    } else {
+        postgres::connect_no_ssl(pg).await
+    }
+}
+
+/// Check that the PostgreSQL settings a restore needs are there
+pub fn validate_pg_config(config: &PostgresConfig) -> Result<()> {
+    if config.host.as_deref().is_none_or(str::is_empty) {
+        bail!("PostgreSQL host is required");
+    }
+    if config.port.is_none() {
+        bail!("PostgreSQL port is required");
+    }
+    if config.username.as_deref().is_none_or(str::is_empty) {
+        bail!("PostgreSQL username is required");
+    }
+    Ok(())
+}
+
+impl SnapshotService {
+    pub fn new(client: S3Client, bucket: String, pg_config: PostgresConfig) -> Self {
+        Self {
+            client,
+            bucket,
+            pg_config,
+            download_options: DownloadOptions::default(),
+            stream_restore: false,
+            encryption_key: None,
+            download_dir: std::env::temp_dir(),
+        }
+    }
+
+    /// Delete a snapshot along with its sidecars
+    pub async fn delete_snapshot(&self, snapshot: &BackupMetadata) -> Result<()> {
+        // Deleting a sidecar that doesn't exist is not an error
+        let mut keys = vec![snapshot.key.clone()];
+        keys.extend(s3::sidecar_keys(&snapshot.key));
+        s3::delete_objects(&self.client, &self.bucket, &keys).await?;
+        info!("Deleted snapshot {}", snapshot.key);
+        Ok(())
+    }
+
+    /// Delete the snapshots and sidecars a prune plan doesn't keep
+    pub async fn prune(&self, plan: &PrunePlan) -> Result<()> {
+        s3::delete_objects(&self.client, &self.bucket, &plan.delete_keys()).await?;
+        info!("Pruned {} snapshots", plan.delete.len());
+        Ok(())
+    }
+
+    /// Copy a snapshot along with its sidecars to `new_key`, removing the original for a rename
+    pub async fn copy_snapshot(&self, snapshot: &BackupMetadata, new_key: &str, action: KeyAction) -> Result<()> {
+        if new_key.is_empty() || new_key == snapshot.key {
+            bail!("Enter a new key for {}", snapshot.key);
+        }
+        // Restores decode snapshots by their suffix, so it has to stay what the object holds
+        if crypto::is_encrypted_key(new_key) != crypto::is_encrypted_key(&snapshot.key)
+            || Compression::detect(new_key, None) != Compression::detect(&snapshot.key, None)
+        {
+            bail!("{} must keep the .age/.zst/.gz suffix of {}", new_key, snapshot.key);
+        }
+        let (client, bucket) = (&self.client, self.bucket.as_str());
+        if s3::object_exists(client, bucket, new_key).await? {
+            bail!("{} already exists", new_key);
+        }
+
+        s3::copy_object(client, bucket, &snapshot.key, new_key).await?;
+        let mut copied = vec![snapshot.key.clone()];
+        for suffix in s3::SIDECAR_SUFFIXES {
+            let sidecar = format!("{}{}", snapshot.key, suffix);
+            if s3::object_exists(client, bucket, &sidecar).await? {
+                s3::copy_object(client, bucket, &sidecar, &format!("{}{}", new_key, suffix)).await?;
+                copied.push(sidecar);
+            }
+        }
+
+        if action == KeyAction::Rename {
+            s3::delete_objects(client, bucket, &copied).await?;
+        }
+        info!("{} {} to {}", action, snapshot.key, new_key);
+        Ok(())
+    }
+
+    /// Check up front that an encrypted snapshot can be decrypted, before anything is downloaded
+    pub async fn check_decryptable(&self, snapshot: &BackupMetadata) -> Result<()> {
+        if self.encryption_key.is_some() {
+            return Ok(());
+        }
+        match s3::object_encrypted(&self.client, &self.bucket, &snapshot.key).await {
+            Ok(true) => Err(crypto::missing_key()),
+            Ok(false) => Ok(()),
+            Err(e) => {
+                warn!("Could not check whether {} is encrypted: {}", snapshot.key, e);
+                Ok(())
+            }
+        }
+    }
+
+    /// Whether a snapshot can be piped straight into the restore, judging by its first `STREAM_PROBE_LEN` bytes.
+    /// Anything that can't be checked is downloaded first instead.
+    pub async fn snapshot_streamable(&self, snapshot: &BackupMetadata) -> bool {
+        let head = match s3::read_object_head(&self.client, &self.bucket, &snapshot.key, STREAM_PROBE_LEN).await {
+            Ok(head) => head,
+            Err(e) => {
+                warn!("Could not detect format of {}, downloading it first: {}", snapshot.key, e);
+                return false;
+            }
+        };
+
+        // The dump inside an encrypted or compressed snapshot is what decides
+        let key = self.encryption_key.clone();
+        let hint = Compression::detect(&snapshot.key, None);
+        let decoded = tokio::task::spawn_blocking(move || compression::decode_head(head, key.as_ref(), hint)).await;
+        match decoded.map_err(anyhow::Error::from).and_then(|dump| dump) {
+            Ok(dump) => {
+                let streamable = DumpFormat::streamable_dump(&dump);
+                if !streamable {
+                    info!("{} can't be restored from a stream, downloading it first", snapshot.key);
+                }
+                streamable
+            }
+            Err(e) => {
+                warn!("Could not decode start of {}, downloading it first: {}", snapshot.key, e);
+                false
+            }
+        }
+    }
+
+    /// Download a snapshot into `download_dir`, resuming an earlier partial download of it.
+    /// The partial file is kept when the download is cancelled or fails.
+    pub async fn download_snapshot(&self, snapshot: &BackupMetadata, events: &UnboundedSender<ServiceEvent>, cancel: &CancelToken) -> Result<PathBuf> {
+        // Download to a path derived from the key so an interrupted download can resume
+        let path = s3::download_path(&self.download_dir, &snapshot.key);
+        info!("Downloading {} to {}", snapshot.key, path.display());
+
+        let _ = events.send(ServiceEvent::Downloading(TransferProgress::default()));
+        s3::download_object(&self.client, &self.bucket, &snapshot.key, &path, &self.download_options, cancel, |progress| {
+            let _ = events.send(ServiceEvent::Downloading(progress));
+        })
+        .await
+        .context("Failed to download backup")?;
+
+        info!("Download completed successfully: {}", path.display());
+        let _ = events.send(ServiceEvent::Downloaded(path.clone()));
+        Ok(path)
+    }
+
+    /// Check a downloaded snapshot against the checksums S3 has for it, returning the ones that were checked.
+    /// On a mismatch the file is removed, so it is neither restored nor resumed from.
+    pub async fn verify_snapshot(&self, snapshot: &BackupMetadata, path: &Path, events: &UnboundedSender<ServiceEvent>) -> Result<Vec<&'static str>> {
+        let _ = events.send(ServiceEvent::Verifying);
+        let checksums = s3::object_checksums(&self.client, &self.bucket, &snapshot.key)
+            .await
+            .context("Could not verify backup")?;
+
+        let file = path.to_path_buf();
+        match tokio::task::spawn_blocking(move || checksum::verify_file(&file, &checksums)).await? {
+            Ok(verified) if verified.is_empty() => {
+                warn!("No usable checksums for {}, restoring unverified", snapshot.key);
+                Ok(verified)
+            }
+            Ok(verified) => {
+                info!("Verified {} ({})", snapshot.key, verified.join(", "));
+                Ok(verified)
+            }
+            Err(e) => {
+                s3::remove_download(path).await;
+                Err(e.context("Downloaded backup is corrupt"))
+            }
+        }
+    }
+
+    /// Restore a snapshot, streaming it if that's enabled and possible and downloading and verifying it first otherwise
+    pub async fn restore(&self, request: &RestoreRequest, events: &UnboundedSender<ServiceEvent>, cancel: &CancelToken) -> Result<RestoreOutcome> {
+        let snapshot = &request.snapshot;
+        self.check_decryptable(snapshot).await?;
+
+        if self.stream_restore && self.snapshot_streamable(snapshot).await {
+            info!("Streaming snapshot {} into the restore", snapshot.key);
+            let checksums = s3::object_checksums(&self.client, &self.bucket, &snapshot.key)
+                .await
+                .context("Could not verify backup")?;
+            let reader = s3::open_object(&self.client, &self.bucket, &snapshot.key, checksums.part_size)
+                .await
+                .context("Failed to open backup")?;
+            return self.run_restore(request, SnapshotSource::Stream(reader, checksums), events, cancel).await;
+        }
+
+        let path = self.download_snapshot(snapshot, events, cancel).await?;
+        self.verify_snapshot(snapshot, &path, events).await?;
+
+        info!("Starting restore process for downloaded file: {}", path.display());
+        let outcome = self.restore_download(request, &path, events, cancel).await;
+        // A finished download is never resumed from, so only download jobs keep theirs
+        s3::remove_download(&path).await;
+        outcome
+    }
+
+    async fn restore_download(&self, request: &RestoreRequest, path: &Path, events: &UnboundedSender<ServiceEvent>, cancel: &CancelToken) -> Result<RestoreOutcome> {
+        let mut header = Vec::new();
+        std::fs::File::open(path)?.take(64).read_to_end(&mut header)?;
+        let encrypted = crypto::is_encrypted_header(&header);
+        if encrypted && self.encryption_key.is_none() {
+            return Err(crypto::missing_key());
+        }
+
+        // Encrypted and compressed snapshots are decoded on their way into the restore, so no decrypted copy is written
+        let source = if encrypted
+            || Compression::detect(&request.snapshot.key, None) != Compression::None
+            || Compression::from_header(&header) != Compression::None
+        {
+            SnapshotSource::Encoded(path.to_path_buf())
+        } else {
+            SnapshotSource::File(path.to_path_buf())
+        };
+        self.run_restore(request, source, events, cancel).await
+    }
+
+    /// Check a streamed snapshot against the checksums S3 has for it once the restore has read all of it,
+    /// so a corrupt stream fails the restore before the database is swapped in
+    fn verify_stream(
+        &self,
+        snapshot: &BackupMetadata,
+        bytes_read: Option<&(Arc<AtomicU64>, u64)>,
+        stream_digests: Option<(Arc<Mutex<StreamDigests>>, ObjectChecksums)>,
+    ) -> Result<()> {
+        let (Some((consumed, size)), Some((digests, checksums))) = (bytes_read, stream_digests) else {
+            return Ok(());
+        };
+        let consumed = consumed.load(Ordering::Relaxed);
+        if consumed != *size {
+            bail!("Restore read {} of {} bytes of {}, so it could not be verified", consumed, size, snapshot.key);
+        }
+
+        let digests = std::mem::replace(&mut *digests.lock().unwrap(), StreamDigests::new(None));
+        match digests.verify(&checksums) {
+            Ok(verified) if verified.is_empty() => {
+                warn!("No usable checksums for {}, restored unverified", snapshot.key);
+                Ok(())
+            }
+            Ok(verified) => {
+                info!("Verified streamed {} ({})", snapshot.key, verified.join(", "));
+                Ok(())
+            }
+            Err(e) => Err(e.context("Streamed backup is corrupt")),
+        }
+    }
+
+    async fn run_restore(&self, request: &RestoreRequest, source: SnapshotSource, events: &UnboundedSender<ServiceEvent>, cancel: &CancelToken) -> Result<RestoreOutcome> {
+        validate_pg_config(&self.pg_config)?;
+        if request.target.is_empty() {
+            bail!("Name of the database to restore into is required");
+        }
+
+        let snapshot = &request.snapshot;
+        let plan = request.mode.plan(&request.target);
+        // A dump decoded on its way into the restore has no table of contents to read ahead of time, so its table count is not checked
+        let expected_tables = match &source {
+            SnapshotSource::File(path) => backup::expected_tables(&path.to_string_lossy())?,
+            SnapshotSource::Encoded(_) | SnapshotSource::Stream(..) => None,
+        };
+        let pgclient = connect_pg(&self.pg_config, &pg_connection_config(&self.pg_config, None))
+            .await
+            .context("Failed to connect to PostgreSQL")?;
+        plan.prepare(&pgclient).await.context("Failed to prepare restore database")?;
+        info!("Prepared database '{}' for restore ({})", plan.database, plan.mode);
+
+        // Only restores into a database created for the purpose can be abandoned safely
+        let cancellable = plan.mode != RestoreMode::Existing;
+        let restore_cancel = if cancellable { cancel.clone() } else { CancelToken::new() };
+
+        // Decoded and streamed restores have no table of contents to count, so they report the share of the snapshot read instead
+        let bytes_read = match &source {
+            SnapshotSource::Stream(reader, _) => Some((reader.consumed(), reader.size())),
+            SnapshotSource::Encoded(path) => Some((Arc::new(AtomicU64::new(0)), std::fs::metadata(path)?.len())),
+            SnapshotSource::File(_) => None,
+        };
+        let stream_digests = match &source {
+            SnapshotSource::Stream(reader, checksums) => Some((reader.digests(), checksums.clone())),
+            SnapshotSource::Encoded(_) | SnapshotSource::File(_) => None,
+        };
+        let fraction = |latest: &RestoreProgress| match &bytes_read {
+            Some((consumed, size)) if *size > 0 => (consumed.load(Ordering::Relaxed) as f64 / *size as f64).min(1.0) as f32,
+            _ => latest.fraction(),
+        };
+        let report = |latest: &RestoreProgress| {
+            let _ = events.send(ServiceEvent::Restoring { fraction: fraction(latest), current: latest.current.clone(), cancellable });
+        };
+        report(&RestoreProgress::default());
+
+        let (progress_tx, mut progress_rx) = tokio::sync::mpsc::unbounded_channel::<RestoreProgress>();
+        let host = self.pg_config.host.clone().unwrap_or_default();
+        let port = self.pg_config.port.unwrap_or(5432);
+        let username = self.pg_config.username.clone();
+        let password = self.pg_config.password.clone();
+        let use_ssl = self.pg_config.use_ssl;
+        let encryption_key = self.encryption_key.clone();
+        let compression = match &source {
+            SnapshotSource::Stream(reader, _) => Compression::detect(&snapshot.key, reader.compression()),
+            SnapshotSource::Encoded(_) => Compression::detect(&snapshot.key, None),
+            SnapshotSource::File(_) => Compression::None,
+        };
+        let consumed = bytes_read.as_ref().map(|(consumed, _)| Arc::clone(consumed)).unwrap_or_default();
+        let restore_dbname = plan.database.clone();
+        let clean = plan.clean();
+        let mut restore_handle = tokio::task::spawn_blocking(move || {
+            let on_progress = |progress: &RestoreProgress| {
+                let _ = progress_tx.send(progress.clone());
+            };
+            let input: Box<dyn Read + Send> = match source {
+                SnapshotSource::File(path) => {
+                    return backup::restore_database_with_progress(
+                        &restore_dbname,
+                        &path.to_string_lossy(),
+                        &host,
+                        port,
+                        username.as_deref(),
+                        password.as_deref(),
+                        use_ssl,
+                        clean,
+                        &restore_cancel,
+                        on_progress,
+                    )
+                }
+                SnapshotSource::Encoded(path) => {
+                    let file = std::fs::File::open(&path).with_context(|| format!("Failed to open {}", path.display()))?;
+                    Box::new(CountingReader { inner: std::io::BufReader::new(file), consumed })
+                }
+                SnapshotSource::Stream(reader, _) => Box::new(reader),
+            };
+            backup::restore_stream_with_progress(
+                &restore_dbname,
+                compression::decompress_if_compressed(crypto::decrypt_if_encrypted(input, encryption_key.as_ref())?, compression)?,
+                &host,
+                port,
+                username.as_deref(),
+                password.as_deref(),
+                use_ssl,
+                clean,
+                &restore_cancel,
+                on_progress,
+            )
+        });
+
+        let mut latest = RestoreProgress::default();
+        let mut ticker = tokio::time::interval(Duration::from_millis(100));
+        let join_result = loop {
+            tokio::select! {
+                result = &mut restore_handle => break result,
+                Some(progress) = progress_rx.recv() => {
+                    latest = progress;
+                    report(&latest);
+                },
+                _ = ticker.tick(), if bytes_read.is_some() => report(&latest),
+            }
+        };
+
+        let result = match join_result {
+            Ok(Err(e)) if e.is::<Cancelled>() => {
+                info!("Restore of {} cancelled, dropping '{}'", snapshot.key, plan.database);
+                plan.rollback(&pgclient).await;
+                return Ok(RestoreOutcome::Cancelled { dropped: plan.database });
+            }
+            Ok(Ok(())) => {
+                // Validating and swapping the database in can't be interrupted
+                let _ = events.send(ServiceEvent::Restoring { fraction: 1.0, current: None, cancellable: false });
+                match self.verify_stream(snapshot, bytes_read.as_ref(), stream_digests) {
+                    Ok(()) => self.validate_and_finish_restore(&plan, &pgclient, expected_tables).await,
+                    Err(e) => Err(e),
+                }
+            }
+            Ok(Err(e)) => Err(e),
+            Err(e) => Err(anyhow!("pg_restore task failed: {}", e)),
+        };
+
+        match result {
+            Ok(()) => {
+                info!("pg_restore completed successfully");
+                Ok(RestoreOutcome::Restored { target: plan.target })
+            }
+            Err(e) => {
+                plan.rollback(&pgclient).await;
+                Err(e.context("pg_restore failed"))
+            }
+        }
+    }
+
+    /// Check the freshly restored database and put it in place
+    async fn validate_and_finish_restore(&self, plan: &RestorePlan, pgclient: &tokio_postgres::Client, expected_tables: Option<usize>) -> Result<()> {
+        let restored = connect_pg(&self.pg_config, &pg_connection_config(&self.pg_config, Some(&plan.database))).await?;
+        plan.validate(&restored, expected_tables).await?;
+        drop(restored);
+
+        plan.finish(pgclient).await
+    }
+}
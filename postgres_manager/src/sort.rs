@@ -0,0 +1,121 @@
+use anyhow::{anyhow, Error, Result};
+use std::cmp::Ordering;
+use std::fmt;
+use std::str::FromStr;
+
+use crate::ui::models::BackupMetadata;
+
+/// Column the snapshot list is sorted by
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SortField {
+    #[default]
+    Modified,
+    Size,
+    Key,
+}
+
+impl SortField {
+    /// The field after this one when cycling through them
+    pub fn next(self) -> Self {
+        match self {
+            SortField::Modified => SortField::Size,
+            SortField::Size => SortField::Key,
+            SortField::Key => SortField::Modified,
+        }
+    }
+
+    /// Newest and largest first, keys alphabetically
+    pub fn default_descending(self) -> bool {
+        self != SortField::Key
+    }
+}
+
+impl fmt::Display for SortField {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SortField::Modified => write!(f, "modified"),
+            SortField::Size => write!(f, "size"),
+            SortField::Key => write!(f, "key"),
+        }
+    }
+}
+
+/// Order of the snapshot list, written as `<field>[:asc|:desc]`, e.g. `size:asc`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SnapshotSort {
+    pub field: SortField,
+    pub descending: bool,
+}
+
+impl Default for SnapshotSort {
+    fn default() -> Self {
+        Self::by(SortField::default())
+    }
+}
+
+impl SnapshotSort {
+    /// Sort by `field` in its natural direction
+    pub fn by(field: SortField) -> Self {
+        Self { field, descending: field.default_descending() }
+    }
+
+    /// Switch to the next field
+    pub fn cycle_field(self) -> Self {
+        Self::by(self.field.next())
+    }
+
+    pub fn reversed(self) -> Self {
+        Self { descending: !self.descending, ..self }
+    }
+
+    pub fn compare(&self, a: &BackupMetadata, b: &BackupMetadata) -> Ordering {
+        // The key breaks ties so the order is stable across reloads
+        let ordering = match self.field {
+            SortField::Modified => a.last_modified.cmp(&b.last_modified).then_with(|| a.key.cmp(&b.key)),
+            SortField::Size => a.size.cmp(&b.size).then_with(|| a.key.cmp(&b.key)),
+            SortField::Key => a.key.cmp(&b.key),
+        };
+        if self.descending {
+            ordering.reverse()
+        } else {
+            ordering
+        }
+    }
+
+    pub fn apply(&self, snapshots: &mut [BackupMetadata]) {
+        snapshots.sort_by(|a, b| self.compare(a, b));
+    }
+}
+
+impl fmt::Display for SnapshotSort {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.field, if self.descending { "desc" } else { "asc" })
+    }
+}
+
+impl FromStr for SnapshotSort {
+    type Err = Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        let (field, direction) = match value.split_once(':') {
+            Some((field, direction)) => (field, Some(direction)),
+            None => (value, None),
+        };
+
+        let field = match field.to_ascii_lowercase().as_str() {
+            "modified" | "date" => SortField::Modified,
+            "size" => SortField::Size,
+            "key" | "name" => SortField::Key,
+            _ => return Err(anyhow!("Invalid sort field '{}', use modified, size or key", field)),
+        };
+
+        let descending = match direction.map(str::to_ascii_lowercase).as_deref() {
+            None => field.default_descending(),
+            Some("asc") => false,
+            Some("desc") => true,
+            Some(other) => return Err(anyhow!("Invalid sort direction '{}', use asc or desc", other)),
+        };
+
+        Ok(Self { field, descending })
+    }
+}
@@ -0,0 +1,324 @@
+use anyhow::{Context, Result};
+use std::collections::{HashMap, VecDeque};
+use std::process::Command;
+use log::debug;
+
+/// Object types whose pg_restore description spans more than one word, longest first
+const MULTI_WORD_DESCS: &[&str] = &[
+    "MATERIALIZED VIEW DATA",
+    "SEQUENCE OWNED BY",
+    "TEXT SEARCH CONFIGURATION",
+    "TEXT SEARCH DICTIONARY",
+    "TEXT SEARCH PARSER",
+    "TEXT SEARCH TEMPLATE",
+    "FOREIGN DATA WRAPPER",
+    "PUBLICATION TABLES IN SCHEMA",
+    "PUBLICATION TABLE",
+    "PROCEDURAL LANGUAGE",
+    "MATERIALIZED VIEW",
+    "EVENT TRIGGER",
+    "DEFAULT ACL",
+    "FK CONSTRAINT",
+    "FOREIGN TABLE",
+    "INDEX ATTACH",
+    "LARGE OBJECT",
+    "OPERATOR CLASS",
+    "OPERATOR FAMILY",
+    "SEQUENCE SET",
+    "TABLE ATTACH",
+    "TABLE DATA",
+    "USER MAPPING",
+    "BLOB COMMENTS",
+    "BLOBS",
+];
+
+/// A single entry of a pg_restore archive's table of contents
+#[derive(Clone, Debug, PartialEq)]
+pub struct TocEntry {
+    /// Object type, e.g. `TABLE`, `TABLE DATA` or `FK CONSTRAINT`
+    pub desc: String,
+    /// Schema-qualified name as pg_restore prints it in verbose mode, e.g. `public.users`
+    pub name: String,
+}
+
+/// Parse the output of `pg_restore --list`
+pub fn parse_toc(listing: &str) -> Vec<TocEntry> {
+    listing.lines().filter_map(parse_toc_line).collect()
+}
+
+/// Parse one `pg_restore --list` line such as `215; 1259 16386 TABLE public users postgres`
+fn parse_toc_line(line: &str) -> Option<TocEntry> {
+    let line = line.trim();
+    if line.is_empty() || line.starts_with(';') {
+        return None;
+    }
+
+    let (_, rest) = line.split_once(';')?;
+    let mut tokens = rest.split_whitespace();
+    // Skip the catalog table OID and the object OID
+    tokens.next()?;
+    tokens.next()?;
+    let rest = tokens.collect::<Vec<_>>().join(" ");
+
+    let desc = MULTI_WORD_DESCS
+        .iter()
+        .find(|desc| rest.starts_with(*desc) && rest[desc.len()..].starts_with(' '))
+        .map(|desc| desc.to_string())
+        .or_else(|| rest.split_whitespace().next().map(|s| s.to_string()))?;
+
+    let fields: Vec<&str> = rest[desc.len()..].split_whitespace().collect();
+    // Remaining fields are the schema, the name (possibly several words) and the owner
+    let name = match fields.as_slice() {
+        [] => String::new(),
+        [schema, name] => qualify(schema, name),
+        [schema, name @ .., _owner] => qualify(schema, &name.join(" ")),
+        [name] => name.to_string(),
+    };
+
+    Some(TocEntry { desc, name })
+}
+
+fn qualify(schema: &str, name: &str) -> String {
+    if schema == "-" {
+        name.to_string()
+    } else {
+        format!("{}.{}", schema, name)
+    }
+}
+
+/// Read the table of contents of an archive with `pg_restore --list`. No server connection is needed.
+pub fn read_toc(input: &str) -> Result<Vec<TocEntry>> {
+    let output = Command::new("pg_restore")
+        .arg("--list")
+        .arg(input)
+        .output()
+        .context("Failed to execute pg_restore --list")?;
+
+    if !output.status.success() {
+        anyhow::bail!("pg_restore --list failed: {}", String::from_utf8_lossy(&output.stderr));
+    }
+
+    let entries = parse_toc(&String::from_utf8_lossy(&output.stdout));
+    debug!("Archive {} has {} table of contents entries", input, entries.len());
+    Ok(entries)
+}
+
+/// Oldest and newest custom archive versions whose table of contents `data_offsets_known` can read
+const MIN_ARCHIVE_VERSION: (u8, u8) = (1, 12);
+const MAX_ARCHIVE_VERSION: (u8, u8) = (1, 16);
+
+/// Offset state pg_dump records for an entry whose data it couldn't locate, because it was writing to a pipe
+const OFFSET_POS_NOT_SET: u8 = 1;
+
+/// Reads the integers and strings of a custom archive the way pg_restore does
+struct ArchiveReader<'a> {
+    data: &'a [u8],
+    int_size: usize,
+}
+
+impl<'a> ArchiveReader<'a> {
+    fn bytes(&mut self, len: usize) -> Option<&'a [u8]> {
+        let bytes = self.data.get(..len)?;
+        self.data = &self.data[len..];
+        Some(bytes)
+    }
+
+    fn byte(&mut self) -> Option<u8> {
+        self.bytes(1).map(|b| b[0])
+    }
+
+    /// A sign byte followed by `int_size` little-endian bytes
+    fn int(&mut self) -> Option<i64> {
+        let negative = self.byte()? != 0;
+        let value = self.bytes(self.int_size)?.iter().rev().fold(0i64, |value, b| (value << 8) | i64::from(*b));
+        Some(if negative { -value } else { value })
+    }
+
+    /// A length followed by that many bytes. A negative length is a NULL string, read as `Some(None)`.
+    fn string(&mut self) -> Option<Option<&'a [u8]>> {
+        match self.int()? {
+            len if len < 0 => Some(None),
+            len => self.bytes(len as usize).map(Some),
+        }
+    }
+}
+
+/// Whether the custom archive starting with `head` records where the data of every table of contents entry
+/// that has data is. pg_dump can only do that when it writes to a seekable file, not to a pipe.
+/// `None` if `head` isn't a custom archive of a version this understands, or ends before its table of contents does.
+pub fn data_offsets_known(head: &[u8]) -> Option<bool> {
+    let mut reader = ArchiveReader { data: head, int_size: 4 };
+    if reader.bytes(5)? != b"PGDMP" {
+        return None;
+    }
+    let version = (reader.byte()?, reader.byte()?);
+    reader.byte()?;
+    if !(MIN_ARCHIVE_VERSION..=MAX_ARCHIVE_VERSION).contains(&version) {
+        return None;
+    }
+    reader.int_size = usize::from(reader.byte()?);
+    let offset_size = usize::from(reader.byte()?);
+    // Only the custom format (1) stores offsets
+    if reader.byte()? != 1 {
+        return None;
+    }
+    // Compression: an algorithm byte since 1.15, a level before
+    if version >= (1, 15) {
+        reader.byte()?;
+    } else {
+        reader.int()?;
+    }
+    // Creation time, then the database name, server version and pg_dump version
+    for _ in 0..7 {
+        reader.int()?;
+    }
+    for _ in 0..3 {
+        reader.string()?;
+    }
+
+    let entries = reader.int()?;
+    let mut known = true;
+    for _ in 0..entries.max(0) {
+        // Dump ID and whether the entry has data
+        reader.int()?;
+        reader.int()?;
+        // Table OID, OID, tag and description
+        for _ in 0..4 {
+            reader.string()?;
+        }
+        // Section
+        reader.int()?;
+        // Definition, drop statement, copy statement, namespace, tablespace and, since 1.14, table access method
+        for _ in 0..if version >= (1, 14) { 6 } else { 5 } {
+            reader.string()?;
+        }
+        if version >= (1, 16) {
+            // Relation kind
+            reader.int()?;
+        }
+        // Owner and the long unused "with OIDs" flag
+        reader.string()?;
+        reader.string()?;
+        // Dependencies, up to a NULL string
+        while reader.string()?.is_some() {}
+
+        let state = reader.byte()?;
+        reader.bytes(offset_size)?;
+        if state == OFFSET_POS_NOT_SET {
+            known = false;
+        }
+    }
+
+    Some(known)
+}
+
+/// Number of tables a restore of `entries` creates
+pub fn table_count(entries: &[TocEntry]) -> usize {
+    entries.iter().filter(|entry| entry.desc == "TABLE").count()
+}
+
+/// Progress of a running restore
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct RestoreProgress {
+    pub completed: usize,
+    pub total: usize,
+    /// Object pg_restore is currently working on
+    pub current: Option<String>,
+}
+
+impl RestoreProgress {
+    /// Fraction of the table of contents restored so far, between 0.0 and 1.0
+    pub fn fraction(&self) -> f32 {
+        if self.total == 0 {
+            0.0
+        } else {
+            (self.completed as f32 / self.total as f32).min(1.0)
+        }
+    }
+}
+
+/// Split a `pg_restore --verbose` line into the object type and name it reports working on
+pub fn parse_verbose_line(line: &str) -> Option<(String, String)> {
+    let message = line.strip_prefix("pg_restore: ")?;
+
+    if let Some(name) = message.strip_prefix("processing data for table ") {
+        return Some(("TABLE DATA".to_string(), name.trim_matches('"').to_string()));
+    }
+
+    if let Some(rest) = message.strip_prefix("executing ") {
+        let desc = MULTI_WORD_DESCS
+            .iter()
+            .find(|desc| rest.starts_with(*desc))
+            .map(|desc| desc.to_string())
+            .or_else(|| rest.split_whitespace().next().map(|s| s.to_string()))?;
+        return Some((desc.clone(), rest[desc.len()..].trim().trim_matches('"').to_string()));
+    }
+
+    let rest = message.strip_prefix("creating ")?;
+    let (desc, name) = rest.split_once(" \"")?;
+    Some((desc.to_string(), name.trim_end_matches('"').to_string()))
+}
+
+/// Tracks `pg_restore --verbose` output against the archive's table of contents
+#[derive(Debug)]
+pub struct TocTracker {
+    done: Vec<bool>,
+    /// Entries not yet seen, in archive order, by type and name and by type alone. Entries matched through
+    /// the other map stay queued and are skipped when they come up, so each line costs constant time on average.
+    by_name: HashMap<(String, String), VecDeque<usize>>,
+    by_desc: HashMap<String, VecDeque<usize>>,
+    progress: RestoreProgress,
+}
+
+impl TocTracker {
+    pub fn new(entries: Vec<TocEntry>) -> Self {
+        let total = entries.len();
+        let mut by_name: HashMap<_, VecDeque<usize>> = HashMap::new();
+        let mut by_desc: HashMap<_, VecDeque<usize>> = HashMap::new();
+        for (i, entry) in entries.into_iter().enumerate() {
+            by_desc.entry(entry.desc.clone()).or_default().push_back(i);
+            by_name.entry((entry.desc, entry.name)).or_default().push_back(i);
+        }
+        Self {
+            done: vec![false; total],
+            by_name,
+            by_desc,
+            progress: RestoreProgress { total, ..Default::default() },
+        }
+    }
+
+    pub fn progress(&self) -> &RestoreProgress {
+        &self.progress
+    }
+
+    /// Feed one line of pg_restore's stderr. Returns true if the progress changed.
+    pub fn observe(&mut self, line: &str) -> bool {
+        let Some((desc, name)) = parse_verbose_line(line) else {
+            return false;
+        };
+
+        // Prefer the exact entry; fall back to the next pending entry of the same type
+        let key = (desc, name);
+        let idx = Self::next_pending(self.by_name.get_mut(&key), &self.done)
+            .or_else(|| Self::next_pending(self.by_desc.get_mut(&key.0), &self.done));
+
+        if let Some(i) = idx {
+            self.done[i] = true;
+            self.progress.completed += 1;
+        }
+        let (desc, name) = key;
+        self.progress.current = Some(if name.is_empty() { desc } else { name });
+        true
+    }
+
+    /// Take the first entry of `queue` that hasn't been seen yet, dropping the seen ones before it
+    fn next_pending(queue: Option<&mut VecDeque<usize>>, done: &[bool]) -> Option<usize> {
+        let queue = queue?;
+        while let Some(i) = queue.pop_front() {
+            if !done[i] {
+                return Some(i);
+            }
+        }
+        None
+    }
+}
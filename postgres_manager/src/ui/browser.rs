@@ -1,18 +1,47 @@
 use anyhow::{anyhow, Result};
-use aws_sdk_s3::{Client as S3Client, config::Credentials};
+use aws_sdk_s3::Client as S3Client;
 use crossterm::{event::{self, DisableMouseCapture, EnableMouseCapture, Event, KeyCode}, terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen}, execute};
 use log::{debug, error, info};
 use ratatui::backend::{Backend, CrosstermBackend};
 use ratatui::Terminal;
+use std::collections::{HashMap, HashSet};
 use std::time::Duration;
 use std::io::stdout;
-use tokio::io::AsyncWriteExt;
+use futures_util::StreamExt;
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
+use tokio::task::JoinHandle;
 use tokio::time::sleep;
-use random_word::Lang;
 use tokio_postgres::Config as PgConfig;
 
-use crate::postgres;
-use crate::ui::models::{S3Config, PostgresConfig, BackupMetadata, PopupState, InputMode, FocusField};
+use crate::backup::RestoreMode;
+use crate::crypto::{self, EncryptionKey};
+use crate::filter::SnapshotFilter;
+use crate::history::{HistoryEntry, JobHistory};
+use crate::jobs::{Job, JobKind, JobManager, JobStatus};
+use crate::sort::SnapshotSort;
+use crate::manifest::SnapshotManifest;
+use crate::postgres::{self, DatabaseInfo};
+use crate::retention::{self, PrunePlan, RetentionPolicy};
+use crate::s3::{self, DownloadOptions, SnapshotPage};
+use crate::service::{self, RestoreRequest, SnapshotService};
+use crate::ui::models::{S3Config, PostgresConfig, BackupMetadata, PopupState, InputMode, FocusField, KeyAction, DatabaseAction, SidePanel};
+
+/// Number of snapshots PageUp/PageDown move the selection by
+const PAGE_JUMP: usize = 10;
+
+/// Number of snapshots whose metadata is looked up at once to tell whether they are encrypted
+const ENCRYPTION_LOOKUPS: usize = 8;
+
+/// Number of finished jobs kept in the history panel
+const HISTORY_LIMIT: usize = 100;
+
+/// What a background lookup found out about a snapshot, or a further page of the listing
+enum SnapshotDetail {
+    Encrypted(String, bool),
+    Manifest(String, Option<Box<SnapshotManifest>>),
+    /// A page of the listing numbered `.0`, or why the rest of it could not be listed
+    Page(u64, Result<SnapshotPage, String>),
+}
 
 /// Snapshot browser for managing S3 backups
 pub struct SnapshotBrowser {
@@ -20,12 +49,53 @@ pub struct SnapshotBrowser {
     pub pg_config: PostgresConfig,
     pub s3_client: Option<S3Client>,
     pub snapshots: Vec<BackupMetadata>,
+    /// Token of the next page of the listing, while the rest of it is being fetched in the background
+    pub next_token: Option<String>,
+    /// Counts reloads of the list, so pages of an earlier listing are ignored
+    listing: u64,
+    page_loader: Option<JoinHandle<()>>,
     pub selected_idx: Option<usize>,
     pub input_mode: InputMode,
     pub input_buffer: String,
     pub focus: FocusField,
     pub popup_state: PopupState,
     pub temp_file: Option<String>,
+    pub restore_mode: RestoreMode,
+    /// Name of the database to create when restoring in `RestoreMode::New`
+    pub restore_db_name: String,
+    pub download_options: DownloadOptions,
+    /// Pipe snapshots straight from S3 into the restore instead of downloading them first
+    pub stream_restore: bool,
+    /// Key for decrypting encrypted snapshots
+    pub encryption_key: Option<EncryptionKey>,
+    /// Policy applied when pruning snapshots
+    pub retention: RetentionPolicy,
+    /// Manifests fetched in the background for the details pane by snapshot key, `None` for snapshots without one
+    pub manifests: HashMap<String, Option<SnapshotManifest>>,
+    /// Keys whose manifest is being fetched
+    manifest_requests: HashSet<String>,
+    /// Whether snapshots are encrypted according to their `encryption` metadata, by key. Looked up in the
+    /// background for each loaded page, as the key suffix is only a hint.
+    pub encrypted: HashMap<String, bool>,
+    details_tx: UnboundedSender<SnapshotDetail>,
+    details_rx: UnboundedReceiver<SnapshotDetail>,
+    /// Search typed after '/', and the filter parsed from it
+    pub search: String,
+    pub filter: SnapshotFilter,
+    /// Why the current search could not be parsed, the previous filter stays applied meanwhile
+    pub search_error: Option<String>,
+    pub sort: SnapshotSort,
+    /// Background downloads and restores, and the one selected in the jobs panel
+    pub jobs: JobManager,
+    pub selected_job: Option<usize>,
+    /// Finished jobs, newest first, and the file they are recorded in
+    pub history: Vec<HistoryEntry>,
+    pub history_file: Option<JobHistory>,
+    /// What the pane next to the snapshot list shows
+    pub side_panel: SidePanel,
+    /// Databases on the PostgreSQL server, and the one selected in the databases panel
+    pub databases: Vec<DatabaseInfo>,
+    pub selected_database: Option<usize>,
 }
 
 impl std::fmt::Debug for SnapshotBrowser {
@@ -35,12 +105,31 @@ impl std::fmt::Debug for SnapshotBrowser {
             .field("pg_config", &self.pg_config)
             .field("s3_client", &"<S3Client>")
             .field("snapshots", &self.snapshots)
+            .field("next_token", &self.next_token)
             .field("selected_idx", &self.selected_idx)
             .field("input_mode", &self.input_mode)
             .field("input_buffer", &self.input_buffer)
             .field("focus", &self.focus)
             .field("popup_state", &self.popup_state)
             .field("temp_file", &self.temp_file)
+            .field("restore_mode", &self.restore_mode)
+            .field("restore_db_name", &self.restore_db_name)
+            .field("stream_restore", &self.stream_restore)
+            .field("encryption_key", &self.encryption_key)
+            .field("retention", &self.retention)
+            .field("manifests", &self.manifests)
+            .field("encrypted", &self.encrypted)
+            .field("search", &self.search)
+            .field("filter", &self.filter)
+            .field("search_error", &self.search_error)
+            .field("sort", &self.sort)
+            .field("jobs", &self.jobs)
+            .field("selected_job", &self.selected_job)
+            .field("history", &self.history)
+            .field("history_file", &self.history_file)
+            .field("side_panel", &self.side_panel)
+            .field("databases", &self.databases)
+            .field("selected_database", &self.selected_database)
             .finish()
     }
 }
@@ -92,18 +181,8 @@ impl SnapshotBrowser {
             return Err(anyhow!("PostgreSQL username is required"));
         }
 
-        // Construct connection string
-        let mut config = PgConfig::new();
-        config.host(self.pg_config.host.as_ref().unwrap());
-        config.port(self.pg_config.port.unwrap());
-        config.user(self.pg_config.username.as_ref().unwrap());
-        config.password(&self.pg_config.password.as_ref().unwrap_or(&String::new()));
-        let result = if self.pg_config.use_ssl {
-            postgres::connect_ssl(&config, false, None).await
-        } else {
-            postgres::connect_no_ssl(&config).await
-        };
-        match result {
+        let config = self.pg_connection_config(None);
+        match self.connect_pg(&config).await {
             Ok(client) => {
                 info!("Successfully connected to PostgreSQL");
                 self.popup_state = PopupState::TestPgResult(format!("Successfully connected to PostgreSQL\nConnection string: {:?}", config));
@@ -117,21 +196,65 @@ impl SnapshotBrowser {
         }
     }
 
+    /// Build a connection config from the PostgreSQL settings, optionally for a specific database
+    pub fn pg_connection_config(&self, dbname: Option<&str>) -> PgConfig {
+        service::pg_connection_config(&self.pg_config, dbname)
+    }
+
+    async fn connect_pg(&self, config: &PgConfig) -> Result<tokio_postgres::Client> {
+        service::connect_pg(&self.pg_config, config).await
+    }
+
     pub fn new(config: S3Config, pg_config: PostgresConfig) -> Self {
+        let (details_tx, details_rx) = unbounded_channel();
         Self {
             config,
             pg_config,
             s3_client: None,
             snapshots: Vec::new(),
+            next_token: None,
+            listing: 0,
+            page_loader: None,
             selected_idx: None,
             input_mode: InputMode::Normal,
             input_buffer: String::new(),
             focus: FocusField::SnapshotList,
             popup_state: PopupState::Hidden,
             temp_file: None,
+            restore_mode: RestoreMode::default(),
+            restore_db_name: String::new(),
+            download_options: DownloadOptions::default(),
+            stream_restore: false,
+            encryption_key: None,
+            retention: RetentionPolicy::default(),
+            manifests: HashMap::new(),
+            manifest_requests: HashSet::new(),
+            encrypted: HashMap::new(),
+            details_tx,
+            details_rx,
+            search: String::new(),
+            filter: SnapshotFilter::default(),
+            search_error: None,
+            sort: SnapshotSort::default(),
+            jobs: JobManager::new(),
+            selected_job: None,
+            history: Vec::new(),
+            history_file: None,
+            side_panel: SidePanel::default(),
+            databases: Vec::new(),
+            selected_database: None,
         }
     }
 
+    /// Name of the database a restore should end up in, if one is configured for the current mode
+    pub fn restore_target(&self) -> Option<String> {
+        let target = match self.restore_mode {
+            RestoreMode::New => Some(self.restore_db_name.clone()),
+            RestoreMode::Existing | RestoreMode::Swap => self.pg_config.db_name.clone(),
+        };
+        target.filter(|t| !t.is_empty())
+    }
+
     pub fn verify_s3_settings(&self) -> Result<()> {
         if self.config.bucket.is_empty() {
             return Err(anyhow!("Bucket name is required"));
@@ -169,341 +292,593 @@ impl SnapshotBrowser {
         // Clear any previous error
         self.set_error(None);
 
-        let credentials = Credentials::new(
-            &self.config.access_key_id,
-            &self.config.secret_access_key,
-            None, None, "postgres-manager"
-        );
-
-        let mut config_builder = aws_sdk_s3::config::Builder::new()
-            .credentials_provider(credentials)
-            .region(aws_sdk_s3::config::Region::new(self.config.region.clone()));
-
-        if !self.config.endpoint_url.is_empty() {
-            let endpoint_url = if !self.config.endpoint_url.starts_with("http") {
-                format!("http://{}", self.config.endpoint_url)
-            } else {
-                self.config.endpoint_url.clone()
-            };
+        self.s3_client = Some(crate::s3::build_client(&self.config));
 
-            config_builder = config_builder.endpoint_url(endpoint_url);
-        }
+        Ok(())
+    }
 
-        if self.config.path_style {
-            config_builder = config_builder.force_path_style(true);
+    /// Load the first page of snapshots, replacing the current list, and fetch the rest in the background
+    pub async fn load_snapshots(&mut self) -> Result<()> {
+        if self.s3_client.is_none() {
+            self.init_s3_client().await?;
         }
 
-        // Add behavior version which is required by AWS SDK
-        config_builder = config_builder.behavior_version(aws_sdk_s3::config::BehaviorVersion::latest());
+        let client = self.s3_client.as_ref().unwrap();
 
-        let config = config_builder.build();
-        self.s3_client = Some(S3Client::from_conf(config));
+        match crate::s3::list_backups_page(client, &self.config.bucket, &self.config.prefix, None).await {
+            Ok(page) => {
+                self.look_up_encryption(&page.snapshots);
+                self.snapshots = page.snapshots;
+                self.next_token = page.next_token;
+                self.load_remaining_snapshots();
+                self.manifests.clear();
+                self.manifest_requests.clear();
+                self.sort_snapshots();
+
+                let visible = self.visible_snapshots().len();
+                if visible > 0 && self.selected_idx.is_none() {
+                    self.selected_idx = Some(0);
+                } else if visible == 0 {
+                    self.selected_idx = None;
+                } else if let Some(idx) = self.selected_idx {
+                    if idx >= visible {
+                        self.selected_idx = Some(visible - 1);
+                    }
+                }
 
-        Ok(())
+                Ok(())
+            },
+            Err(e) => {
+                self.set_error(Some(e.to_string()));
+                Err(e)
+            }
+        }
     }
 
-    pub async fn load_snapshots(&mut self) -> Result<()> {
-        if self.s3_client.is_none() {
-            if let Err(e) = self.init_s3_client().await {
-                return Err(e);
-            }
+    /// Whether the listing has more pages that have not been loaded yet
+    pub fn has_more_snapshots(&self) -> bool {
+        self.next_token.is_some()
+    }
+
+    /// Fetch the pages after the first one in the background, replacing a fetch of an earlier listing.
+    /// `poll_details` merges them into the list as they arrive.
+    fn load_remaining_snapshots(&mut self) {
+        self.listing += 1;
+        if let Some(loader) = self.page_loader.take() {
+            loader.abort();
         }
+        let (Some(client), Some(mut token)) = (self.s3_client.clone(), self.next_token.clone()) else {
+            return;
+        };
 
-        let client = self.s3_client.as_ref().unwrap();
+        let listing = self.listing;
+        let (bucket, prefix) = (self.config.bucket.clone(), self.config.prefix.clone());
+        let tx = self.details_tx.clone();
+        self.page_loader = Some(tokio::spawn(async move {
+            loop {
+                let page = s3::list_backups_page(&client, &bucket, &prefix, Some(token)).await.map_err(|e| e.to_string());
+                let next = page.as_ref().ok().and_then(|page| page.next_token.clone());
+                if tx.send(SnapshotDetail::Page(listing, page)).is_err() {
+                    return;
+                }
+                match next {
+                    Some(next) => token = next,
+                    None => return,
+                }
+            }
+        }));
+    }
+
+    /// Merge a further page of the listing into the list and re-sort it, keeping the current selection
+    pub fn add_snapshot_page(&mut self, page: SnapshotPage) {
+        debug!("Loaded {} more snapshots", page.snapshots.len());
+        self.look_up_encryption(&page.snapshots);
+        let selected_key = self.selected_snapshot().map(|s| s.key.clone());
 
-        let mut list_objects_builder = client.list_objects_v2()
-            .bucket(&self.config.bucket);
+        self.snapshots.extend(page.snapshots);
+        self.next_token = page.next_token;
+        self.sort_snapshots();
 
-        if !self.config.prefix.is_empty() {
-            list_objects_builder = list_objects_builder.prefix(&self.config.prefix);
+        match selected_key {
+            Some(key) => self.select_key(&key),
+            None => self.selected_idx = (!self.visible_snapshots().is_empty()).then_some(0),
         }
+    }
 
-        match list_objects_builder.send().await {
-            Ok(resp) => {
-                self.snapshots.clear();
-
-                let contents = resp.contents();
-                if !contents.is_empty() {
-                    for obj in contents {
-                        if let (Some(key), Some(size), Some(last_modified)) = (obj.key(), obj.size(), obj.last_modified()) {
-                            self.snapshots.push(BackupMetadata {
-                                key: key.to_string(),
-                                size: size,
-                                last_modified: last_modified.clone(),
-                            });
+    /// Check the metadata of `snapshots` in the background for the ones encrypted without an `.age` suffix.
+    /// The results are picked up by `poll_details`.
+    fn look_up_encryption(&mut self, snapshots: &[BackupMetadata]) {
+        let Some(client) = self.s3_client.clone() else {
+            return;
+        };
+        let keys: Vec<String> = snapshots.iter()
+            .map(|s| s.key.clone())
+            .filter(|key| !crypto::is_encrypted_key(key) && !self.encrypted.contains_key(key))
+            .collect();
+        if keys.is_empty() {
+            return;
+        }
+
+        let bucket = self.config.bucket.clone();
+        let tx = self.details_tx.clone();
+        tokio::spawn(async move {
+            let mut lookups = futures_util::stream::iter(keys)
+                .map(|key| {
+                    let (client, bucket) = (&client, &bucket);
+                    async move {
+                        let encrypted = s3::object_encrypted(client, bucket, &key).await;
+                        (key, encrypted)
+                    }
+                })
+                .buffer_unordered(ENCRYPTION_LOOKUPS);
+            while let Some((key, encrypted)) = lookups.next().await {
+                match encrypted {
+                    Ok(encrypted) => {
+                        if tx.send(SnapshotDetail::Encrypted(key, encrypted)).is_err() {
+                            break;
                         }
                     }
+                    Err(e) => debug!("Failed to check whether {} is encrypted: {}", key, e),
                 }
+            }
+        });
+    }
 
-                // Sort by last modified, newest first
-                self.snapshots.sort_by(|a, b| b.last_modified.cmp(&a.last_modified));
-
-                if !self.snapshots.is_empty() && self.selected_idx.is_none() {
-                    self.selected_idx = Some(0);
-                } else if self.snapshots.is_empty() {
-                    self.selected_idx = None;
-                } else if let Some(idx) = self.selected_idx {
-                    if idx >= self.snapshots.len() {
-                        self.selected_idx = Some(self.snapshots.len() - 1);
+    /// Pick up what the background lookups found out about snapshots, and further pages of the listing
+    pub fn poll_details(&mut self) {
+        while let Ok(detail) = self.details_rx.try_recv() {
+            match detail {
+                SnapshotDetail::Encrypted(key, encrypted) => {
+                    self.encrypted.insert(key, encrypted);
+                }
+                SnapshotDetail::Manifest(key, manifest) => {
+                    // Requests made before the list was reloaded are stale
+                    if self.manifest_requests.remove(&key) {
+                        self.manifests.insert(key, manifest.map(|m| *m));
                     }
                 }
+                SnapshotDetail::Page(listing, _) if listing != self.listing => {}
+                SnapshotDetail::Page(_, Ok(page)) => self.add_snapshot_page(page),
+                SnapshotDetail::Page(_, Err(e)) => {
+                    error!("Failed to list more snapshots: {}", e);
+                    self.next_token = None;
+                    self.set_error(Some(format!("Snapshot list is incomplete: {}", e)));
+                }
+            }
+        }
+    }
 
-                Ok(())
-            },
-            Err(e) => {
-                self.set_error(Some(format!("Failed to list objects: {}", e)));
-                Err(anyhow!("Failed to list objects: {}", e))
+    /// Whether `snapshot` is encrypted, by its key suffix or the metadata looked up for it
+    pub fn is_encrypted(&self, snapshot: &BackupMetadata) -> bool {
+        crypto::is_encrypted_key(&snapshot.key) || self.encrypted.get(&snapshot.key).copied().unwrap_or(false)
+    }
+
+    /// Fetch the manifest of the selected snapshot for the details pane in the background, unless it is
+    /// already cached or on its way. The result is picked up by `poll_details`.
+    pub fn load_selected_manifest(&mut self) {
+        let Some(key) = self.selected_snapshot().map(|s| s.key.clone()) else {
+            return;
+        };
+        let Some(client) = self.s3_client.clone() else {
+            return;
+        };
+        if self.manifests.contains_key(&key) || !self.manifest_requests.insert(key.clone()) {
+            return;
+        }
+
+        let bucket = self.config.bucket.clone();
+        let tx = self.details_tx.clone();
+        tokio::spawn(async move {
+            let manifest = match s3::read_manifest(&client, &bucket, &key).await {
+                Ok(manifest) => manifest,
+                Err(e) => {
+                    debug!("Failed to load manifest of {}: {}", key, e);
+                    None
+                }
+            };
+            let _ = tx.send(SnapshotDetail::Manifest(key, manifest.map(Box::new)));
+        });
+    }
+
+    /// List every snapshot and work out which of them the retention policy would delete
+    pub async fn plan_prune(&mut self) -> Result<()> {
+        if self.s3_client.is_none() {
+            self.init_s3_client().await?;
+        }
+
+        let client = self.s3_client.as_ref().unwrap();
+        let snapshots = s3::list_snapshots(client, &self.config.bucket, &self.config.prefix).await?;
+        let plan = retention::plan(&snapshots, &self.retention)?;
+        info!("Retention policy ({}) would delete {} of {} snapshots", self.retention, plan.delete.len(), snapshots.len());
+
+        self.popup_state = if plan.delete.is_empty() {
+            PopupState::Success("No snapshots to prune".to_string())
+        } else {
+            PopupState::ConfirmPrune(plan)
+        };
+        Ok(())
+    }
+
+    /// Start deleting the snapshots of a confirmed prune as a background job
+    pub async fn prune(&mut self, plan: PrunePlan) -> Result<()> {
+        let service = self.connected_service().await?;
+        self.jobs.spawn_prune(service, plan);
+        Ok(())
+    }
+
+    /// Start deleting a snapshot along with its sidecars as a background job
+    pub async fn delete_snapshot(&mut self, snapshot: &BackupMetadata) -> Result<()> {
+        let service = self.connected_service().await?;
+        self.jobs.spawn_delete(service, snapshot.clone());
+        Ok(())
+    }
+
+    /// Start copying a snapshot along with its sidecars to `new_key` as a background job, removing the original
+    /// for a rename. The list is reloaded with the new key selected once it is done.
+    pub async fn copy_snapshot(&mut self, snapshot: &BackupMetadata, new_key: &str, action: KeyAction) -> Result<()> {
+        let service = self.connected_service().await?;
+        self.jobs.spawn_copy(service, snapshot.clone(), new_key.to_string(), action);
+        Ok(())
+    }
+
+    fn sort_snapshots(&mut self) {
+        self.sort.apply(&mut self.snapshots);
+    }
+
+    /// Re-sort the list, keeping the selected snapshot selected
+    pub fn set_sort(&mut self, sort: SnapshotSort) {
+        let selected_key = self.selected_snapshot().map(|s| s.key.clone());
+        self.sort = sort;
+        self.sort_snapshots();
+        if let Some(key) = selected_key {
+            self.select_key(&key);
+        }
+    }
+
+    /// Snapshots matching the current filter, in list order. `selected_idx` indexes into these.
+    pub fn visible_snapshots(&self) -> Vec<&BackupMetadata> {
+        self.snapshots.iter().filter(|s| self.filter.matches(s)).collect()
+    }
+
+    /// Select the snapshot with `key` if it is visible, otherwise keep the selection within the list
+    fn select_key(&mut self, key: &str) {
+        let visible = self.visible_snapshots();
+        self.selected_idx = match visible.iter().position(|s| s.key == key) {
+            Some(idx) => Some(idx),
+            None if visible.is_empty() => None,
+            None => Some(self.selected_idx.unwrap_or(0).min(visible.len() - 1)),
+        };
+    }
+
+    /// Update the search and filter the list with it, keeping the selected snapshot selected if it still matches
+    pub fn set_search(&mut self, search: String) {
+        match SnapshotFilter::parse(&search) {
+            Ok(filter) => {
+                let selected_key = self.selected_snapshot().map(|s| s.key.clone());
+                self.filter = filter;
+                self.search_error = None;
+                match selected_key {
+                    Some(key) => self.select_key(&key),
+                    None => self.selected_idx = (!self.visible_snapshots().is_empty()).then_some(0),
+                }
             }
+            Err(e) => self.search_error = Some(e.to_string()),
         }
+        self.search = search;
     }
 
     pub fn next(&mut self) {
+        let visible = self.visible_snapshots().len();
         if let Some(idx) = self.selected_idx {
-            if idx + 1 < self.snapshots.len() {
+            if idx + 1 < visible {
                 self.selected_idx = Some(idx + 1);
             }
-        } else if !self.snapshots.is_empty() {
+        } else if visible > 0 {
             self.selected_idx = Some(0);
         }
     }
 
     pub fn previous(&mut self) {
+        let visible = self.visible_snapshots().len();
         if let Some(idx) = self.selected_idx {
             if idx > 0 {
                 self.selected_idx = Some(idx - 1);
             }
-        } else if !self.snapshots.is_empty() {
-            self.selected_idx = Some(self.snapshots.len() - 1);
+        } else if visible > 0 {
+            self.selected_idx = Some(visible - 1);
+        }
+    }
+
+    /// Move the selection down by a page, stopping at the last loaded snapshot
+    pub fn page_down(&mut self) {
+        let visible = self.visible_snapshots().len();
+        if visible > 0 {
+            self.selected_idx = Some(self.selected_idx.map_or(0, |idx| (idx + PAGE_JUMP).min(visible - 1)));
         }
     }
 
+    /// Move the selection up by a page, stopping at the first snapshot
+    pub fn page_up(&mut self) {
+        if !self.visible_snapshots().is_empty() {
+            self.selected_idx = Some(self.selected_idx.map_or(0, |idx| idx.saturating_sub(PAGE_JUMP)));
+        }
+    }
+
+    pub fn first(&mut self) {
+        self.selected_idx = (!self.visible_snapshots().is_empty()).then_some(0);
+    }
+
+    pub fn last(&mut self) {
+        self.selected_idx = self.visible_snapshots().len().checked_sub(1);
+    }
+
     pub fn selected_snapshot(&self) -> Option<&BackupMetadata> {
-        self.selected_idx.and_then(|idx| self.snapshots.get(idx))
+        self.selected_idx.and_then(|idx| self.visible_snapshots().get(idx).copied())
     }
 
-    pub async fn download_snapshot<B: Backend>(&mut self, snapshot: &BackupMetadata, terminal: &mut Terminal<B>, temp_path: &std::path::Path) -> Result<Option<String>> {
-        // Clone the necessary data to avoid borrowing issues
-        let temp_path_str = temp_path.to_string_lossy().to_string();
-        let s3_client = self.s3_client.clone();
-        let bucket = self.config.bucket.clone();
+    /// Service for the current settings, or `None` until the S3 client is set up
+    pub fn service(&self) -> Option<SnapshotService> {
+        let client = self.s3_client.clone()?;
+        let mut service = SnapshotService::new(client, self.config.bucket.clone(), self.pg_config.clone());
+        service.download_options = self.download_options.clone();
+        service.stream_restore = self.stream_restore;
+        service.encryption_key = self.encryption_key.clone();
+        Some(service)
+    }
 
-        // Start download
-        self.popup_state = PopupState::Downloading(snapshot.clone(), 0.0, 0.0);
-        self.temp_file = Some(temp_path_str.clone());
-
-        // Track download rate
-        let mut last_update = std::time::Instant::now();
-        let mut last_bytes = 0u64;
-        let mut current_rate = 0.0;
-
-        // Begin downloading the file
-        if let Some(client) = &s3_client {
-            let get_obj = client.get_object()
-                .bucket(&bucket)
-                .key(&snapshot.key)
-                .send()
-                .await;
-
-            match get_obj {
-                Ok(resp) => {
-                    if let Some(total_size) = resp.content_length() {
-                        let mut file = tokio::fs::File::create(&temp_path).await?;
-                        let mut stream = resp.body;
-                        let mut downloaded: u64 = 0;
-
-                        while let Some(chunk) = stream.try_next().await? {
-                            file.write_all(&chunk).await?;
-                            downloaded += chunk.len() as u64;
-                            let progress = downloaded as f32 / total_size as f32;
-
-                            // Calculate download rate
-                            let now = std::time::Instant::now();
-                            let elapsed = now.duration_since(last_update).as_secs_f64();
-                            if elapsed >= 0.5 { // Update rate every 0.5 seconds
-                                let bytes_since_last = downloaded - last_bytes;
-                                current_rate = bytes_since_last as f64 / elapsed;
-                                last_update = now;
-                                last_bytes = downloaded;
-                            }
+    /// Like `service`, setting up the S3 client first if needed
+    async fn connected_service(&mut self) -> Result<SnapshotService> {
+        if self.s3_client.is_none() {
+            self.init_s3_client().await?;
+        }
+        self.service().ok_or_else(|| anyhow!("S3 client not initialized"))
+    }
 
-                            // Check for user input (like ESC key) during download
-                            if crossterm::event::poll(std::time::Duration::from_millis(0)).unwrap_or(false) {
-                                if let crossterm::event::Event::Key(key) = crossterm::event::read().unwrap_or(crossterm::event::Event::Key(crossterm::event::KeyEvent::new(crossterm::event::KeyCode::Null, crossterm::event::KeyModifiers::NONE))) {
-                                    if key.code == crossterm::event::KeyCode::Esc {
-                                        log::debug!("User pressed ESC to cancel download during chunk processing");
-                                        self.popup_state = PopupState::ConfirmCancel(snapshot.clone(), progress, current_rate);
-                                        terminal.draw(|f| crate::ui::renderer::ui::<B>(f, self))?;
-                                        continue;
-                                    }
-                                }
-                            }
+    /// Start restoring `snapshot` as a background job with the current restore settings
+    pub fn start_restore(&mut self, snapshot: &BackupMetadata) {
+        let Some(service) = self.service() else {
+            log::warn!("Restore attempted but S3 client not initialized");
+            self.popup_state = PopupState::Error("S3 client not initialized".to_string());
+            return;
+        };
+        let Some(target) = self.restore_target() else {
+            self.popup_state = PopupState::Error("Name of the new database is required".to_string());
+            return;
+        };
 
-                            match &self.popup_state {
-                                PopupState::ConfirmCancel(..) => {
-                                    // Wait for user confirmation
-                                    terminal.draw(|f| crate::ui::renderer::ui::<B>(f, self))?;
-                                    continue;
-                                },
-                                PopupState::Hidden => {
-                                    // Download was cancelled and confirmed
-                                    log::debug!("Download cancelled by user");
-                                    file.flush().await?;
-                                    self.temp_file = None; // Reset temp file
-                                    return Ok(None);
-                                },
-                                _ => {
-                                    // Continue downloading
-                                    self.popup_state = PopupState::Downloading(snapshot.clone(), progress, current_rate);
-                                }
-                            }
-                            // Force a redraw to show progress
-                            terminal.draw(|f| crate::ui::renderer::ui::<B>(f, self))?;
-                            // Small delay to allow UI updates
-                            tokio::time::sleep(tokio::time::Duration::from_millis(50)).await;
-                        }
-                        self.temp_file = Some(temp_path_str.clone());
-                        log::info!("Download completed successfully: {}", temp_path_str);
-                        self.popup_state = PopupState::Success("Download complete".to_string());
-                        // Show success message briefly
-                        terminal.draw(|f| crate::ui::renderer::ui::<B>(f, self))?;
-                        tokio::time::sleep(std::time::Duration::from_secs(1)).await;
-                        return Ok(Some(temp_path_str));
-                    } else {
-                        log::warn!("Could not determine file size for snapshot: {}", snapshot.key);
-                        self.popup_state = PopupState::Error("Could not determine file size".to_string());
-                        return Ok(None);
+        let request = RestoreRequest { snapshot: snapshot.clone(), mode: self.restore_mode, target };
+        self.popup_state = match self.jobs.spawn_restore(service, request) {
+            Ok(_) => PopupState::Hidden,
+            Err(e) => PopupState::Error(e.to_string()),
+        };
+    }
+
+    /// Start downloading and verifying `snapshot` as a background job
+    pub fn start_download(&mut self, snapshot: &BackupMetadata) {
+        match self.service() {
+            Some(service) => {
+                if let Err(e) = self.jobs.spawn_download(service, snapshot.clone()) {
+                    self.popup_state = PopupState::Error(e.to_string());
+                }
+            }
+            None => self.popup_state = PopupState::Error("S3 client not initialized".to_string()),
+        }
+    }
+
+    /// Pick up the progress of background jobs and record the ones that finished. Failures are reported in a
+    /// popup unless another one is open, and the lists are reloaded after jobs that changed snapshots or databases.
+    pub async fn poll_jobs(&mut self) {
+        let finished = self.jobs.poll();
+        let mut select = None;
+        let mut reload = false;
+        let mut databases_changed = false;
+        let mut select_database = None;
+
+        for id in finished {
+            let Some(job) = self.jobs.get(id) else {
+                continue;
+            };
+            let entry = job.history_entry();
+            if let Some(path) = &job.download {
+                // Store the downloaded path in case we need it later
+                self.temp_file = Some(path.to_string_lossy().to_string());
+            }
+            match (&job.status, &job.kind) {
+                (JobStatus::Failed(message), _) if self.popup_state == PopupState::Hidden => {
+                    self.popup_state = PopupState::Error(format!("{} of {} failed: {}. Check postgres_manager.log for details",
+                        job.kind, job.subject, message));
+                }
+                (JobStatus::Succeeded(message), kind) if kind.is_database() => {
+                    databases_changed = true;
+                    select_database = match kind {
+                        JobKind::Clone { new_name } | JobKind::RenameDatabase { new_name } => Some(new_name.clone()),
+                        JobKind::CreateDatabase | JobKind::SetOwner { .. } => Some(job.subject.clone()),
+                        _ => None,
+                    };
+                    if self.popup_state == PopupState::Hidden {
+                        self.popup_state = PopupState::Success(message.clone());
                     }
                 }
-                Err(e) => {
-                    log::error!("Failed to download snapshot {}: {}", snapshot.key, e);
-                    self.popup_state = PopupState::Error(format!("Failed to download backup: {}", e));
-                    return Ok(None);
+                (JobStatus::Succeeded(message), JobKind::Copy { .. } | JobKind::Delete | JobKind::Prune) => {
+                    reload = true;
+                    match &job.kind {
+                        JobKind::Copy { new_key, .. } => select = Some(new_key.clone()),
+                        JobKind::Prune => self.selected_idx = None,
+                        _ => {}
+                    }
+                    if self.popup_state == PopupState::Hidden {
+                        self.popup_state = PopupState::Success(message.clone());
+                    }
                 }
+                _ => {}
+            }
+            if let Some(entry) = entry {
+                self.record_history(entry);
+            }
+        }
+
+        if self.selected_job.is_none_or(|idx| idx >= self.jobs.jobs().len()) {
+            self.selected_job = self.jobs.jobs().len().checked_sub(1);
+        }
+        if reload {
+            if let Err(e) = self.load_snapshots().await {
+                debug!("Failed to reload snapshots: {}", e);
+            }
+            if let Some(key) = select {
+                self.select_key(&key);
+            }
+        }
+        if databases_changed {
+            if let Err(e) = self.load_databases().await {
+                debug!("Failed to reload databases: {}", e);
+            }
+            if let Some(name) = select_database {
+                self.select_database(&name);
             }
-        } else {
-            log::warn!("Download attempted but S3 client not initialized");
-            self.popup_state = PopupState::Error("S3 client not initialized".to_string());
-            return Ok(None);
         }
     }
 
-    /// Restore a database from a downloaded snapshot file
-    pub async fn restore_snapshot<B: Backend>(&mut self, snapshot: &BackupMetadata, terminal: &mut Terminal<B>, file_path: &str) -> Result<()> {
-        // Validate PostgreSQL settings
-        if self.pg_config.host.is_none() || self.pg_config.host.as_ref().unwrap().is_empty() {
-            self.popup_state = PopupState::Error("PostgreSQL host is required".to_string());
-            return Err(anyhow!("PostgreSQL host is required"));
+    /// Cancel the running jobs and wait for them to stop, recording how they ended
+    pub async fn shutdown_jobs(&mut self) {
+        for id in self.jobs.shutdown().await {
+            if let Some(entry) = self.jobs.get(id).and_then(Job::history_entry) {
+                self.record_history(entry);
+            }
         }
+    }
 
-        if self.pg_config.port.is_none() {
-            self.popup_state = PopupState::Error("PostgreSQL port is required".to_string());
-            return Err(anyhow!("PostgreSQL port is required"));
+    /// Add a finished job to the history panel and the history file
+    fn record_history(&mut self, entry: HistoryEntry) {
+        if let Some(history) = &self.history_file {
+            if let Err(e) = history.append(&entry) {
+                error!("Failed to record {} of {} in the job history: {:#}", entry.kind, entry.subject, e);
+            }
         }
+        self.history.insert(0, entry);
+        self.history.truncate(HISTORY_LIMIT);
+    }
 
-        if self.pg_config.username.is_none() || self.pg_config.username.as_ref().unwrap().is_empty() {
-            self.popup_state = PopupState::Error("PostgreSQL username is required".to_string());
-            return Err(anyhow!("PostgreSQL username is required"));
+    pub fn selected_job(&self) -> Option<&Job> {
+        self.selected_job.and_then(|idx| self.jobs.jobs().get(idx))
+    }
+
+    pub fn next_job(&mut self) {
+        if let Some(idx) = self.selected_job {
+            if idx + 1 < self.jobs.jobs().len() {
+                self.selected_job = Some(idx + 1);
+            }
         }
+    }
 
-        if self.pg_config.db_name.is_none() || self.pg_config.db_name.as_ref().unwrap().is_empty() {
-            self.popup_state = PopupState::Error("PostgreSQL database name is required".to_string());
-            return Err(anyhow!("PostgreSQL database name is required"));
+    pub fn previous_job(&mut self) {
+        if let Some(idx) = self.selected_job {
+            self.selected_job = Some(idx.saturating_sub(1));
         }
+    }
 
-        // Start restore operation
-        self.popup_state = PopupState::Restoring(snapshot.clone(), 0.0);
-        terminal.draw(|f| crate::ui::renderer::ui::<B>(f, self))?;
+    /// Ask to cancel the selected job, if it can still be cancelled
+    pub fn confirm_cancel_selected_job(&mut self) {
+        let Some(job) = self.selected_job() else {
+            return;
+        };
+        self.popup_state = match job.check_cancellable() {
+            Ok(()) => PopupState::ConfirmCancelJob(job.id),
+            Err(_) if !job.is_running() => PopupState::Hidden,
+            Err(e) => {
+                debug!("User pressed ESC on job #{}, but it can't be cancelled: {}", job.id, e);
+                PopupState::Error(e.to_string())
+            }
+        };
+    }
 
-        // Use a separate thread for the restore operation to avoid blocking the UI
-        let host = self.pg_config.host.as_ref().unwrap().clone();
-        let port = self.pg_config.port.unwrap();
-        let username = self.pg_config.username.clone();
-        let password = self.pg_config.password.clone();
+    /// Forget finished jobs, keeping the selection on the same job if it is still running
+    pub fn clear_finished_jobs(&mut self) {
+        let selected_id = self.selected_job().map(|job| job.id);
+        self.jobs.clear_finished();
+        self.selected_job = selected_id
+            .and_then(|id| self.jobs.jobs().iter().position(|job| job.id == id))
+            .or_else(|| self.jobs.jobs().len().checked_sub(1));
+    }
 
-        let use_ssl = self.pg_config.use_ssl;
-        let file_path_owned = file_path.to_string();
+    /// Show `panel` next to the snapshot list, or the details if it is already shown. Focus moves to the databases
+    /// panel when it opens and back to the snapshot list when it closes.
+    pub fn toggle_side_panel(&mut self, panel: SidePanel) {
+        self.side_panel = if self.side_panel == panel { SidePanel::Details } else { panel };
+        if self.side_panel == SidePanel::Databases {
+            self.focus = FocusField::Databases;
+        } else if self.focus == FocusField::Databases {
+            self.focus = FocusField::SnapshotList;
+        }
+    }
 
-        let pgclient = self.test_pg_connection().await?;
+    /// Connect to the server to manage its databases, without picking one of them
+    async fn pg_client(&self) -> Result<tokio_postgres::Client> {
+        service::validate_pg_config(&self.pg_config)?;
+        self.connect_pg(&self.pg_connection_config(None)).await
+    }
 
-        let new_dbname = format!("{}-restored", random_word::get(Lang::En));
-        let create_restore_db = crate::postgres::create_database(&pgclient.unwrap(), &new_dbname).await;
+    /// List the databases on the server, keeping the selected one selected
+    pub async fn load_databases(&mut self) -> Result<()> {
+        let client = self.pg_client().await?;
+        let selected = self.selected_database().map(|db| db.name.clone());
+        self.databases = postgres::database_info(&client).await?;
+        self.select_database(selected.as_deref().unwrap_or_default());
+        Ok(())
+    }
 
-        match create_restore_db {
-            Ok(_) => {
-                log::info!("Successfully created restore database: {}", new_dbname);
-            },
-            Err(e) => {
-                let error_msg = format!("Failed to create restore database: {}", e);
-                self.popup_state = PopupState::Error(error_msg.clone());
-                return Err(anyhow!(error_msg));
-            }
+    /// Select the database called `name`, otherwise keep the selection within the list
+    fn select_database(&mut self, name: &str) {
+        self.selected_database = match self.databases.iter().position(|db| db.name == name) {
+            Some(idx) => Some(idx),
+            None if self.databases.is_empty() => None,
+            None => Some(self.selected_database.unwrap_or(0).min(self.databases.len() - 1)),
+        };
+    }
+
+    pub fn selected_database(&self) -> Option<&DatabaseInfo> {
+        self.selected_database.and_then(|idx| self.databases.get(idx))
+    }
+
+    pub fn next_database(&mut self) {
+        self.selected_database = match self.selected_database {
+            Some(idx) if idx + 1 < self.databases.len() => Some(idx + 1),
+            None if !self.databases.is_empty() => Some(0),
+            selected => selected,
+        };
+    }
+
+    pub fn previous_database(&mut self) {
+        if let Some(idx) = self.selected_database {
+            self.selected_database = Some(idx.saturating_sub(1));
         }
-        // Spawn a blocking task to handle the restore operation
-        let restore_handle = tokio::task::spawn_blocking(move || {
-            // Call the restore_database function from the backup module
-            let result = crate::backup::restore_database(
-                &new_dbname,
-                &file_path_owned,
-                &host,
-                port,
-                username.as_deref(),
-                password.as_deref(),
-                use_ssl,
-            );
-            result
-        });
+    }
 
-        // Send completion signal (100% progress) in the main async context after restore completes
-        // Update the UI with progress while the restore is running
-        let mut progress = 0.0;
-        // Only update progress bar at the end (no fine-grained progress for pg_restore)
-        while progress < 1.0 {
-            // Check for user input (like ESC key) during restore
-            if crossterm::event::poll(std::time::Duration::from_millis(0)).unwrap_or(false) {
-                if let crossterm::event::Event::Key(key) = crossterm::event::read().unwrap_or(crossterm::event::Event::Key(crossterm::event::KeyEvent::new(crossterm::event::KeyCode::Null, crossterm::event::KeyModifiers::NONE))) {
-                    if key.code == crossterm::event::KeyCode::Esc {
-                        log::debug!("User pressed ESC during restore, but restore cannot be cancelled");
-                        // We don't allow cancelling restore operations as they can leave the database in an inconsistent state
-                    }
-                }
-            }
-            // Update the UI
-            self.popup_state = PopupState::Restoring(snapshot.clone(), progress);
-            terminal.draw(|f| crate::ui::renderer::ui::<B>(f, self))?;
-            tokio::time::sleep(tokio::time::Duration::from_millis(100)).await;
-            // For now, break after a short wait and update progress to 1.0 when done
-            break;
-        }
-        // Wait for the restore operation to complete
-        match restore_handle.await {
-            Ok(inner_result) => {
-                progress = 1.0;
-                self.popup_state = PopupState::Restoring(snapshot.clone(), progress);
-                terminal.draw(|f| crate::ui::renderer::ui::<B>(f, self))?;
-                match inner_result {
-                    Ok(_) => {
-                        log::info!("pg_restore completed successfully");
-                        self.popup_state = PopupState::Success("pg_restore completed successfully".to_string());
-                    },
-                    Err(e) => {
-                        log::error!("pg_restore failed: {}", e);
-                        self.popup_state = PopupState::Error(format!("pg_restore failed: {}", e));
-                        return Err(anyhow!("pg_restore task failed: {}", e));
-                    }
-                }
-            },
-            Err(e) => {
-                log::error!("pg_restore task panicked: {}", e);
-                self.popup_state = PopupState::Error(format!("pg_restore task failed: {}", e));
-                return Err(anyhow!("pg_restore_handler task issues: {}", e));
-            }
+    /// Start creating, renaming or changing the owner of a database with the name typed for it as a background
+    /// job. The databases are reloaded with the result selected once it is done.
+    pub fn start_database_action(&mut self, action: &DatabaseAction, input: &str) {
+        if let Err(e) = service::validate_pg_config(&self.pg_config) {
+            self.popup_state = PopupState::Error(e.to_string());
+            return;
         }
+        self.jobs.spawn_database_action(self.pg_config.clone(), action, input.to_string());
+    }
 
-        // Show final status message
-        terminal.draw(|f| crate::ui::renderer::ui::<B>(f, self))?;
-        tokio::time::sleep(std::time::Duration::from_secs(2)).await;
+    /// Start dropping a confirmed database as a background job
+    pub fn start_drop_database(&mut self, name: &str) {
+        if let Err(e) = service::validate_pg_config(&self.pg_config) {
+            self.popup_state = PopupState::Error(e.to_string());
+            return;
+        }
+        self.jobs.spawn_drop_database(self.pg_config.clone(), name.to_string());
+    }
 
-        Ok(())
+    /// Start cloning the database `name` as a background job
+    pub fn start_clone(&mut self, name: &str) {
+        if let Err(e) = service::validate_pg_config(&self.pg_config) {
+            self.popup_state = PopupState::Error(e.to_string());
+            return;
+        }
+        self.jobs.spawn_clone(self.pg_config.clone(), name.to_string());
     }
 }
 
@@ -541,7 +916,20 @@ pub async fn run_tui(
     };
 
     let pg_config = env_pg_config;
-    let browser = SnapshotBrowser::new(config, pg_config);
+    let mut browser = SnapshotBrowser::new(config, pg_config);
+    browser.download_options = crate::config::load_download_options();
+    browser.stream_restore = crate::config::load_stream_restore();
+    browser.retention = crate::config::load_retention_policy();
+    match crate::config::load_encryption_key(None) {
+        Ok(key) => browser.encryption_key = key,
+        Err(e) => error!("Failed to load encryption key, encrypted snapshots can't be restored: {}", e),
+    }
+    let history = JobHistory::new(crate::config::load_history_path());
+    match history.load(HISTORY_LIMIT) {
+        Ok(entries) => browser.history = entries,
+        Err(e) => error!("Failed to load job history: {:#}", e),
+    }
+    browser.history_file = Some(history);
 
     // Run app
     let res = run_app(&mut terminal, browser).await;
@@ -566,6 +954,10 @@ pub async fn run_app<B: Backend>(terminal: &mut Terminal<B>, mut browser: Snapsh
     }
 
     loop {
+        browser.poll_jobs().await;
+        browser.poll_details();
+        browser.load_selected_manifest();
+
         // Draw UI
         terminal.draw(|f| crate::ui::renderer::ui::<B>(f, &mut browser))?;
 
@@ -576,68 +968,81 @@ pub async fn run_app<B: Backend>(terminal: &mut Terminal<B>, mut browser: Snapsh
                     InputMode::Normal => match key.code {
                         KeyCode::Char('q') => {
                             debug!("User pressed 'q' to quit");
-                            return Ok(None);
+                            match browser.jobs.running() {
+                                0 => return Ok(None),
+                                running => browser.popup_state = PopupState::ConfirmQuit(running),
+                            }
                         },
                         KeyCode::Esc => {
                             match &browser.popup_state {
-                                PopupState::Downloading(snapshot, progress, rate) => {
-                                    debug!("User pressed ESC to cancel download");
-                                    // Show cancel confirmation
-                                    browser.popup_state = PopupState::ConfirmCancel(snapshot.clone(), *progress, *rate);
+                                PopupState::Hidden if browser.focus == FocusField::Jobs => {
+                                    debug!("User pressed ESC to cancel a job");
+                                    browser.confirm_cancel_selected_job();
                                 }
-                                PopupState::ConfirmRestore(_snapshot) => {
+                                PopupState::ConfirmRestore(_) | PopupState::ConfirmPrune(_) | PopupState::ConfirmDelete(_) |
+                                PopupState::ConfirmDropDatabase(_) => {
+                                    browser.popup_state = PopupState::Hidden;
+                                },
+                                PopupState::ConfirmCancelJob(_) | PopupState::ConfirmQuit(_) => {
                                     browser.popup_state = PopupState::Hidden;
                                 },
-                                PopupState::TestS3Result(_) | PopupState::TestPgResult(_) => {
+                                PopupState::TestS3Result(_) | PopupState::TestPgResult(_) | PopupState::Error(_) => {
                                     browser.popup_state = PopupState::Hidden;
                                 }
                                 _ => {}
                             }
                         },
-                        KeyCode::Char('y') if matches!(browser.popup_state, PopupState::ConfirmCancel(..)) => {
-                            debug!("User confirmed download cancel");
-                            // User confirmed cancel
-                            browser.popup_state = PopupState::Hidden;
-                            browser.temp_file = None; // Reset temp file
+                        KeyCode::Char('y') if matches!(browser.popup_state, PopupState::ConfirmCancelJob(_)) => {
+                            if let PopupState::ConfirmCancelJob(id) = std::mem::replace(&mut browser.popup_state, PopupState::Hidden) {
+                                debug!("User confirmed cancel of job #{}", id);
+                                if let Err(e) = browser.jobs.cancel(id) {
+                                    browser.popup_state = PopupState::Error(e.to_string());
+                                }
+                            }
+                        },
+                        KeyCode::Char('y') if matches!(browser.popup_state, PopupState::ConfirmQuit(_)) => {
+                            info!("User confirmed quitting, cancelling {} running jobs", browser.jobs.running());
+                            browser.shutdown_jobs().await;
+                            return Ok(None);
                         },
 
                         KeyCode::Char('y') if matches!(browser.popup_state, PopupState::ConfirmRestore(_)) => {
-                            if let Some(snapshot) = browser.selected_snapshot().cloned() {
+                            if let PopupState::ConfirmRestore(snapshot) = std::mem::replace(&mut browser.popup_state, PopupState::Hidden) {
                                 info!("User confirmed restore of snapshot: {}", snapshot.key);
-                                // Create a persistent file in the system's temp directory
-                                let temp_dir = std::env::temp_dir();
-                                let file_name = format!("pg-backup-{}.sql", chrono::Utc::now().timestamp());
-                                let temp_path = temp_dir.join(file_name);
-                                info!("Creating persistent backup file at: {}", temp_path.display());
-
-                                // Start download
-                                match browser.download_snapshot(&snapshot, terminal, &temp_path).await {
-                                    Ok(Some(downloaded_path)) => {
-                                        // Now that we have the file, start the restore process
-                                        info!("Starting restore process for downloaded file: {}", downloaded_path);
-                                        if let Err(e) = browser.restore_snapshot(&snapshot, terminal, &downloaded_path).await {
-                                            error!("Error during restore: {}", e);
-                                            browser.popup_state = PopupState::Error("pg_restore finished with errors, check postgres_manager.log for details".to_string());
-                                        }
-                                        // Don't exit the program, just continue with the UI loop
-                                        // Store the downloaded path in case we need it later
-                                        browser.temp_file = Some(downloaded_path);
-                                    },
-                                    Ok(None) => {},  // Download was cancelled or failed
-                                    Err(e) => {
-                                        error!("Error during download: {}", e);
-                                        browser.popup_state = PopupState::Error(format!("Download error: {}", e));
-                                    }
+                                browser.start_restore(&snapshot);
+                            }
+                        },
+                        KeyCode::Char('y') if matches!(browser.popup_state, PopupState::ConfirmPrune(_)) => {
+                            if let PopupState::ConfirmPrune(plan) = std::mem::replace(&mut browser.popup_state, PopupState::Hidden) {
+                                info!("User confirmed pruning {} snapshots", plan.delete.len());
+                                if let Err(e) = browser.prune(plan).await {
+                                    error!("Error while pruning snapshots: {}", e);
+                                    browser.popup_state = PopupState::Error(format!("Prune error: {}", e));
+                                }
+                            }
+                        },
+                        KeyCode::Char('y') if matches!(browser.popup_state, PopupState::ConfirmDelete(_)) => {
+                            if let PopupState::ConfirmDelete(snapshot) = std::mem::replace(&mut browser.popup_state, PopupState::Hidden) {
+                                info!("User confirmed delete of snapshot: {}", snapshot.key);
+                                if let Err(e) = browser.delete_snapshot(&snapshot).await {
+                                    error!("Error while deleting snapshot: {}", e);
+                                    browser.popup_state = PopupState::Error(format!("Delete error: {}", e));
                                 }
                             }
                         },
+                        KeyCode::Char('y') if matches!(browser.popup_state, PopupState::ConfirmDropDatabase(_)) => {
+                            if let PopupState::ConfirmDropDatabase(database) = std::mem::replace(&mut browser.popup_state, PopupState::Hidden) {
+                                info!("User confirmed drop of database: {}", database.name);
+                                browser.start_drop_database(&database.name);
+                            }
+                        },
                         KeyCode::Char('n') => match &browser.popup_state {
-                            PopupState::ConfirmCancel(snapshot, progress, rate) => {
-                                debug!("User denied download cancel");
-                                // User denied cancel, resume download
-                                browser.popup_state = PopupState::Downloading(snapshot.clone(), *progress, *rate);
+                            PopupState::ConfirmCancelJob(_) | PopupState::ConfirmQuit(_) => {
+                                debug!("User denied cancelling jobs");
+                                browser.popup_state = PopupState::Hidden;
                             }
-                            PopupState::ConfirmRestore(_snapshot) => {
+                            PopupState::ConfirmRestore(_) | PopupState::ConfirmPrune(_) | PopupState::ConfirmDelete(_) |
+                            PopupState::ConfirmDropDatabase(_) => {
                                 browser.popup_state = PopupState::Hidden;
                             }
                             _ => {
@@ -655,7 +1060,8 @@ pub async fn run_app<B: Backend>(terminal: &mut Terminal<B>, mut browser: Snapsh
                                     }
                                 }
                                 FocusField::PgHost | FocusField::PgPort | FocusField::PgUsername |
-                                FocusField::PgPassword | FocusField::PgSsl | FocusField::PgDbName => {
+                                FocusField::PgPassword | FocusField::PgSsl | FocusField::PgDbName |
+                                FocusField::RestoreTarget | FocusField::Databases => {
                                     if let Err(e) = browser.test_pg_connection().await {
                                         browser.popup_state = PopupState::Error(format!("Error: {}", e));
                                     }
@@ -663,13 +1069,85 @@ pub async fn run_app<B: Backend>(terminal: &mut Terminal<B>, mut browser: Snapsh
                                 _ => {}
                             }
                         },
-                        KeyCode::Enter => {
-                            if browser.focus == FocusField::SnapshotList {
-                                if let Some(snapshot) = browser.selected_snapshot() {
-                                    browser.popup_state = PopupState::ConfirmRestore(snapshot.clone());
+                        KeyCode::Enter if browser.focus == FocusField::SnapshotList => {
+                            if let Some(snapshot) = browser.selected_snapshot() {
+                                browser.popup_state = PopupState::ConfirmRestore(snapshot.clone());
+                            }
+                        },
+                        // Databases panel
+                        KeyCode::Down | KeyCode::Char('j') if browser.focus == FocusField::Databases => browser.next_database(),
+                        KeyCode::Up | KeyCode::Char('k') if browser.focus == FocusField::Databases => browser.previous_database(),
+                        KeyCode::Char('r') if browser.focus == FocusField::Databases && browser.popup_state == PopupState::Hidden => {
+                            debug!("User pressed 'r' to refresh databases");
+                            if let Err(e) = browser.load_databases().await {
+                                browser.popup_state = PopupState::Error(format!("Error: {:#}", e));
+                            }
+                        },
+                        KeyCode::Char('N') if browser.focus == FocusField::Databases && browser.popup_state == PopupState::Hidden => {
+                            debug!("User pressed 'N' to create a database");
+                            browser.input_buffer = String::new();
+                            browser.input_mode = InputMode::Editing;
+                            browser.popup_state = PopupState::EditDatabase(DatabaseAction::Create);
+                        },
+                        KeyCode::Char(c @ ('v' | 'o')) if browser.focus == FocusField::Databases && browser.popup_state == PopupState::Hidden => {
+                            if let Some(database) = browser.selected_database().cloned() {
+                                let action = if c == 'v' {
+                                    browser.input_buffer = database.name.clone();
+                                    DatabaseAction::Rename(database.name)
+                                } else {
+                                    browser.input_buffer = database.owner;
+                                    DatabaseAction::SetOwner(database.name)
+                                };
+                                debug!("User pressed '{}' to {:?}", c, action);
+                                browser.input_mode = InputMode::Editing;
+                                browser.popup_state = PopupState::EditDatabase(action);
+                            }
+                        },
+                        KeyCode::Char('c') if browser.focus == FocusField::Databases && browser.popup_state == PopupState::Hidden => {
+                            if let Some(name) = browser.selected_database().map(|db| db.name.clone()) {
+                                info!("User started clone of database: {}", name);
+                                browser.start_clone(&name);
+                            }
+                        },
+                        KeyCode::Char('d') if browser.focus == FocusField::Databases && browser.popup_state == PopupState::Hidden => {
+                            if let Some(database) = browser.selected_database() {
+                                browser.popup_state = PopupState::ConfirmDropDatabase(database.clone());
+                            }
+                        },
+                        KeyCode::Char('P') if browser.popup_state == PopupState::Hidden => {
+                            debug!("User pressed 'P' to toggle the databases panel");
+                            browser.toggle_side_panel(SidePanel::Databases);
+                            if browser.side_panel == SidePanel::Databases {
+                                if let Err(e) = browser.load_databases().await {
+                                    browser.popup_state = PopupState::Error(format!("Error: {:#}", e));
                                 }
                             }
                         },
+                        KeyCode::Char('o') if browser.popup_state == PopupState::Hidden => {
+                            browser.set_sort(browser.sort.cycle_field());
+                        },
+                        KeyCode::Char('O') if browser.popup_state == PopupState::Hidden => {
+                            browser.set_sort(browser.sort.reversed());
+                        },
+                        KeyCode::Char('/') if browser.popup_state == PopupState::Hidden => {
+                            debug!("User pressed '/' to search snapshots");
+                            browser.input_mode = InputMode::Search;
+                            browser.focus = FocusField::SnapshotList;
+                        },
+                        KeyCode::Char('d') if browser.focus == FocusField::SnapshotList && browser.popup_state == PopupState::Hidden => {
+                            if let Some(snapshot) = browser.selected_snapshot() {
+                                browser.popup_state = PopupState::ConfirmDelete(snapshot.clone());
+                            }
+                        },
+                        KeyCode::Char(c @ ('c' | 'v')) if browser.focus == FocusField::SnapshotList && browser.popup_state == PopupState::Hidden => {
+                            if let Some(snapshot) = browser.selected_snapshot().cloned() {
+                                let action = if c == 'c' { KeyAction::Copy } else { KeyAction::Rename };
+                                debug!("User pressed '{}' to {} snapshot {}", c, action, snapshot.key);
+                                browser.input_buffer = snapshot.key.clone();
+                                browser.input_mode = InputMode::Editing;
+                                browser.popup_state = PopupState::EditKey(snapshot, action);
+                            }
+                        },
                         KeyCode::Tab => {
                             browser.focus = match browser.focus {
                                 FocusField::SnapshotList => FocusField::Bucket,
@@ -687,11 +1165,14 @@ pub async fn run_app<B: Backend>(terminal: &mut Terminal<B>, mut browser: Snapsh
                                 FocusField::PgUsername => FocusField::PgPassword,
                                 FocusField::PgPassword => FocusField::PgSsl,
                                 FocusField::PgSsl => FocusField::PgDbName,
-                                FocusField::PgDbName => FocusField::SnapshotList,
+                                FocusField::PgDbName => FocusField::RestoreTarget,
+                                FocusField::RestoreTarget => FocusField::Jobs,
+                                FocusField::Jobs if browser.side_panel == SidePanel::Databases => FocusField::Databases,
+                                FocusField::Jobs | FocusField::Databases => FocusField::SnapshotList,
                             };
                         },
                         // Edit mode
-                        KeyCode::Char('e') if browser.focus != FocusField::SnapshotList => {
+                        KeyCode::Char('e') if !matches!(browser.focus, FocusField::SnapshotList | FocusField::Jobs | FocusField::Databases) => {
                             browser.input_mode = InputMode::Editing;
                             browser.input_buffer = match browser.focus {
                                 FocusField::SnapshotList => String::new(),
@@ -707,7 +1188,9 @@ pub async fn run_app<B: Backend>(terminal: &mut Terminal<B>, mut browser: Snapsh
                                 FocusField::PgUsername => browser.pg_config.username.clone().unwrap_or_default(),
                                 FocusField::PgPassword => browser.pg_config.password.clone().unwrap_or_default(),
                                 FocusField::PgSsl => browser.pg_config.use_ssl.to_string(),
-                                FocusField::PgDbName => browser.pg_config.db_name.clone().unwrap_or_default()
+                                FocusField::PgDbName => browser.pg_config.db_name.clone().unwrap_or_default(),
+                                FocusField::RestoreTarget => browser.restore_db_name.clone(),
+                                FocusField::Jobs | FocusField::Databases => String::new(),
                             };
                         },
                         // S3 Settings shortcuts
@@ -715,12 +1198,33 @@ pub async fn run_app<B: Backend>(terminal: &mut Terminal<B>, mut browser: Snapsh
                         KeyCode::Char('R') if browser.input_mode == InputMode::Normal => browser.focus = FocusField::Region,
                         KeyCode::Char('x') if browser.input_mode == InputMode::Normal => browser.focus = FocusField::Prefix,
                         // Navigation shortcuts
-                        KeyCode::Down | KeyCode::Char('j') if browser.focus == FocusField::SnapshotList => {
-                            browser.next();
-                        },
+                        KeyCode::Down | KeyCode::Char('j') if browser.focus == FocusField::SnapshotList => browser.next(),
                         KeyCode::Up | KeyCode::Char('k') if browser.focus == FocusField::SnapshotList => {
                             browser.previous();
                         },
+                        KeyCode::PageDown if browser.focus == FocusField::SnapshotList => browser.page_down(),
+                        KeyCode::PageUp if browser.focus == FocusField::SnapshotList => browser.page_up(),
+                        KeyCode::Home if browser.focus == FocusField::SnapshotList => browser.first(),
+                        KeyCode::End if browser.focus == FocusField::SnapshotList => browser.last(),
+                        KeyCode::Down | KeyCode::Char('j') if browser.focus == FocusField::Jobs => browser.next_job(),
+                        KeyCode::Up | KeyCode::Char('k') if browser.focus == FocusField::Jobs => browser.previous_job(),
+                        KeyCode::Char('H') if browser.popup_state == PopupState::Hidden => {
+                            debug!("User pressed 'H' to toggle the job history");
+                            browser.toggle_side_panel(SidePanel::History);
+                        },
+                        KeyCode::Char('J') if browser.popup_state == PopupState::Hidden => {
+                            debug!("User pressed 'J' to focus the jobs panel");
+                            browser.focus = FocusField::Jobs;
+                        },
+                        KeyCode::Char('C') if browser.focus == FocusField::Jobs && browser.popup_state == PopupState::Hidden => {
+                            browser.clear_finished_jobs();
+                        },
+                        KeyCode::Char('D') if browser.focus == FocusField::SnapshotList && browser.popup_state == PopupState::Hidden => {
+                            if let Some(snapshot) = browser.selected_snapshot().cloned() {
+                                info!("User started download of snapshot: {}", snapshot.key);
+                                browser.start_download(&snapshot);
+                            }
+                        },
                         // S3 Settings shortcuts
                         KeyCode::Char('E') if browser.input_mode == InputMode::Normal => {
                             browser.focus = FocusField::EndpointUrl;
@@ -747,6 +1251,23 @@ pub async fn run_app<B: Backend>(terminal: &mut Terminal<B>, mut browser: Snapsh
                         KeyCode::Char('l') if browser.input_mode == InputMode::Normal => {
                             browser.focus = FocusField::PgSsl;
                         },
+                        KeyCode::Char('m') => {
+                            debug!("User pressed 'm' to change restore target");
+                            browser.restore_mode = browser.restore_mode.next();
+                            if browser.popup_state == PopupState::Hidden {
+                                browser.focus = FocusField::RestoreTarget;
+                            }
+                        },
+                        KeyCode::Char('w') => {
+                            debug!("User pressed 'w' to toggle streamed restores");
+                            browser.stream_restore = !browser.stream_restore;
+                        },
+                        KeyCode::Char('X') if browser.popup_state == PopupState::Hidden => {
+                            debug!("User pressed 'X' to prune snapshots");
+                            if let Err(e) = browser.plan_prune().await {
+                                browser.popup_state = PopupState::Error(format!("Error: {}", e));
+                            }
+                        },
                         // State management
 
                         KeyCode::Char('r') => {
@@ -772,13 +1293,33 @@ pub async fn run_app<B: Backend>(terminal: &mut Terminal<B>, mut browser: Snapsh
                                 FocusField::PgUsername => browser.pg_config.username.clone().unwrap_or_default(),
                                 FocusField::PgPassword => browser.pg_config.password.clone().unwrap_or_default(),
                                 FocusField::PgSsl => browser.pg_config.use_ssl.to_string(),
-                                FocusField::PgDbName => browser.pg_config.db_name.clone().unwrap_or_default()
+                                FocusField::PgDbName => browser.pg_config.db_name.clone().unwrap_or_default(),
+                                FocusField::RestoreTarget => browser.restore_db_name.clone(),
+                                FocusField::Jobs | FocusField::Databases => String::new(),
                             };
                         },
                         // Handle any unmatched key
                         _ => {}
                     },
                     InputMode::Editing => match key.code {
+                        KeyCode::Enter if matches!(browser.popup_state, PopupState::EditKey(..)) => {
+                            browser.input_mode = InputMode::Normal;
+                            if let PopupState::EditKey(snapshot, action) = std::mem::replace(&mut browser.popup_state, PopupState::Hidden) {
+                                let new_key = browser.input_buffer.trim().to_string();
+                                if let Err(e) = browser.copy_snapshot(&snapshot, &new_key, action).await {
+                                    error!("Error during {} of {}: {}", action, snapshot.key, e);
+                                    browser.popup_state = PopupState::Error(format!("{} error: {}", action, e));
+                                }
+                            }
+                        },
+                        KeyCode::Enter if matches!(browser.popup_state, PopupState::EditDatabase(_)) => {
+                            browser.input_mode = InputMode::Normal;
+                            if let PopupState::EditDatabase(action) = std::mem::replace(&mut browser.popup_state, PopupState::Hidden) {
+                                let input = browser.input_buffer.trim().to_string();
+                                info!("User confirmed {} with '{}'", action, input);
+                                browser.start_database_action(&action, &input);
+                            }
+                        },
                         KeyCode::Enter => {
                             if browser.focus == FocusField::SnapshotList {
                                 if let Some(snapshot) = browser.selected_snapshot() {
@@ -816,6 +1357,11 @@ pub async fn run_app<B: Backend>(terminal: &mut Terminal<B>, mut browser: Snapsh
                                         browser.pg_config.use_ssl = browser.input_buffer.to_lowercase() == "true";
                                     },
                                     FocusField::PgDbName => browser.pg_config.db_name = Some(browser.input_buffer.clone()),
+                                    FocusField::RestoreTarget => {
+                                        // Naming a database only makes sense when restoring into a new one
+                                        browser.restore_db_name = browser.input_buffer.clone();
+                                        browser.restore_mode = RestoreMode::New;
+                                    },
                                     _ => {}
                                 }
 
@@ -838,9 +1384,35 @@ pub async fn run_app<B: Backend>(terminal: &mut Terminal<B>, mut browser: Snapsh
                         },
                         KeyCode::Esc => {
                             browser.input_mode = InputMode::Normal;
+                            if matches!(browser.popup_state, PopupState::EditKey(..) | PopupState::EditDatabase(_)) {
+                                browser.popup_state = PopupState::Hidden;
+                            }
                         },
                         _ => {}
                     },
+                    InputMode::Search => {
+                        match key.code {
+                            // Keep the filter and go back to browsing it
+                            KeyCode::Enter => browser.input_mode = InputMode::Normal,
+                            KeyCode::Esc => {
+                                browser.input_mode = InputMode::Normal;
+                                browser.set_search(String::new());
+                            },
+                            KeyCode::Down => browser.next(),
+                            KeyCode::Up => browser.previous(),
+                            KeyCode::Char(c) => {
+                                let mut search = browser.search.clone();
+                                search.push(c);
+                                browser.set_search(search);
+                            },
+                            KeyCode::Backspace => {
+                                let mut search = browser.search.clone();
+                                search.pop();
+                                browser.set_search(search);
+                            },
+                            _ => {}
+                        }
+                    },
                 }
             }
         }
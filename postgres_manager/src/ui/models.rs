@@ -2,6 +2,10 @@ use aws_sdk_s3::primitives::DateTime as AwsDateTime;
 use std::fmt;
 use std::fmt::Debug;
 
+use crate::jobs::JobId;
+use crate::postgres::DatabaseInfo;
+use crate::retention::PrunePlan;
+
 /// Configuration for S3 connection
 #[derive(Clone, Debug)]
 pub struct S3Config {
@@ -42,6 +46,10 @@ pub struct PostgresConfig {
     pub username: Option<String>,
     pub password: Option<String>,
     pub use_ssl: bool,
+    /// Check the server's certificate when connecting over SSL
+    pub verify_ssl: bool,
+    /// PEM file with extra root certificates to trust when verifying
+    pub root_cert_path: Option<String>,
     pub db_name: Option<String>,
 }
 
@@ -50,6 +58,8 @@ pub struct PostgresConfig {
 pub enum InputMode {
     Normal,
     Editing,
+    /// Typing a search that filters the snapshot list
+    Search,
 }
 
 /// Metadata for a backup
@@ -60,14 +70,64 @@ pub struct BackupMetadata {
     pub last_modified: AwsDateTime,
 }
 
+/// What happens to a snapshot given a new key
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum KeyAction {
+    Copy,
+    Rename,
+}
+
+impl fmt::Display for KeyAction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            KeyAction::Copy => write!(f, "Copy"),
+            KeyAction::Rename => write!(f, "Rename"),
+        }
+    }
+}
+
+/// A change to a database that needs a name typed first
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DatabaseAction {
+    Create,
+    /// Rename the database
+    Rename(String),
+    /// Change the owner of the database
+    SetOwner(String),
+}
+
+impl fmt::Display for DatabaseAction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DatabaseAction::Create => write!(f, "Create Database"),
+            DatabaseAction::Rename(_) => write!(f, "Rename Database"),
+            DatabaseAction::SetOwner(_) => write!(f, "Change Owner"),
+        }
+    }
+}
+
+/// What the pane next to the snapshot list shows
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SidePanel {
+    /// Manifest of the selected snapshot
+    #[default]
+    Details,
+    History,
+    Databases,
+}
+
 /// State of the popup
 #[derive(Debug, PartialEq)]
 pub enum PopupState {
     Hidden,
     ConfirmRestore(BackupMetadata),
-    Downloading(BackupMetadata, f32, f64),
-    ConfirmCancel(BackupMetadata, f32, f64),
-    Restoring(BackupMetadata, f32),  // Snapshot being restored, progress percentage
+    ConfirmCancelJob(JobId),
+    ConfirmQuit(usize),  // Number of jobs still running
+    ConfirmPrune(PrunePlan),
+    ConfirmDelete(BackupMetadata),
+    EditKey(BackupMetadata, KeyAction),  // Snapshot being copied or renamed, the new key is in the input buffer
+    EditDatabase(DatabaseAction),  // The new name or owner is in the input buffer
+    ConfirmDropDatabase(DatabaseInfo),
     TestS3Result(String),
     TestPgResult(String),
     Error(String),
@@ -93,7 +153,10 @@ pub enum FocusField {
     PgPassword,      // Alt+r
     PgSsl,          // Alt+t
     PgDbName,        // Alt+y
+    RestoreTarget,
     SnapshotList,
+    Jobs,
+    Databases,
 }
 
 impl fmt::Display for FocusField {
@@ -112,7 +175,10 @@ impl fmt::Display for FocusField {
             FocusField::PgPassword => write!(f, "PostgreSQL Password"),
             FocusField::PgSsl => write!(f, "PostgreSQL SSL"),
             FocusField::PgDbName => write!(f, "PostgreSQL Database"),
+            FocusField::RestoreTarget => write!(f, "Restore Target"),
             FocusField::SnapshotList => write!(f, "Snapshot List"),
+            FocusField::Jobs => write!(f, "Jobs"),
+            FocusField::Databases => write!(f, "Databases"),
         }
     }
 }
@@ -3,12 +3,15 @@ use ratatui::{
     layout::{Constraint, Direction, Layout, Alignment, Rect},
     style::{Color, Modifier, Style},
     text::{Span, Line},
-    widgets::{Block, Borders, List, ListItem, Paragraph, Clear},
+    widgets::{Block, Borders, List, ListItem, ListState, Paragraph, Clear, Wrap},
     Frame,
 };
 use chrono::{DateTime, Utc};
 
-use crate::ui::models::{FocusField, PopupState};
+use crate::backup::RestoreMode;
+use crate::history::HistoryState;
+use crate::jobs::{JobKind, JobStatus};
+use crate::ui::models::{DatabaseAction, FocusField, PopupState, SidePanel};
 use crate::ui::browser::SnapshotBrowser;
 
 /// Helper function to create a centered rect
@@ -38,6 +41,98 @@ pub fn centered_rect(percent_x: u16, height: u16, r: Rect) -> Rect {
         .split(popup_layout[1])[1]
 }
 
+/// Describe where a restore will write to with the current settings
+fn restore_target_label(browser: &SnapshotBrowser) -> String {
+    match browser.restore_target() {
+        Some(target) => format!("{} '{}'", browser.restore_mode, target),
+        None => format!("{} (no database name set)", browser.restore_mode),
+    }
+}
+
+/// Lines of the details pane for the selected snapshot
+fn snapshot_details(browser: &SnapshotBrowser) -> Vec<Line<'static>> {
+    let Some(snapshot) = browser.selected_snapshot() else {
+        return vec![Line::from("No snapshot selected")];
+    };
+
+    let field = |name: &str, value: String| Line::from(vec![
+        Span::styled(format!("{}: ", name), Style::default().add_modifier(Modifier::BOLD)),
+        Span::raw(value),
+    ]);
+    let modified: DateTime<Utc> = DateTime::from_timestamp(snapshot.last_modified.secs(), 0).unwrap_or_default();
+    let mut lines = vec![
+        field("Key", snapshot.key.clone()),
+        field("Size", humansize::format_size(snapshot.size.max(0) as u64, humansize::DECIMAL)),
+        field("Modified", modified.format("%Y-%m-%d %H:%M:%S").to_string()),
+        Line::from(""),
+    ];
+
+    match browser.manifests.get(&snapshot.key) {
+        Some(Some(manifest)) => lines.extend([
+            field("Database", format!("{} on {}:{}", manifest.database, manifest.host, manifest.port)),
+            field("Server", manifest.server_version.clone().unwrap_or_else(|| "unknown".to_string())),
+            field("pg_dump", manifest.pg_dump_version.clone().unwrap_or_else(|| "unknown".to_string())),
+            field("Format", manifest.format.clone()),
+            field("Compression", manifest.compression.clone()),
+            field("Encrypted", if manifest.encrypted { "yes" } else { "no" }.to_string()),
+            field("SHA-256", manifest.sha256.clone()),
+            field("Started", manifest.started_at.clone()),
+            field("Duration", format!("{:.1}s", manifest.duration_secs)),
+        ]),
+        Some(None) => lines.push(Line::from(Span::styled("No manifest", Style::default().fg(Color::Gray)))),
+        None => lines.push(Line::from(Span::styled("Loading manifest...", Style::default().fg(Color::Gray)))),
+    }
+    lines
+}
+
+/// Databases panel with a header row above the list, columns padded to the longest name and owner
+fn render_databases(f: &mut Frame, browser: &SnapshotBrowser, area: Rect) {
+    let style = if browser.focus == FocusField::Databases {
+        Style::default().fg(Color::Yellow)
+    } else {
+        Style::default()
+    };
+    let block = Block::default()
+        .title(format!("Databases ({})", browser.databases.len()))
+        .borders(Borders::ALL)
+        .style(style);
+    let inner = block.inner(area);
+    f.render_widget(block, area);
+
+    let rows = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([Constraint::Length(1), Constraint::Min(0)].as_ref())
+        .split(inner);
+
+    let name_width = browser.databases.iter().map(|db| db.name.len()).max().unwrap_or(0).max("NAME".len());
+    let owner_width = browser.databases.iter().map(|db| db.owner.len()).max().unwrap_or(0).max("OWNER".len());
+    let row = |name: &str, owner: &str, size: &str, connections: &str| {
+        format!("{:<name_width$}  {:<owner_width$}  {:>10}  {:>5}", name, owner, size, connections)
+    };
+
+    // Indented past the highlight symbol so the header lines up with the rows
+    let header = Paragraph::new(format!("  {}", row("NAME", "OWNER", "SIZE", "CONNS")))
+        .style(Style::default().add_modifier(Modifier::BOLD));
+    f.render_widget(header, rows[0]);
+
+    let items: Vec<ListItem> = browser.databases
+        .iter()
+        .map(|db| {
+            let size = db.size
+                .map(|size| humansize::format_size(size.max(0) as u64, humansize::DECIMAL))
+                .unwrap_or_else(|| "-".to_string());
+            // Databases with sessions can't be cloned, and dropping them disconnects those sessions
+            let color = if db.connections > 0 { Color::Cyan } else { Color::Reset };
+            ListItem::new(Line::from(vec![Span::styled(row(&db.name, &db.owner, &size, &db.connections.to_string()), Style::default().fg(color))]))
+        })
+        .collect();
+    let list = List::new(items)
+        .highlight_style(Style::default().fg(Color::Green).add_modifier(Modifier::BOLD))
+        .highlight_symbol("> ");
+    let mut state = ListState::default().with_selected(browser.selected_database);
+    f.render_stateful_widget(list, rows[1], &mut state);
+}
+
 /// Render the UI
 pub fn ui<B: Backend>(f: &mut Frame, browser: &mut SnapshotBrowser) {
     // We'll handle the editing mode overlay at the end to ensure it doesn't hide the UI
@@ -48,7 +143,7 @@ pub fn ui<B: Backend>(f: &mut Frame, browser: &mut SnapshotBrowser) {
             [
                 Constraint::Length(3),  // Title
                 Constraint::Length(9),  // S3 Settings
-                Constraint::Length(8),  // PostgreSQL Settings
+                Constraint::Length(9),  // PostgreSQL Settings
                 Constraint::Min(10),    // Snapshot List
             ]
             .as_ref(),
@@ -241,6 +336,7 @@ pub fn ui<B: Backend>(f: &mut Frame, browser: &mut SnapshotBrowser) {
                 Constraint::Length(1),
                 Constraint::Length(1),
                 Constraint::Length(1),
+                Constraint::Length(1),
             ]
             .as_ref(),
         )
@@ -312,18 +408,44 @@ pub fn ui<B: Backend>(f: &mut Frame, browser: &mut SnapshotBrowser) {
         .style(db_style);
     f.render_widget(db, pg_settings_chunks[5]);
 
+    // Restore Target
+    let restore_target_style = if browser.focus == FocusField::RestoreTarget {
+        Style::default().fg(Color::Yellow)
+    } else {
+        Style::default()
+    };
+    let restore_target_text = format!("Restore Target: {}", restore_target_label(browser));
+    let restore_target = Paragraph::new(restore_target_text)
+        .style(restore_target_style);
+    f.render_widget(restore_target, pg_settings_chunks[6]);
+
     // Snapshot List
     let snapshot_style = if browser.focus == FocusField::SnapshotList {
         Style::default().fg(Color::Yellow)
     } else {
         Style::default()
     };
+    let visible = browser.visible_snapshots();
+    let searching = browser.input_mode == crate::ui::models::InputMode::Search;
+    let arrow = if browser.sort.descending { "↓" } else { "↑" };
+    let mut snapshot_title = if browser.has_more_snapshots() {
+        format!("Snapshots by {} {} (loading, {} so far)", browser.sort.field, arrow, browser.snapshots.len())
+    } else {
+        format!("Snapshots by {} {}", browser.sort.field, arrow)
+    };
+    if searching || !browser.filter.is_empty() {
+        snapshot_title = format!("{} - {} match /{}{}", snapshot_title, visible.len(), browser.search, if searching { "_" } else { "" });
+    }
+    let mut title = vec![Span::raw(snapshot_title)];
+    if let Some(error) = &browser.search_error {
+        title.push(Span::styled(format!(" ({})", error), Style::default().fg(Color::Red)));
+    }
     let snapshot_block = Block::default()
-        .title("Snapshots")
+        .title(Line::from(title))
         .borders(Borders::ALL)
         .style(snapshot_style);
 
-    let snapshot_items: Vec<ListItem> = browser.snapshots
+    let snapshot_items: Vec<ListItem> = visible
         .iter()
         .enumerate()
         .map(|(i, snapshot)| {
@@ -332,7 +454,8 @@ pub fn ui<B: Backend>(f: &mut Frame, browser: &mut SnapshotBrowser) {
             let dt: DateTime<Utc> = DateTime::from_timestamp(timestamp as i64, 0).unwrap_or_default();
             let formatted_date = dt.format("%Y-%m-%d %H:%M:%S").to_string();
             let size_mb = snapshot.size as f64 / 1024.0 / 1024.0;
-            let content = format!("{} - {:.2} MB - {}", snapshot.key, size_mb, formatted_date);
+            let lock = if browser.is_encrypted(snapshot) { "🔒 " } else { "" };
+            let content = format!("{}{} - {:.2} MB - {}", lock, snapshot.key, size_mb, formatted_date);
             let style = if Some(i) == browser.selected_idx {
                 Style::default().fg(Color::Green).add_modifier(Modifier::BOLD)
             } else {
@@ -342,14 +465,92 @@ pub fn ui<B: Backend>(f: &mut Frame, browser: &mut SnapshotBrowser) {
         })
         .collect();
 
+    // The jobs panel takes the bottom of the snapshot area once there is something to show in it
+    let jobs = browser.jobs.jobs();
+    let jobs_height = if jobs.is_empty() && browser.focus != FocusField::Jobs { 0 } else { jobs.len().clamp(1, 5) as u16 + 2 };
+    let list_chunks = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([Constraint::Min(5), Constraint::Length(jobs_height)].as_ref())
+        .split(chunks[3]);
+
+    let snapshot_chunks = Layout::default()
+        .direction(Direction::Horizontal)
+        .constraints([Constraint::Percentage(65), Constraint::Percentage(35)].as_ref())
+        .split(list_chunks[0]);
+
     let snapshot_list = List::new(snapshot_items)
         .block(snapshot_block);
-    f.render_widget(snapshot_list, chunks[3]);
+    f.render_widget(snapshot_list, snapshot_chunks[0]);
+
+    match browser.side_panel {
+        SidePanel::Details => {
+            let details = Paragraph::new(snapshot_details(browser))
+                .block(Block::default().title("Details").borders(Borders::ALL))
+                .wrap(Wrap { trim: true });
+            f.render_widget(details, snapshot_chunks[1]);
+        }
+        SidePanel::History => {
+            let history_items: Vec<ListItem> = if browser.history.is_empty() {
+                vec![ListItem::new("No finished jobs yet")]
+            } else {
+                browser.history
+                    .iter()
+                    .map(|entry| {
+                        let color = match entry.state {
+                            HistoryState::Succeeded => Color::Reset,
+                            HistoryState::Failed => Color::Red,
+                            HistoryState::Cancelled => Color::Gray,
+                        };
+                        ListItem::new(Line::from(vec![Span::styled(entry.to_string(), Style::default().fg(color))]))
+                    })
+                    .collect()
+            };
+            let history = List::new(history_items)
+                .block(Block::default().title("History").borders(Borders::ALL));
+            f.render_widget(history, snapshot_chunks[1]);
+        }
+        SidePanel::Databases => render_databases(f, browser, snapshot_chunks[1]),
+    }
+
+    if jobs_height > 0 {
+        let jobs_style = if browser.focus == FocusField::Jobs {
+            Style::default().fg(Color::Yellow)
+        } else {
+            Style::default()
+        };
+        let job_items: Vec<ListItem> = if jobs.is_empty() {
+            vec![ListItem::new("No jobs. Press 'D' on a snapshot to download it, Enter to restore it")]
+        } else {
+            jobs.iter()
+                .map(|job| {
+                    let color = match job.status {
+                        JobStatus::Running => Color::Reset,
+                        JobStatus::Succeeded(_) => Color::Green,
+                        JobStatus::Failed(_) => Color::Red,
+                        JobStatus::Cancelled(_) => Color::Gray,
+                    };
+                    ListItem::new(Line::from(vec![Span::styled(job.summary(), Style::default().fg(color))]))
+                })
+                .collect()
+        };
+        let jobs_list = List::new(job_items)
+            .block(Block::default()
+                .title(format!("Jobs ({} running)", browser.jobs.running()))
+                .borders(Borders::ALL)
+                .style(jobs_style))
+            .highlight_style(Style::default().add_modifier(Modifier::BOLD))
+            .highlight_symbol("> ");
+        let mut jobs_state = ListState::default().with_selected(browser.selected_job);
+        f.render_stateful_widget(jobs_list, list_chunks[1], &mut jobs_state);
+    }
 
     // Show help text at the bottom
     let help_text = match browser.input_mode {
-        crate::ui::models::InputMode::Normal => "Press 'q' to quit, 'e' to edit, 't' to test connection, 'r' to refresh, Enter to select",
+        crate::ui::models::InputMode::Normal if browser.focus == FocusField::Databases => "Up/Down to select a database, 'N' to create, 'c' to clone, 'v' to rename, 'o' to change owner, 'd' to drop, 'r' to refresh, 'P' to close, 'q' to quit",
+        crate::ui::models::InputMode::Normal if browser.focus == FocusField::Jobs => "Up/Down to select a job, Esc to cancel it, 'C' to clear finished jobs, 'H' for history, Tab to move on, 'q' to quit",
+        crate::ui::models::InputMode::Normal => "Press 'q' to quit, 'e' to edit, 't' to test connection, 'r' to refresh, 'm' to change restore target, 'd' to delete, 'c'/'v' to copy/rename, 'X' to prune, '/' to search, 'o'/'O' to sort/reverse, 'D' to download, 'J' for jobs, 'H' for history, 'P' for databases, Enter to select",
         crate::ui::models::InputMode::Editing => "Press Esc to cancel, Enter to save",
+        crate::ui::models::InputMode::Search => "Type to filter: words match keys fuzzily, db:<name>, since:/until:<YYYY-MM-DD>, >/< size like 100MB. Enter to keep, Esc to clear",
     };
     let help_paragraph = Paragraph::new(help_text)
         .style(Style::default().fg(Color::Gray))
@@ -368,48 +569,163 @@ pub fn ui<B: Backend>(f: &mut Frame, browser: &mut SnapshotBrowser) {
     // Show popup if needed - render last to ensure they're on top
     match &browser.popup_state {
         PopupState::ConfirmRestore(snapshot) => {
-            let area = centered_rect(60, 5, f.size());
+            let area = centered_rect(60, 9, f.size());
             // Clear the area where the popup will be rendered
             f.render_widget(ratatui::widgets::Clear, area);
+            let method = if browser.stream_restore { "Stream from S3, no temp file" } else { "Download, then restore" };
+            let warning = if browser.restore_mode == RestoreMode::Existing {
+                Line::from(Span::styled("Objects already in the target database are dropped and overwritten", Style::default().fg(Color::Red)))
+            } else {
+                Line::from(vec![])
+            };
             let popup = Paragraph::new(vec![
                 Line::from(vec![Span::raw(format!("Are you sure you want to restore this backup '{}'?", snapshot.key))]),
-                Line::from(vec![]),
+                Line::from(vec![Span::raw(format!("Target: {}", restore_target_label(browser)))]),
+                Line::from(vec![Span::raw(format!("Method: {}", method))]),
+                warning,
                 Line::from(vec![Span::raw("Press 'y' to confirm, 'n' to cancel")]),
+                Line::from(vec![Span::raw("'m' to change target, 'w' to change method")]),
             ])
             .block(Block::default().title("Confirm Restore").borders(Borders::ALL))
             .alignment(Alignment::Center);
             f.render_widget(popup, area);
         }
-        PopupState::Downloading(snapshot, progress, rate) => {
-            let area = centered_rect(60, 5, f.size());
+        PopupState::ConfirmDelete(snapshot) => {
+            let area = centered_rect(60, 6, f.size());
             // Clear the area where the popup will be rendered
             f.render_widget(ratatui::widgets::Clear, area);
-            let rate_mb = *rate / 1024.0 / 1024.0;
             let popup = Paragraph::new(vec![
-                Line::from(vec![Span::raw(format!("Downloading: {}", snapshot.key))]),
-                Line::from(vec![Span::raw(format!("Progress: {:.1}% ({:.2} MB/s)", *progress * 100.0, rate_mb))]),
+                Line::from(vec![Span::raw(format!("Are you sure you want to delete this backup '{}'?", snapshot.key))]),
+                Line::from(vec![Span::raw("The object and its checksum sidecar are removed from S3")]),
                 Line::from(vec![]),
-                Line::from(vec![Span::raw("Press Esc to cancel")]),
+                Line::from(vec![Span::raw("Press 'y' to confirm, 'n' to cancel")]),
             ])
-            .block(Block::default().title("Downloading").borders(Borders::ALL))
+            .block(Block::default().title("Confirm Delete").borders(Borders::ALL))
             .alignment(Alignment::Center);
             f.render_widget(popup, area);
         }
-        PopupState::ConfirmCancel(snapshot, progress, rate) => {
-            let area = centered_rect(60, 5, f.size());
+        PopupState::EditKey(snapshot, action) => {
+            let area = centered_rect(60, 6, f.size());
             // Clear the area where the popup will be rendered
             f.render_widget(ratatui::widgets::Clear, area);
-            let rate_mb = *rate / 1024.0 / 1024.0;
             let popup = Paragraph::new(vec![
-                Line::from(vec![Span::raw(format!("Cancel download of: {}", snapshot.key))]),
-                Line::from(vec![Span::raw(format!("Progress: {:.1}% ({:.2} MB/s)", *progress * 100.0, rate_mb))]),
+                Line::from(vec![Span::raw(format!("From: {}", snapshot.key))]),
+                Line::from(vec![Span::styled(
+                    format!("To: {}", browser.input_buffer),
+                    Style::default().fg(Color::Yellow),
+                )]),
+                Line::from(vec![]),
+                Line::from(vec![Span::raw("Press Enter to save, Esc to cancel")]),
+            ])
+            .block(Block::default().title(format!("{} Snapshot", action)).borders(Borders::ALL))
+            .alignment(Alignment::Center);
+            f.render_widget(popup, area);
+        }
+        PopupState::EditDatabase(action) => {
+            let area = centered_rect(60, 6, f.size());
+            // Clear the area where the popup will be rendered
+            f.render_widget(ratatui::widgets::Clear, area);
+            let (from, label) = match action {
+                DatabaseAction::Create => (String::new(), "Name"),
+                DatabaseAction::Rename(name) => (format!("Database: {}", name), "New name"),
+                DatabaseAction::SetOwner(name) => (format!("Database: {}", name), "New owner"),
+            };
+            let popup = Paragraph::new(vec![
+                Line::from(vec![Span::raw(from)]),
+                Line::from(vec![Span::styled(
+                    format!("{}: {}", label, browser.input_buffer),
+                    Style::default().fg(Color::Yellow),
+                )]),
+                Line::from(vec![]),
+                Line::from(vec![Span::raw("Press Enter to save, Esc to cancel")]),
+            ])
+            .block(Block::default().title(action.to_string()).borders(Borders::ALL))
+            .alignment(Alignment::Center);
+            f.render_widget(popup, area);
+        }
+        PopupState::ConfirmDropDatabase(database) => {
+            let area = centered_rect(60, 6, f.size());
+            // Clear the area where the popup will be rendered
+            f.render_widget(ratatui::widgets::Clear, area);
+            let sessions = match database.connections {
+                0 => "Nobody is connected to it".to_string(),
+                n => format!("Its {} connected sessions will be terminated", n),
+            };
+            let popup = Paragraph::new(vec![
+                Line::from(vec![Span::raw(format!("Are you sure you want to drop the database '{}'?", database.name))]),
+                Line::from(vec![Span::raw(sessions)]),
+                Line::from(vec![]),
+                Line::from(vec![Span::raw("Press 'y' to confirm, 'n' to cancel")]),
+            ])
+            .block(Block::default().title("Confirm Drop").borders(Borders::ALL))
+            .alignment(Alignment::Center);
+            f.render_widget(popup, area);
+        }
+        PopupState::ConfirmCancelJob(id) => {
+            let area = centered_rect(60, 6, f.size());
+            // Clear the area where the popup will be rendered
+            f.render_widget(ratatui::widgets::Clear, area);
+            let (title, consequence) = match browser.jobs.get(*id) {
+                Some(job) => {
+                    let consequence = match (&job.kind, job.stage_label()) {
+                        (JobKind::Restore { .. }, "restoring") => "The partially restored database will be dropped",
+                        _ => "A partial download is kept so it can be resumed",
+                    };
+                    (format!("Cancel {} of: {}", job.kind.name(), job.subject), consequence)
+                }
+                None => (format!("Cancel job #{}", id), ""),
+            };
+            let popup = Paragraph::new(vec![
+                Line::from(vec![Span::raw(title)]),
+                Line::from(vec![Span::raw(consequence)]),
                 Line::from(vec![]),
-                Line::from(vec![Span::raw("Press 'y' to confirm cancel, 'n' to continue downloading")]),
+                Line::from(vec![Span::raw("Press 'y' to confirm cancel, 'n' to keep it running")]),
             ])
             .block(Block::default().title("Confirm Cancel").borders(Borders::ALL))
             .alignment(Alignment::Center);
             f.render_widget(popup, area);
         }
+        PopupState::ConfirmQuit(running) => {
+            let area = centered_rect(60, 6, f.size());
+            // Clear the area where the popup will be rendered
+            f.render_widget(ratatui::widgets::Clear, area);
+            let popup = Paragraph::new(vec![
+                Line::from(vec![Span::raw(format!("{} jobs are still running", running))]),
+                Line::from(vec![Span::raw("Quitting cancels them and waits for them to stop")]),
+                Line::from(vec![]),
+                Line::from(vec![Span::raw("Press 'y' to quit, 'n' to stay")]),
+            ])
+            .block(Block::default().title("Confirm Quit").borders(Borders::ALL))
+            .alignment(Alignment::Center);
+            f.render_widget(popup, area);
+        }
+        PopupState::ConfirmPrune(plan) => {
+            // Show as many of the doomed snapshots as fit, the rest are summarised
+            const LISTED: usize = 8;
+            let area = centered_rect(70, LISTED as u16 + 7, f.size());
+            // Clear the area where the popup will be rendered
+            f.render_widget(ratatui::widgets::Clear, area);
+            let mut lines = vec![
+                Line::from(vec![Span::raw(format!(
+                    "Keeping {} ({}), this deletes {} snapshots ({}):",
+                    plan.keep.len(),
+                    browser.retention,
+                    plan.delete.len(),
+                    humansize::format_size(plan.delete_size(), humansize::DECIMAL),
+                ))]),
+                Line::from(vec![]),
+            ];
+            lines.extend(plan.delete.iter().take(LISTED).map(|s| Line::from(vec![Span::raw(s.key.clone())])));
+            if plan.delete.len() > LISTED {
+                lines.push(Line::from(vec![Span::raw(format!("... and {} more", plan.delete.len() - LISTED))]));
+            }
+            lines.push(Line::from(vec![]));
+            lines.push(Line::from(vec![Span::raw("Press 'y' to delete them, 'n' to cancel")]));
+            let popup = Paragraph::new(lines)
+                .block(Block::default().title("Confirm Prune").borders(Borders::ALL))
+                .alignment(Alignment::Center);
+            f.render_widget(popup, area);
+        }
         PopupState::Error(message) => {
             let area = centered_rect(60, 5, f.size());
             // Clear the area where the popup will be rendered
@@ -476,38 +792,6 @@ pub fn ui<B: Backend>(f: &mut Frame, browser: &mut SnapshotBrowser) {
             .alignment(Alignment::Center);
             f.render_widget(popup, area);
         }
-        PopupState::Restoring(snapshot, progress) => {
-            let area = centered_rect(60, 7, f.size());
-            // Clear the area where the popup will be rendered
-            f.render_widget(ratatui::widgets::Clear, area);
-            
-            // Create a progress bar
-            let _progress_percent = (*progress * 100.0) as u16;
-            let progress_bar_width = 50;
-            let filled_width = (progress_bar_width as f32 * *progress) as usize;
-            let empty_width = progress_bar_width as usize - filled_width;
-            
-            let progress_bar = format!(
-                "[{}{}] {:.1}%",
-                "=".repeat(filled_width),
-                " ".repeat(empty_width),
-                *progress * 100.0
-            );
-            
-            let popup = Paragraph::new(vec![
-                Line::from(vec![Span::raw(format!("Restoring database from: {}", snapshot.key))]),
-                Line::from(vec![]),
-                Line::from(vec![Span::styled(
-                    progress_bar,
-                    Style::default().fg(Color::Green).add_modifier(Modifier::BOLD),
-                )]),
-                Line::from(vec![]),
-                Line::from(vec![Span::raw("This operation cannot be cancelled")]),
-            ])
-            .block(Block::default().title("Restoring Database").borders(Borders::ALL))
-            .alignment(Alignment::Center);
-            f.render_widget(popup, area);
-        }
         _ => {}
     }
 
@@ -537,6 +821,7 @@ pub fn ui<B: Backend>(f: &mut Frame, browser: &mut SnapshotBrowser) {
             FocusField::PgPassword => pg_settings_chunks[3],
             FocusField::PgSsl => pg_settings_chunks[4],
             FocusField::PgDbName => pg_settings_chunks[5],
+            FocusField::RestoreTarget => pg_settings_chunks[6],
             _ => return,
         };
 
@@ -566,6 +851,7 @@ pub fn ui<B: Backend>(f: &mut Frame, browser: &mut SnapshotBrowser) {
             FocusField::PgPassword => browser.input_buffer.clone(),
             FocusField::PgSsl => browser.input_buffer.clone(),
             FocusField::PgDbName => browser.input_buffer.clone(),
+            FocusField::RestoreTarget => browser.input_buffer.clone(),
             _ => String::new(),
         };
 
@@ -584,6 +870,7 @@ pub fn ui<B: Backend>(f: &mut Frame, browser: &mut SnapshotBrowser) {
             FocusField::PgPassword => "Password",
             FocusField::PgSsl => "SSL",
             FocusField::PgDbName => "Database Name",
+            FocusField::RestoreTarget => "New Database Name",
             _ => "",
         };
 
@@ -0,0 +1,306 @@
+mod common;
+
+use postgres_manager::backup::{apply_connection, passfile_entry, pgsslmode};
+use std::ffi::OsStr;
+use std::process::Command;
+
+#[test]
+fn test_passfile_entry() {
+    assert_eq!(passfile_entry("localhost", 5432, Some("postgres"), "secret"), "localhost:5432:*:postgres:secret\n");
+    assert_eq!(passfile_entry("db.internal", 6543, None, "secret"), "db.internal:6543:*:*:secret\n");
+}
+
+#[test]
+fn test_passfile_entry_escapes_separators() {
+    assert_eq!(
+        passfile_entry("::1", 5432, Some("ad:min"), r"pa:ss\word"),
+        r"\:\:1:5432:*:ad\:min:pa\:ss\\word".to_string() + "\n"
+    );
+}
+
+#[test]
+fn test_apply_connection_keeps_inherited_password() {
+    fn pgpassword(cmd: &Command) -> Option<Option<&OsStr>> {
+        cmd.get_envs().find(|(key, _)| *key == OsStr::new("PGPASSWORD")).map(|(_, value)| value)
+    }
+
+    // Without a configured password the child inherits PGPASSWORD untouched
+    let mut cmd = Command::new("pg_dump");
+    assert!(apply_connection(&mut cmd, "localhost", 5432, Some("postgres"), None, false).unwrap().is_none());
+    assert_eq!(pgpassword(&cmd), None);
+    assert!(cmd.get_envs().all(|(key, _)| key != OsStr::new("PGPASSFILE")));
+
+    // A configured password goes into the passfile, which an inherited PGPASSWORD would override
+    let mut cmd = Command::new("pg_dump");
+    let passfile = apply_connection(&mut cmd, "localhost", 5432, Some("postgres"), Some("secret"), false).unwrap().unwrap();
+    assert_eq!(pgpassword(&cmd), Some(None));
+    assert!(cmd.get_envs().any(|(key, value)| key == OsStr::new("PGPASSFILE") && value == Some(passfile.path().as_os_str())));
+}
+
+#[test]
+fn test_pgsslmode_keeps_environment() {
+    assert_eq!(pgsslmode(true, None), Some("require"));
+    assert_eq!(pgsslmode(true, Some("prefer")), Some("require"));
+    assert_eq!(pgsslmode(true, Some("verify-full")), None);
+    assert_eq!(pgsslmode(false, None), Some("disable"));
+    assert_eq!(pgsslmode(false, Some("verify-ca")), None);
+}
+
+#[test]
+fn test_dump_options_args() {
+    use postgres_manager::backup::{DumpFormat, DumpOptions};
+
+    assert_eq!(DumpOptions::default().args(), vec!["--format", "custom"]);
+
+    let options = DumpOptions {
+        format: DumpFormat::Directory,
+        compress: Some(6),
+        jobs: Some(4),
+        schemas: vec!["public".to_string()],
+        exclude_tables: vec!["audit_log".to_string()],
+        no_owner: true,
+        no_acl: true,
+        ..Default::default()
+    };
+    assert!(options.validate().is_ok());
+    assert_eq!(
+        options.args(),
+        vec![
+            "--format", "directory", "--compress", "6", "--jobs", "4",
+            "--schema", "public", "--exclude-table", "audit_log", "--no-owner", "--no-acl",
+        ]
+    );
+}
+
+#[test]
+fn test_dump_options_validate() {
+    use postgres_manager::backup::{DumpFormat, DumpOptions};
+
+    assert!(DumpOptions { jobs: Some(4), ..Default::default() }.validate().is_err());
+    assert!(DumpOptions { format: DumpFormat::Directory, jobs: Some(0), ..Default::default() }.validate().is_err());
+    assert!(DumpOptions { compress: Some(10), ..Default::default() }.validate().is_err());
+}
+
+#[test]
+fn test_dump_format_from_header() {
+    use postgres_manager::backup::DumpFormat;
+
+    assert_eq!(DumpFormat::from_header(b"PGDMP\x01\x0e\x00"), DumpFormat::Custom);
+    assert_eq!(DumpFormat::from_header(b"--\n-- PostgreSQL database dump\n"), DumpFormat::Plain);
+
+    let mut tar_header = vec![0u8; 512];
+    tar_header[257..262].copy_from_slice(b"ustar");
+    assert_eq!(DumpFormat::from_header(&tar_header), DumpFormat::Tar);
+}
+
+#[test]
+fn test_dump_format_streamable() {
+    use postgres_manager::backup::DumpFormat;
+
+    assert!(DumpFormat::Custom.is_streamable());
+    assert!(!DumpFormat::Tar.is_streamable());
+    assert!(DumpFormat::Plain.is_streamable());
+    assert!(!DumpFormat::Directory.is_streamable());
+}
+
+#[test]
+fn test_plain_dump_refuses_clean() {
+    use postgres_manager::backup::{restore_stream_with_progress, DumpFormat};
+    use postgres_manager::cancel::CancelToken;
+
+    assert!(DumpFormat::Custom.can_clean());
+    assert!(DumpFormat::Tar.can_clean());
+    assert!(!DumpFormat::Plain.can_clean());
+
+    // Refused before psql is started, so no server is needed
+    let script = std::io::Cursor::new(b"--\n-- PostgreSQL database dump\n--\nCREATE TABLE users (id int);\n".to_vec());
+    let err = restore_stream_with_progress("app", script, "localhost", 1, None, None, false, true, &CancelToken::new(), |_| {}).unwrap_err();
+    assert!(err.to_string().starts_with("Plain SQL dumps can't be restored over an existing database"), "{}", err);
+}
+
+#[test]
+fn test_upload_metadata() {
+    use postgres_manager::backup::UploadOptions;
+    use postgres_manager::compression::Compression;
+    use postgres_manager::crypto::EncryptionRecipient;
+
+    assert!(UploadOptions::default().metadata().is_empty());
+
+    let compressed = UploadOptions { compression: Compression::Zstd, encryption: None };
+    assert_eq!(compressed.metadata(), vec![("compression", "zstd")]);
+    assert_eq!(compressed.suffix(), ".zst");
+
+    let recipient = EncryptionRecipient::parse(&age::x25519::Identity::generate().to_public().to_string()).unwrap();
+    let encrypted = UploadOptions { compression: Compression::Gzip, encryption: Some(recipient) };
+    assert_eq!(encrypted.metadata(), vec![("encryption", "age"), ("compression", "gzip")]);
+    assert_eq!(encrypted.suffix(), ".gz.age");
+}
+
+/// A version 1.14 custom archive header with one table data entry whose offset is in `offset_state`
+fn custom_archive(offset_state: u8) -> Vec<u8> {
+    fn int(out: &mut Vec<u8>, value: i32) {
+        out.push(u8::from(value < 0));
+        out.extend_from_slice(&value.unsigned_abs().to_le_bytes());
+    }
+    fn string(out: &mut Vec<u8>, value: Option<&str>) {
+        match value {
+            Some(value) => {
+                int(out, value.len() as i32);
+                out.extend_from_slice(value.as_bytes());
+            }
+            None => int(out, -1),
+        }
+    }
+
+    let mut out = b"PGDMP".to_vec();
+    // Version 1.14.0, 4 byte ints and 8 byte offsets, custom format, compression level 0
+    out.extend_from_slice(&[1, 14, 0, 4, 8, 1]);
+    int(&mut out, 0);
+    for _ in 0..7 {
+        int(&mut out, 0);
+    }
+    for value in ["app", "15.4", "15.4"] {
+        string(&mut out, Some(value));
+    }
+
+    int(&mut out, 1);
+    int(&mut out, 1);
+    int(&mut out, 1);
+    for value in [Some("0"), Some("0"), Some("users"), Some("TABLE DATA")] {
+        string(&mut out, value);
+    }
+    int(&mut out, 3);
+    for value in [Some(""), Some(""), Some("COPY public.users (id) FROM stdin;\n"), Some("public"), None, None] {
+        string(&mut out, value);
+    }
+    string(&mut out, Some("postgres"));
+    string(&mut out, Some("false"));
+    string(&mut out, Some("1"));
+    string(&mut out, None);
+    out.push(offset_state);
+    out.extend_from_slice(&[0; 8]);
+    out
+}
+
+#[test]
+fn test_streamable_dump_falls_back_without_offsets() {
+    use postgres_manager::backup::DumpFormat;
+    use postgres_manager::toc::data_offsets_known;
+
+    // Written to a file, with the entry's data located
+    let seekable = custom_archive(2);
+    assert_eq!(data_offsets_known(&seekable), Some(true));
+    assert!(DumpFormat::streamable_dump(&seekable));
+
+    // Written to a pipe, so pg_restore would have to seek back for the data
+    let piped = custom_archive(1);
+    assert_eq!(data_offsets_known(&piped), Some(false));
+    assert!(!DumpFormat::streamable_dump(&piped));
+
+    // A table of contents that doesn't fit in what was read can't be checked
+    let truncated = &seekable[..seekable.len() - 4];
+    assert_eq!(data_offsets_known(truncated), None);
+    assert!(!DumpFormat::streamable_dump(truncated));
+
+    assert!(DumpFormat::streamable_dump(b"--\n-- PostgreSQL database dump\n"));
+    let mut tar = vec![0u8; 512];
+    tar[257..262].copy_from_slice(b"ustar");
+    assert!(!DumpFormat::streamable_dump(&tar));
+}
+
+#[test]
+fn test_restore_plan() {
+    use postgres_manager::backup::RestoreMode;
+
+    let existing = RestoreMode::Existing.plan("app");
+    assert_eq!(existing.database, "app");
+    assert!(existing.clean());
+
+    let new = RestoreMode::New.plan("app-copy");
+    assert_eq!(new.database, "app-copy");
+    assert!(!new.clean());
+
+    let swap = RestoreMode::Swap.plan("app");
+    assert!(swap.database.starts_with("app-restore-"));
+    assert_eq!(swap.target, "app");
+    assert_eq!(swap.previous(), "app-previous");
+    assert!(!swap.clean());
+}
+
+/// Record which restore a database came from
+async fn mark_generation(config: &postgres_manager::ui::models::PostgresConfig, database: &str, generation: i32) {
+    common::pg_client(config, Some(database))
+        .await
+        .batch_execute(&format!("CREATE TABLE generation (n int); INSERT INTO generation VALUES ({});", generation))
+        .await
+        .unwrap();
+}
+
+async fn generation(config: &postgres_manager::ui::models::PostgresConfig, database: &str) -> i32 {
+    common::pg_client(config, Some(database)).await.query_one("SELECT n FROM generation;", &[]).await.unwrap().get(0)
+}
+
+#[tokio::test]
+async fn test_restore_plan_transitions() {
+    use postgres_manager::backup::RestoreMode;
+    use postgres_manager::postgres::{database_exists, drop_database_with_force};
+
+    let Some(config) = common::pg_config() else {
+        return;
+    };
+    let client = common::pg_client(&config, None).await;
+    let target = common::scratch_name("plan");
+    let created = format!("{}-new", target);
+    let swap = RestoreMode::Swap.plan(&target);
+    for name in [&target, &created, &swap.previous(), &swap.retired()] {
+        if database_exists(&client, name).await.unwrap() {
+            drop_database_with_force(&client, name).await.unwrap();
+        }
+    }
+
+    // Existing needs the target, New refuses it and is rolled back by dropping what it created
+    assert!(RestoreMode::Existing.plan(&target).prepare(&client).await.is_err());
+    let new = RestoreMode::New.plan(&created);
+    new.prepare(&client).await.unwrap();
+    assert!(new.prepare(&client).await.is_err());
+    new.rollback(&client).await;
+    assert!(!database_exists(&client, &created).await.unwrap());
+
+    // Swapping into a missing target just renames the scratch database
+    swap.prepare(&client).await.unwrap();
+    mark_generation(&config, &swap.database, 1).await;
+    swap.finish(&client).await.unwrap();
+    assert_eq!(generation(&config, &target).await, 1);
+    assert!(!database_exists(&client, &swap.previous()).await.unwrap());
+
+    // Later swaps keep one previous generation and drop the older one only after swapping
+    for n in [2, 3] {
+        let swap = RestoreMode::Swap.plan(&target);
+        swap.prepare(&client).await.unwrap();
+        mark_generation(&config, &swap.database, n).await;
+        let restored = common::pg_client(&config, Some(&swap.database)).await;
+        assert_eq!(swap.validate(&restored, Some(1)).await.unwrap(), 1);
+        assert!(swap.validate(&restored, Some(2)).await.is_err());
+        drop(restored);
+
+        swap.finish(&client).await.unwrap();
+        assert_eq!(generation(&config, &target).await, n);
+        assert_eq!(generation(&config, &swap.previous()).await, n - 1);
+        assert!(!database_exists(&client, &swap.database).await.unwrap());
+        assert!(!database_exists(&client, &swap.retired()).await.unwrap());
+    }
+
+    // Rolling back a swap only drops its scratch database; rolling back Existing touches nothing
+    let failed = RestoreMode::Swap.plan(&target);
+    failed.prepare(&client).await.unwrap();
+    failed.rollback(&client).await;
+    assert!(!database_exists(&client, &failed.database).await.unwrap());
+    let existing = RestoreMode::Existing.plan(&target);
+    existing.prepare(&client).await.unwrap();
+    existing.rollback(&client).await;
+    assert_eq!(generation(&config, &target).await, 3);
+    assert_eq!(generation(&config, &swap.previous()).await, 2);
+
+    drop_database_with_force(&client, &target).await.unwrap();
+    drop_database_with_force(&client, &swap.previous()).await.unwrap();
+}
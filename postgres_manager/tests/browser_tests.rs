@@ -1,7 +1,12 @@
+mod common;
+
 use insta::assert_debug_snapshot;
 
 // We need to use the crate name directly since we're in an integration test
-use postgres_manager::ui::models::{BackupMetadata, FocusField, InputMode, PopupState, PostgresConfig, S3Config};
+use postgres_manager::history::HistoryState;
+use postgres_manager::postgres::DatabaseInfo;
+use postgres_manager::ui::browser::SnapshotBrowser;
+use postgres_manager::ui::models::{BackupMetadata, FocusField, InputMode, PopupState, PostgresConfig, S3Config, SidePanel};
 use aws_sdk_s3::primitives::DateTime as AwsDateTime;
 
 // Helper function to create a test AwsDateTime with a fixed timestamp
@@ -34,6 +39,8 @@ fn test_postgres_config() {
         username: Some("postgres".to_string()),
         password: Some("password".to_string()),
         use_ssl: false,
+        verify_ssl: false,
+        root_cert_path: None,
         db_name: Some("postgres".to_string()),
     };
     
@@ -63,15 +70,15 @@ fn test_popup_states() {
     
     let hidden = PopupState::Hidden;
     let confirm_restore = PopupState::ConfirmRestore(backup.clone());
-    let downloading = PopupState::Downloading(backup.clone(), 0.5, 1024.0 * 1024.0);
-    let confirm_cancel = PopupState::ConfirmCancel(backup.clone(), 0.5, 1024.0 * 1024.0);
+    let confirm_cancel_job = PopupState::ConfirmCancelJob(1);
+    let confirm_quit = PopupState::ConfirmQuit(2);
     let error = PopupState::Error("Test error message".to_string());
     let success = PopupState::Success("Test success message".to_string());
     
     assert_debug_snapshot!("popup_state_hidden", hidden);
     assert_debug_snapshot!("popup_state_confirm_restore", confirm_restore);
-    assert_debug_snapshot!("popup_state_downloading", downloading);
-    assert_debug_snapshot!("popup_state_confirm_cancel", confirm_cancel);
+    assert_debug_snapshot!("popup_state_confirm_cancel_job", confirm_cancel_job);
+    assert_debug_snapshot!("popup_state_confirm_quit", confirm_quit);
     assert_debug_snapshot!("popup_state_error", error);
     assert_debug_snapshot!("popup_state_success", success);
 }
@@ -93,3 +100,287 @@ fn test_input_modes() {
     assert_debug_snapshot!("input_mode_normal", InputMode::Normal);
     assert_debug_snapshot!("input_mode_editing", InputMode::Editing);
 }
+
+#[test]
+fn test_later_pages_are_sorted_in() {
+    use postgres_manager::s3::SnapshotPage;
+
+    let snapshot = |i: i64| common::snapshot(&format!("snapshot-{}.dump", i), 1024, 1672574400 + i);
+    let mut browser = SnapshotBrowser::new(postgres_manager::config::load_s3_config(), PostgresConfig::default());
+    browser.snapshots = (0..3).map(snapshot).collect();
+    browser.next_token = Some("token".to_string());
+    browser.set_sort(browser.sort);
+    browser.selected_idx = Some(0);
+    assert!(browser.has_more_snapshots());
+
+    // Newer snapshots from a later page go to the top of the newest-first list, the selection stays put
+    browser.add_snapshot_page(SnapshotPage { snapshots: vec![snapshot(5), snapshot(4)], next_token: None });
+    let keys: Vec<_> = browser.snapshots.iter().map(|s| s.key.as_str()).collect();
+    assert_eq!(keys, ["snapshot-5.dump", "snapshot-4.dump", "snapshot-2.dump", "snapshot-1.dump", "snapshot-0.dump"]);
+    assert_eq!(browser.selected_snapshot().unwrap().key, "snapshot-2.dump");
+    assert!(!browser.has_more_snapshots());
+}
+
+#[test]
+fn test_restore_defaults_to_new_database() {
+    use postgres_manager::backup::RestoreMode;
+
+    let pg_config = PostgresConfig { db_name: Some("app".to_string()), ..PostgresConfig::default() };
+    let mut browser = SnapshotBrowser::new(postgres_manager::config::load_s3_config(), pg_config);
+
+    // Nothing is restored over the configured database until that is picked explicitly
+    assert_eq!(browser.restore_mode, RestoreMode::New);
+    assert_eq!(browser.restore_target(), None);
+    browser.restore_mode = browser.restore_mode.next();
+    assert_eq!(browser.restore_mode, RestoreMode::Swap);
+    browser.restore_mode = browser.restore_mode.next();
+    assert_eq!(browser.restore_mode, RestoreMode::Existing);
+    assert_eq!(browser.restore_target(), Some("app".to_string()));
+}
+
+#[test]
+fn test_encrypted_by_metadata() {
+    let snapshot = |key: &str| common::snapshot(key, 0, 1672574400);
+    let mut browser = SnapshotBrowser::new(postgres_manager::config::load_s3_config(), PostgresConfig::default());
+
+    assert!(browser.is_encrypted(&snapshot("app.dump.age")));
+    assert!(!browser.is_encrypted(&snapshot("app.dump")));
+
+    // Encrypted without the suffix, e.g. after a rename
+    browser.encrypted.insert("app.dump".to_string(), true);
+    browser.encrypted.insert("plain.dump".to_string(), false);
+    assert!(browser.is_encrypted(&snapshot("app.dump")));
+    assert!(!browser.is_encrypted(&snapshot("plain.dump")));
+}
+
+#[test]
+fn test_page_navigation() {
+    let mut browser = SnapshotBrowser::new(postgres_manager::config::load_s3_config(), PostgresConfig::default());
+    browser.page_down();
+    assert_eq!(browser.selected_idx, None);
+
+    browser.snapshots = (0..25).map(|i| common::snapshot(&format!("snapshot-{}.dump", i), 1024, 1672574400 + i)).collect();
+    browser.selected_idx = Some(0);
+
+    browser.page_down();
+    assert_eq!(browser.selected_idx, Some(10));
+    browser.page_down();
+    assert_eq!(browser.selected_idx, Some(20));
+    browser.page_down();
+    assert_eq!(browser.selected_idx, Some(24));
+
+    browser.page_up();
+    assert_eq!(browser.selected_idx, Some(14));
+    browser.first();
+    assert_eq!(browser.selected_idx, Some(0));
+    browser.last();
+    assert_eq!(browser.selected_idx, Some(24));
+}
+
+#[test]
+fn test_search_filters_selection() {
+    let mut browser = SnapshotBrowser::new(postgres_manager::config::load_s3_config(), PostgresConfig::default());
+    browser.snapshots = ["backups/app-20240101000000.dump", "backups/crm-20240101000000.dump", "backups/app-20240102000000.dump"]
+        .iter()
+        .map(|key| common::snapshot(key, 1024, 1672574400))
+        .collect();
+    browser.selected_idx = Some(2);
+
+    // The selected snapshot still matches, so it stays selected at its new position
+    browser.set_search("db:app".to_string());
+    assert_eq!(browser.visible_snapshots().len(), 2);
+    assert_eq!(browser.selected_idx, Some(1));
+    assert_eq!(browser.selected_snapshot().unwrap().key, "backups/app-20240102000000.dump");
+
+    browser.next();
+    assert_eq!(browser.selected_idx, Some(1));
+
+    // An invalid search keeps the previous filter
+    browser.set_search("db:app >12XB".to_string());
+    assert!(browser.search_error.is_some());
+    assert_eq!(browser.visible_snapshots().len(), 2);
+
+    browser.set_search("crm".to_string());
+    assert!(browser.search_error.is_none());
+    assert_eq!(browser.selected_snapshot().unwrap().key, "backups/crm-20240101000000.dump");
+
+    browser.set_search("nothing".to_string());
+    assert_eq!(browser.selected_idx, None);
+
+    browser.set_search(String::new());
+    assert_eq!(browser.selected_idx, Some(0));
+    assert_eq!(browser.visible_snapshots().len(), 3);
+}
+
+#[test]
+fn test_sort_keeps_selection() {
+    let mut browser = SnapshotBrowser::new(postgres_manager::config::load_s3_config(), PostgresConfig::default());
+    browser.snapshots = (0..3).map(|i| common::snapshot(&format!("snapshot-{}.dump", i), 1024 * (3 - i), 1672574400 + i)).collect();
+    browser.selected_idx = Some(0);
+
+    browser.set_sort(browser.sort.cycle_field().reversed());
+    assert_eq!(browser.snapshots[0].key, "snapshot-2.dump");
+    assert_eq!(browser.selected_idx, Some(2));
+    assert_eq!(browser.selected_snapshot().unwrap().key, "snapshot-0.dump");
+}
+
+#[test]
+fn test_databases_panel() {
+    let mut browser = SnapshotBrowser::new(postgres_manager::config::load_s3_config(), PostgresConfig::default());
+    browser.databases = ["app", "crm"]
+        .iter()
+        .map(|name| DatabaseInfo { name: name.to_string(), owner: "postgres".to_string(), size: Some(8_000_000), connections: 0 })
+        .collect();
+
+    // Opening the panel focuses it, switching to another one hands focus back to the snapshot list
+    browser.toggle_side_panel(SidePanel::Databases);
+    assert_eq!((browser.side_panel, browser.focus), (SidePanel::Databases, FocusField::Databases));
+    browser.toggle_side_panel(SidePanel::History);
+    assert_eq!((browser.side_panel, browser.focus), (SidePanel::History, FocusField::SnapshotList));
+    browser.toggle_side_panel(SidePanel::History);
+    assert_eq!(browser.side_panel, SidePanel::Details);
+
+    assert_eq!(browser.selected_database(), None);
+    browser.next_database();
+    browser.next_database();
+    browser.next_database();
+    assert_eq!(browser.selected_database().map(|db| db.name.as_str()), Some("crm"));
+    browser.previous_database();
+    assert_eq!(browser.selected_database().map(|db| db.name.as_str()), Some("app"));
+
+    // Cloning needs the PostgreSQL settings
+    browser.start_clone("app");
+    assert_eq!(browser.popup_state, PopupState::Error("PostgreSQL host is required".to_string()));
+    assert!(browser.jobs.jobs().is_empty());
+}
+
+#[tokio::test]
+async fn test_jobs_run_in_background() {
+    let mut browser = SnapshotBrowser::new(postgres_manager::config::load_s3_config(), PostgresConfig::default());
+    let snapshot = common::snapshot("snapshot.dump", 100, 1672574400);
+
+    // Without an S3 client nothing is started
+    browser.start_download(&snapshot);
+    assert_eq!(browser.popup_state, PopupState::Error("S3 client not initialized".to_string()));
+    assert!(browser.jobs.jobs().is_empty());
+
+    browser.popup_state = PopupState::Hidden;
+    browser.config = S3Config {
+        bucket: "bucket".to_string(),
+        region: "us-east-1".to_string(),
+        prefix: String::new(),
+        endpoint_url: "http://127.0.0.1:1".to_string(),
+        access_key_id: "key".to_string(),
+        secret_access_key: "secret".to_string(),
+        path_style: true,
+        error_message: None,
+    };
+    browser.download_options.max_retries = 0;
+    browser.s3_client = Some(postgres_manager::s3::build_client(&browser.config));
+    browser.start_download(&snapshot);
+    assert_eq!(browser.jobs.running(), 1);
+
+    // The job fails on its own task while the browser keeps going, and the failure pops up once it is polled
+    for _ in 0..100 {
+        browser.poll_jobs().await;
+        if browser.jobs.running() == 0 {
+            break;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+    }
+    assert_eq!(browser.selected_job().map(|job| job.stage_label()), Some("failed"));
+    assert!(matches!(&browser.popup_state, PopupState::Error(message) if message.starts_with("Download of snapshot.dump failed")));
+    // The finished job is kept in the history even after it is cleared from the panel
+    assert_eq!(browser.history.len(), 1);
+    assert_eq!((browser.history[0].kind.as_str(), browser.history[0].state), ("download", HistoryState::Failed));
+
+    browser.clear_finished_jobs();
+    assert!(browser.jobs.jobs().is_empty());
+    assert_eq!(browser.selected_job, None);
+}
+
+#[tokio::test]
+async fn test_snapshot_list_hides_sidecars_and_loads_details_in_background() {
+    use postgres_manager::manifest::SnapshotManifest;
+
+    let Some(mut config) = common::s3_config() else {
+        return;
+    };
+    config.prefix = format!("{}/", common::scratch_key("browser").trim_end_matches(".dump"));
+    let client = postgres_manager::s3::build_client(&config);
+    let manifest = SnapshotManifest { database: "app".to_string(), ..SnapshotManifest::default() };
+    let objects = [
+        ("app.dump", Vec::new()),
+        ("app.dump.sha256", Vec::new()),
+        ("app.dump.manifest.json", manifest.to_json().unwrap().into_bytes()),
+    ];
+    for (key, body) in &objects {
+        client.put_object()
+            .bucket(&config.bucket)
+            .key(format!("{}{}", config.prefix, key))
+            .body(aws_sdk_s3::primitives::ByteStream::from(body.clone()))
+            .set_metadata(key.ends_with(".dump").then(|| [("encryption".to_string(), "age".to_string())].into()))
+            .send()
+            .await
+            .unwrap();
+    }
+
+    let mut browser = SnapshotBrowser::new(config.clone(), PostgresConfig::default());
+    browser.load_snapshots().await.unwrap();
+    let key = format!("{}app.dump", config.prefix);
+    assert_eq!(browser.snapshots.iter().map(|s| s.key.as_str()).collect::<Vec<_>>(), [key.as_str()]);
+
+    // The manifest and encryption metadata arrive while the browser keeps drawing
+    browser.load_selected_manifest();
+    assert!(!browser.manifests.contains_key(&key));
+    for _ in 0..100 {
+        browser.poll_details();
+        if browser.manifests.contains_key(&key) && browser.encrypted.contains_key(&key) {
+            break;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+    }
+    assert_eq!(browser.manifests.get(&key), Some(&Some(manifest)));
+    assert!(browser.is_encrypted(&browser.snapshots[0]));
+
+    for (key, _) in &objects {
+        client.delete_object().bucket(&config.bucket).key(format!("{}{}", config.prefix, key)).send().await.unwrap();
+    }
+}
+
+#[tokio::test]
+async fn test_remaining_pages_load_in_background() {
+    use futures_util::StreamExt;
+
+    let Some(mut config) = common::s3_config() else {
+        return;
+    };
+    config.prefix = format!("{}/", common::scratch_key("pages").trim_end_matches(".dump"));
+    let client = postgres_manager::s3::build_client(&config);
+    // One more than S3 lists in a page
+    let keys: Vec<String> = (0..1001).map(|i| format!("{}app-{:04}.dump", config.prefix, i)).collect();
+    futures_util::stream::iter(&keys)
+        .for_each_concurrent(16, |key| {
+            let request = client.put_object().bucket(&config.bucket).key(key).body(Vec::new().into()).send();
+            async move {
+                request.await.unwrap();
+            }
+        })
+        .await;
+
+    let mut browser = SnapshotBrowser::new(config.clone(), PostgresConfig::default());
+    browser.load_snapshots().await.unwrap();
+    assert_eq!(browser.snapshots.len(), 1000);
+    assert!(browser.has_more_snapshots());
+    for _ in 0..100 {
+        browser.poll_details();
+        if !browser.has_more_snapshots() {
+            break;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+    }
+    assert_eq!(browser.snapshots.len(), 1001);
+    assert_eq!(browser.config.error_message, None);
+
+    postgres_manager::s3::delete_objects(&client, &config.bucket, &keys).await.unwrap();
+}
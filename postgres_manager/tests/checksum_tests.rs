@@ -0,0 +1,64 @@
+use md5::{Digest, Md5};
+use postgres_manager::checksum::{parse_etag, verify_file, ObjectChecksums};
+use std::io::Write;
+
+fn temp_file(data: &[u8]) -> tempfile::NamedTempFile {
+    let mut file = tempfile::NamedTempFile::new().unwrap();
+    file.write_all(data).unwrap();
+    file
+}
+
+#[test]
+fn test_parse_etag() {
+    let (digest, parts) = parse_etag("\"900150983cd24fb0d6963f7d28e17f72\"").unwrap();
+    assert_eq!(hex::encode(digest), "900150983cd24fb0d6963f7d28e17f72");
+    assert_eq!(parts, None);
+
+    let (_, parts) = parse_etag("\"f238683a1b8d92f8ad455461aeb11a66-3\"").unwrap();
+    assert_eq!(parts, Some(3));
+
+    // ETags of SSE-KMS objects are not MD5 digests
+    assert_eq!(parse_etag("\"not-an-md5\""), None);
+}
+
+#[test]
+fn test_verify_single_part() {
+    let file = temp_file(b"abc");
+    let expected = ObjectChecksums {
+        etag: Some("\"900150983cd24fb0d6963f7d28e17f72\"".to_string()),
+        // base64 SHA-256 and CRC32C of "abc"
+        sha256: Some("ungWv48Bz+pBQUDeXa4iI7ADYaOWF3qctBD/YfIAFa0=".to_string()),
+        crc32c: Some("Nks/tw==".to_string()),
+        sidecar_sha256: Some("ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad  abc.dump".to_string()),
+        part_size: None,
+    };
+    assert_eq!(verify_file(file.path(), &expected).unwrap(), vec!["ETag", "SHA-256", "CRC32C", "Sidecar SHA-256"]);
+
+    let corrupt = temp_file(b"abd");
+    let err = verify_file(corrupt.path(), &expected).unwrap_err();
+    assert!(err.to_string().starts_with("ETag mismatch"));
+}
+
+#[test]
+fn test_verify_multipart_etag() {
+    let data: Vec<u8> = (0..25u8).collect();
+    let file = temp_file(&data);
+
+    let part_md5s: Vec<u8> = data.chunks(10).flat_map(|part| Md5::digest(part).to_vec()).collect();
+    let etag = format!("\"{}-3\"", hex::encode(Md5::digest(&part_md5s)));
+
+    let mut expected = ObjectChecksums { etag: Some(etag), part_size: Some(10), ..Default::default() };
+    assert!(expected.needs_part_size());
+    assert_eq!(verify_file(file.path(), &expected).unwrap(), vec!["ETag"]);
+
+    // A different part size splits the file into a different number of parts
+    expected.part_size = Some(8);
+    assert!(verify_file(file.path(), &expected).is_err());
+}
+
+#[test]
+fn test_verify_without_checksums() {
+    let file = temp_file(b"abc");
+    let expected = ObjectChecksums { etag: Some("\"kms-encrypted\"".to_string()), ..Default::default() };
+    assert!(verify_file(file.path(), &expected).unwrap().is_empty());
+}
@@ -0,0 +1,66 @@
+//! Connection settings for tests that need a live PostgreSQL server or S3 endpoint. These tests return
+//! early, passing, unless `TEST_PG_HOST` or `TEST_S3_ENDPOINT` is set.
+#![allow(dead_code)]
+
+use aws_sdk_s3::primitives::DateTime as AwsDateTime;
+use postgres_manager::service::{connect_pg, pg_connection_config};
+use postgres_manager::ui::models::{BackupMetadata, PostgresConfig, S3Config};
+
+/// PostgreSQL settings from `TEST_PG_HOST`, `TEST_PG_PORT`, `TEST_PG_USERNAME` and `TEST_PG_PASSWORD`
+pub fn pg_config() -> Option<PostgresConfig> {
+    let host = std::env::var("TEST_PG_HOST").ok().filter(|host| !host.is_empty())?;
+    Some(PostgresConfig {
+        host: Some(host),
+        port: std::env::var("TEST_PG_PORT").ok().and_then(|port| port.parse().ok()),
+        username: Some(std::env::var("TEST_PG_USERNAME").unwrap_or_else(|_| "postgres".to_string())),
+        password: std::env::var("TEST_PG_PASSWORD").ok(),
+        use_ssl: false,
+        verify_ssl: false,
+        root_cert_path: None,
+        db_name: Some("postgres".to_string()),
+    })
+}
+
+pub async fn pg_client(config: &PostgresConfig, dbname: Option<&str>) -> tokio_postgres::Client {
+    connect_pg(config, &pg_connection_config(config, dbname)).await.expect("Failed to connect to the test server")
+}
+
+/// Database name unique to this test run, so parallel runs don't trip over each other
+pub fn scratch_name(test: &str) -> String {
+    format!("pm_test_{}_{}", test, std::process::id())
+}
+
+/// S3 settings from `TEST_S3_ENDPOINT`, `TEST_S3_BUCKET`, `TEST_S3_ACCESS_KEY_ID` and `TEST_S3_SECRET_ACCESS_KEY`.
+/// The bucket must already exist.
+pub fn s3_config() -> Option<S3Config> {
+    let endpoint_url = std::env::var("TEST_S3_ENDPOINT").ok().filter(|url| !url.is_empty())?;
+    Some(S3Config {
+        bucket: std::env::var("TEST_S3_BUCKET").unwrap_or_else(|_| "test".to_string()),
+        region: "us-east-1".to_string(),
+        prefix: String::new(),
+        endpoint_url,
+        access_key_id: std::env::var("TEST_S3_ACCESS_KEY_ID").unwrap_or_else(|_| "test".to_string()),
+        secret_access_key: std::env::var("TEST_S3_SECRET_ACCESS_KEY").unwrap_or_else(|_| "test".to_string()),
+        path_style: true,
+        error_message: None,
+    })
+}
+
+/// Object key unique to this test run
+pub fn scratch_key(test: &str) -> String {
+    format!("pm-test-{}/{}.dump", std::process::id(), test)
+}
+
+/// A listed snapshot of `size` bytes, last modified `secs` seconds after the epoch
+pub fn snapshot(key: &str, size: i64, secs: i64) -> BackupMetadata {
+    BackupMetadata {
+        key: key.to_string(),
+        size,
+        last_modified: AwsDateTime::from_secs(secs),
+    }
+}
+
+/// A 1 KiB snapshot of the `app` database taken at midnight on 2024-01-01
+pub fn app_snapshot() -> BackupMetadata {
+    snapshot("backups/app-20240101000000.dump", 1024, 1704067200)
+}
@@ -0,0 +1,77 @@
+use postgres_manager::compression::{decompress_if_compressed, Compression};
+use postgres_manager::s3::FinishWrite;
+use std::io::{Read, Write};
+use std::sync::{Arc, Mutex};
+
+/// Collects everything written to it, like the buffer an upload drains
+#[derive(Clone, Default)]
+struct Collect(Arc<Mutex<Vec<u8>>>);
+
+impl Write for Collect {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+impl FinishWrite for Collect {
+    fn finish(self: Box<Self>) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn test_compression_detect() {
+    assert_eq!(Compression::detect("backups/app.sql.gz", None), Compression::Gzip);
+    assert_eq!(Compression::detect("backups/app.dump.zst", None), Compression::Zstd);
+    assert_eq!(Compression::detect("backups/app.dump.zst.age", None), Compression::Zstd);
+    assert_eq!(Compression::detect("backups/app.dump", Some("gzip")), Compression::Gzip);
+    assert_eq!(Compression::detect("backups/app.dump", Some("zstd")), Compression::Zstd);
+    assert_eq!(Compression::detect("backups/app.dump", Some("identity")), Compression::None);
+    assert_eq!(Compression::detect("backups/app.dump", None), Compression::None);
+}
+
+#[test]
+fn test_compression_from_header() {
+    assert_eq!(Compression::from_header(&[0x1f, 0x8b, 0x08, 0x00]), Compression::Gzip);
+    assert_eq!(Compression::from_header(&[0x28, 0xb5, 0x2f, 0xfd]), Compression::Zstd);
+    assert_eq!(Compression::from_header(b"PGDMP"), Compression::None);
+}
+
+#[test]
+fn test_compression_roundtrip() {
+    let dump = b"PGDMP".repeat(10_000);
+
+    for compression in [Compression::Gzip, Compression::Zstd] {
+        let collect = Collect::default();
+        let mut writer = compression.compress(Box::new(collect.clone())).unwrap();
+        writer.write_all(&dump).unwrap();
+        writer.finish().unwrap();
+
+        let compressed = collect.0.lock().unwrap().clone();
+        assert_eq!(Compression::from_header(&compressed), compression);
+        assert!(compressed.len() < dump.len());
+
+        // Without a hint the compression is sniffed from the data
+        let mut decompressed = Vec::new();
+        decompress_if_compressed(std::io::Cursor::new(compressed), Compression::None)
+            .unwrap()
+            .read_to_end(&mut decompressed)
+            .unwrap();
+        assert_eq!(decompressed, dump);
+    }
+}
+
+#[test]
+fn test_uncompressed_passthrough() {
+    let mut output = Vec::new();
+    decompress_if_compressed(std::io::Cursor::new(b"PGDMP".to_vec()), Compression::None)
+        .unwrap()
+        .read_to_end(&mut output)
+        .unwrap();
+    assert_eq!(output, b"PGDMP");
+}
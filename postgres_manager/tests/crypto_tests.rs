@@ -0,0 +1,91 @@
+use age::secrecy::ExposeSecret;
+use postgres_manager::crypto::{decrypt_if_encrypted, is_encrypted_header, is_encrypted_key, EncryptionKey, EncryptionRecipient};
+use postgres_manager::s3::FinishWrite;
+use std::io::{Read, Write};
+use std::sync::{Arc, Mutex};
+
+/// Collects everything written to it, like the buffer an upload drains
+#[derive(Clone, Default)]
+struct Collect(Arc<Mutex<Vec<u8>>>);
+
+impl Write for Collect {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+impl FinishWrite for Collect {
+    fn finish(self: Box<Self>) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+fn generate_key() -> EncryptionKey {
+    let identity = age::x25519::Identity::generate();
+    EncryptionKey::parse(&format!("# created by age-keygen\n{}\n", identity.to_string().expose_secret())).unwrap()
+}
+
+#[test]
+fn test_encrypted_detection() {
+    assert!(is_encrypted_key("backups/app-20240101.dump.age"));
+    assert!(!is_encrypted_key("backups/app-20240101.dump"));
+    assert!(is_encrypted_header(b"age-encryption.org/v1\n-> X25519"));
+    assert!(!is_encrypted_header(b"PGDMP"));
+}
+
+#[test]
+fn test_parse_key() {
+    assert!(generate_key().recipient().starts_with("age1"));
+    assert!(EncryptionKey::parse("# only a comment\n").is_err());
+    assert!(EncryptionKey::parse("AGE-SECRET-KEY-1NOTAKEY").is_err());
+}
+
+#[test]
+fn test_encrypt_roundtrip() {
+    let key = generate_key();
+    let output = Collect::default();
+    let mut writer = key.encrypt(Box::new(output.clone())).unwrap();
+    writer.write_all(b"PGDMP archive contents").unwrap();
+    writer.finish().unwrap();
+
+    let encrypted = output.0.lock().unwrap().clone();
+    assert!(is_encrypted_header(&encrypted));
+
+    let mut plaintext = Vec::new();
+    decrypt_if_encrypted(std::io::Cursor::new(encrypted.clone()), Some(&key)).unwrap().read_to_end(&mut plaintext).unwrap();
+    assert_eq!(plaintext, b"PGDMP archive contents");
+
+    assert!(decrypt_if_encrypted(std::io::Cursor::new(encrypted.clone()), None).is_err());
+    assert!(decrypt_if_encrypted(std::io::Cursor::new(encrypted), Some(&generate_key())).is_err());
+}
+
+#[test]
+fn test_encrypt_to_recipient() {
+    let key = generate_key();
+    let recipient = EncryptionRecipient::parse(&format!("{}\n", key.recipient())).unwrap();
+    assert_eq!(recipient.to_string(), key.recipient());
+    assert!(EncryptionRecipient::parse("age1notakey").is_err());
+
+    // Only the public key is needed to encrypt, the identity to decrypt
+    let output = Collect::default();
+    let mut writer = recipient.encrypt(Box::new(output.clone())).unwrap();
+    writer.write_all(b"PGDMP archive contents").unwrap();
+    writer.finish().unwrap();
+
+    let encrypted = output.0.lock().unwrap().clone();
+    let mut plaintext = Vec::new();
+    decrypt_if_encrypted(std::io::Cursor::new(encrypted), Some(&key)).unwrap().read_to_end(&mut plaintext).unwrap();
+    assert_eq!(plaintext, b"PGDMP archive contents");
+}
+
+#[test]
+fn test_unencrypted_passthrough() {
+    let mut plaintext = Vec::new();
+    decrypt_if_encrypted(std::io::Cursor::new(b"PGDMP".to_vec()), None).unwrap().read_to_end(&mut plaintext).unwrap();
+    assert_eq!(plaintext, b"PGDMP");
+}
@@ -0,0 +1,112 @@
+mod common;
+
+use chrono::{TimeZone, Utc};
+use common::snapshot;
+use postgres_manager::filter::{database_name, fuzzy_match, latest_of_database, parse_date, parse_size, SnapshotFilter};
+
+/// Noon on day `day` of January 2024, in seconds since the epoch
+fn noon(day: u32) -> i64 {
+    Utc.with_ymd_and_hms(2024, 1, day, 12, 0, 0).unwrap().timestamp()
+}
+
+#[test]
+fn test_fuzzy_match() {
+    assert!(fuzzy_match("app", "backups/app-20240101120000.dump"));
+    assert!(fuzzy_match("BKapp24dmp", "backups/app-20240101120000.dump"));
+    assert!(fuzzy_match("", "anything"));
+    assert!(!fuzzy_match("pa0x", "backups/app-20240101120000.dump"));
+}
+
+#[test]
+fn test_database_name() {
+    assert_eq!(database_name("backups/app-20240101120000.dump"), "app");
+    assert_eq!(database_name("backups/nightly/my-app-20240101120000.dump.zst.age"), "my-app");
+    assert_eq!(database_name("manual.dump"), "manual.dump");
+}
+
+#[test]
+fn test_latest_of_database() {
+    let snapshots = vec![
+        snapshot("backups/app-20240102120000.dump", 10, noon(2)),
+        snapshot("backups/billing-20240103120000.dump", 10, noon(3)),
+        snapshot("backups/app-20240101120000.dump", 10, noon(1)),
+        snapshot("backups/app-archive-20240104120000.dump", 10, noon(4)),
+    ];
+
+    // Newer snapshots of other databases under the same prefix are never picked
+    assert_eq!(latest_of_database(snapshots.clone(), "app").unwrap().key, "backups/app-20240102120000.dump");
+    assert_eq!(latest_of_database(snapshots.clone(), "billing").unwrap().key, "backups/billing-20240103120000.dump");
+    assert_eq!(latest_of_database(snapshots, "missing"), None);
+}
+
+#[test]
+fn test_parse_size() {
+    assert_eq!(parse_size("512").unwrap(), 512);
+    assert_eq!(parse_size("100MB").unwrap(), 100_000_000);
+    assert_eq!(parse_size("1.5g").unwrap(), 1_500_000_000);
+    assert_eq!(parse_size("2KiB").unwrap(), 2048);
+    assert_eq!(parse_size("1.5GiB").unwrap(), 1536 * 1024 * 1024);
+    assert!(parse_size("MB").is_err());
+    assert!(parse_size("12XB").is_err());
+}
+
+#[test]
+fn test_parse_date() {
+    assert_eq!(parse_date("2024-01-02").unwrap(), Utc.with_ymd_and_hms(2024, 1, 2, 0, 0, 0).unwrap());
+    assert_eq!(parse_date("2024-01-02T03:04:05Z").unwrap(), Utc.with_ymd_and_hms(2024, 1, 2, 3, 4, 5).unwrap());
+    assert!(parse_date("yesterday").is_err());
+}
+
+#[test]
+fn test_parse_filter() {
+    let filter = SnapshotFilter::parse("App  db:app since:2024-01-02 until:2024-01-03 >1KB <1MB").unwrap();
+    assert_eq!(filter.terms, vec!["app"]);
+    assert_eq!(filter.database.as_deref(), Some("app"));
+    assert_eq!(filter.since, Some(Utc.with_ymd_and_hms(2024, 1, 2, 0, 0, 0).unwrap()));
+    // A plain date includes the whole day
+    assert_eq!(filter.until, Some(Utc.with_ymd_and_hms(2024, 1, 4, 0, 0, 0).unwrap()));
+    assert_eq!(filter.min_size, Some(1000));
+    assert_eq!(filter.max_size, Some(1_000_000));
+
+    assert!(SnapshotFilter::parse("").unwrap().is_empty());
+    assert!(SnapshotFilter::parse("since:soon").is_err());
+}
+
+#[test]
+fn test_filter_matches() {
+    let snapshots = [
+        snapshot("backups/app-20240101120000.dump", 500, noon(1)),
+        snapshot("backups/app-20240103120000.dump", 5000, noon(3)),
+        snapshot("backups/crm-20240103120000.dump", 5000, noon(3)),
+        snapshot("backups/app-20240105120000.dump", 50000, noon(5)),
+    ];
+    let matching = |search: &str| -> Vec<&str> {
+        let filter = SnapshotFilter::parse(search).unwrap();
+        snapshots.iter().filter(|s| filter.matches(s)).map(|s| s.key.as_str()).collect()
+    };
+
+    assert_eq!(matching("").len(), 4);
+    assert_eq!(matching("crm"), vec!["backups/crm-20240103120000.dump"]);
+    assert_eq!(matching("db:app since:2024-01-02 until:2024-01-03"), vec!["backups/app-20240103120000.dump"]);
+    assert_eq!(matching(">1K <10K"), vec!["backups/app-20240103120000.dump", "backups/crm-20240103120000.dump"]);
+    assert_eq!(matching("db:ap").len(), 0);
+}
+
+#[test]
+fn test_size_filter_matches_listed_sizes() {
+    // The list shows 100_000_000 bytes as "100 MB" and 104_857_600 (100 MiB) as "104.86 MB"
+    let snapshots = [
+        snapshot("backups/app-20240101120000.dump", 99_999_999, noon(1)),
+        snapshot("backups/app-20240102120000.dump", 100_000_000, noon(2)),
+        snapshot("backups/app-20240103120000.dump", 104_857_600, noon(3)),
+    ];
+    let matching = |search: &str| -> Vec<i64> {
+        let filter = SnapshotFilter::parse(search).unwrap();
+        snapshots.iter().filter(|s| filter.matches(s)).map(|s| s.size).collect()
+    };
+
+    assert_eq!(matching(">100MB"), vec![100_000_000, 104_857_600]);
+    assert_eq!(matching("<100MB"), vec![99_999_999, 100_000_000]);
+    assert_eq!(matching(">100MiB"), vec![104_857_600]);
+    assert_eq!(humansize::format_size(100_000_000u64, humansize::DECIMAL), "100 MB");
+}
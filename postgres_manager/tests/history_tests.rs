@@ -0,0 +1,79 @@
+use chrono::{Duration, Utc};
+use postgres_manager::cancel::Cancelled;
+use postgres_manager::history::{self, HistoryEntry, HistoryState, JobHistory};
+use std::path::PathBuf;
+
+fn entry(subject: &str) -> HistoryEntry {
+    HistoryEntry {
+        target: Some("app".to_string()),
+        bytes: Some(1_500_000),
+        ..HistoryEntry::finished("restore", subject, Utc::now() - Duration::seconds(3), Ok(()))
+    }
+}
+
+#[test]
+fn test_finished_states() {
+    let started_at = Utc::now() - Duration::seconds(5);
+    let succeeded = HistoryEntry::finished("dump", "app", started_at, Ok(()));
+    assert_eq!((succeeded.state, succeeded.error), (HistoryState::Succeeded, None));
+    assert!(succeeded.duration_secs >= 5.0);
+
+    let failed = HistoryEntry::finished("dump", "app", started_at, Err(&anyhow::anyhow!("pg_dump exited with 1")));
+    assert_eq!((failed.state, failed.error.as_deref()), (HistoryState::Failed, Some("pg_dump exited with 1")));
+
+    let cancelled = HistoryEntry::finished("restore", "app.dump", started_at, Err(&Cancelled.into()));
+    assert_eq!((cancelled.state, cancelled.error), (HistoryState::Cancelled, None));
+}
+
+#[test]
+fn test_append_and_load() {
+    let dir = tempfile::tempdir().unwrap();
+    let history = JobHistory::new(dir.path().join("history.jsonl"));
+    assert_eq!(history.load(10).unwrap(), Vec::new());
+
+    for subject in ["first.dump", "second.dump", "third.dump"] {
+        history.append(&entry(subject)).unwrap();
+    }
+
+    // Newest first, at most `limit` of them
+    let loaded = history.load(2).unwrap();
+    assert_eq!(loaded.iter().map(|e| e.subject.as_str()).collect::<Vec<_>>(), ["third.dump", "second.dump"]);
+    assert_eq!((loaded[0].target.as_deref(), loaded[0].bytes, loaded[0].state), (Some("app"), Some(1_500_000), HistoryState::Succeeded));
+}
+
+#[test]
+fn test_load_skips_bad_lines() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("history.jsonl");
+    let good = serde_json::to_string(&entry("app.dump")).unwrap();
+    std::fs::write(&path, format!("{}\nnot json\n\n{{\"kind\": \"dump\"}}\n", good)).unwrap();
+
+    let loaded = JobHistory::new(&path).load(10).unwrap();
+    assert_eq!(loaded.len(), 1);
+    assert_eq!(loaded[0].subject, "app.dump");
+}
+
+#[test]
+fn test_default_path() {
+    let state = |dir: &str| Some(PathBuf::from(dir));
+    assert_eq!(history::default_path(state("/state"), state("/home/me")), PathBuf::from("/state/postgres_manager/history.jsonl"));
+    assert_eq!(history::default_path(None, state("/home/me")), PathBuf::from("/home/me/.local/state/postgres_manager/history.jsonl"));
+    // A relative XDG_STATE_HOME is invalid and ignored
+    assert_eq!(history::default_path(state("state"), state("/home/me")), PathBuf::from("/home/me/.local/state/postgres_manager/history.jsonl"));
+    assert_eq!(history::default_path(None, None), PathBuf::from("postgres_manager_history.jsonl"));
+}
+
+#[cfg(unix)]
+#[test]
+fn test_append_creates_private_file() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let dir = tempfile::tempdir().unwrap();
+    let history = JobHistory::new(dir.path().join("postgres_manager").join("history.jsonl"));
+    history.append(&entry("app.dump")).unwrap();
+    history.append(&entry("app.dump")).unwrap();
+
+    let mode = std::fs::metadata(history.path()).unwrap().permissions().mode();
+    assert_eq!(mode & 0o777, 0o600);
+    assert_eq!(history.load(10).unwrap().len(), 2);
+}
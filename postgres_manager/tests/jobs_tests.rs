@@ -0,0 +1,164 @@
+mod common;
+
+use common::app_snapshot;
+use postgres_manager::history::HistoryState;
+use postgres_manager::jobs::{estimate_remaining, format_duration, JobKind, JobManager, JobStatus};
+use postgres_manager::s3::TransferProgress;
+use postgres_manager::service::{RestoreRequest, ServiceEvent, SnapshotService};
+use postgres_manager::ui::models::{DatabaseAction, PostgresConfig, S3Config};
+use std::time::Duration;
+
+/// A service whose S3 endpoint refuses connections, downloading into `dir`
+fn service(dir: &std::path::Path) -> SnapshotService {
+    let client = postgres_manager::s3::build_client(&S3Config {
+        bucket: "bucket".to_string(),
+        region: "us-east-1".to_string(),
+        prefix: String::new(),
+        endpoint_url: "http://127.0.0.1:1".to_string(),
+        access_key_id: "key".to_string(),
+        secret_access_key: "secret".to_string(),
+        path_style: true,
+        error_message: None,
+    });
+    let mut service = SnapshotService::new(client, "bucket".to_string(), PostgresConfig::default());
+    service.download_options.max_retries = 0;
+    service.download_dir = dir.to_path_buf();
+    service
+}
+
+async fn wait_for_jobs(jobs: &mut JobManager) {
+    for _ in 0..100 {
+        jobs.poll();
+        if jobs.running() == 0 {
+            return;
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+    panic!("jobs did not finish");
+}
+
+#[test]
+fn test_estimate_remaining() {
+    let downloading = ServiceEvent::Downloading(TransferProgress { transferred: 25, total: 100, rate: 5.0 });
+    assert_eq!(estimate_remaining(&downloading, Duration::from_secs(60)), Some(Duration::from_secs(15)));
+    let stalled = ServiceEvent::Downloading(TransferProgress { transferred: 25, total: 100, rate: 0.0 });
+    assert_eq!(estimate_remaining(&stalled, Duration::from_secs(60)), None);
+
+    let restoring = ServiceEvent::Restoring { fraction: 0.25, current: None, cancellable: true };
+    assert_eq!(estimate_remaining(&restoring, Duration::from_secs(10)), Some(Duration::from_secs(30)));
+    let done = ServiceEvent::Restoring { fraction: 1.0, current: None, cancellable: false };
+    assert_eq!(estimate_remaining(&done, Duration::from_secs(10)), None);
+    assert_eq!(estimate_remaining(&ServiceEvent::Verifying, Duration::from_secs(10)), None);
+}
+
+#[test]
+fn test_format_duration() {
+    assert_eq!(format_duration(Duration::from_secs(42)), "42s");
+    assert_eq!(format_duration(Duration::from_secs(185)), "3m05s");
+    assert_eq!(format_duration(Duration::from_secs(3720)), "1h02m");
+}
+
+#[tokio::test]
+async fn test_one_restore_per_database() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut jobs = JobManager::new();
+    let request = RestoreRequest { snapshot: app_snapshot(), mode: Default::default(), target: "app".to_string() };
+
+    let first = jobs.spawn_restore(service(dir.path()), request.clone()).unwrap();
+    let err = jobs.spawn_restore(service(dir.path()), request.clone()).unwrap_err();
+    assert_eq!(err.to_string(), "A restore into 'app' is already running");
+    let other_snapshot = common::snapshot("backups/app-20240102000000.dump", 1024, 1704153600);
+    let other = RestoreRequest { snapshot: other_snapshot, target: "app_copy".to_string(), ..request.clone() };
+    jobs.spawn_restore(service(dir.path()), other).unwrap();
+    assert_eq!(jobs.running(), 2);
+
+    // Once the first one has failed the database is free again
+    wait_for_jobs(&mut jobs).await;
+    let job = jobs.get(first).unwrap();
+    assert_eq!(job.kind, JobKind::Restore { mode: Default::default(), target: "app".to_string() });
+    assert!(matches!(&job.status, JobStatus::Failed(message) if message.starts_with("Failed to download backup")), "{:?}", job.status);
+    let entry = job.history_entry().unwrap();
+    assert_eq!((entry.kind.as_str(), entry.subject.as_str(), entry.target.as_deref()), ("restore", app_snapshot().key.as_str(), Some("app")));
+    assert_eq!((entry.state, entry.bytes), (HistoryState::Failed, Some(1024)));
+    assert!(entry.error.unwrap().starts_with("Failed to download backup"));
+    assert!(jobs.cancel(first).is_err());
+    let second = jobs.spawn_restore(service(dir.path()), request).unwrap();
+    assert_eq!(jobs.get(second).unwrap().history_entry(), None);
+    assert_eq!(jobs.shutdown().await, vec![second]);
+    assert_eq!(jobs.running(), 0);
+}
+
+#[tokio::test]
+async fn test_one_job_per_download_file() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut jobs = JobManager::new();
+    let request = RestoreRequest { snapshot: app_snapshot(), mode: Default::default(), target: "app".to_string() };
+
+    let first = jobs.spawn_download(service(dir.path()), app_snapshot()).unwrap();
+    let path = postgres_manager::s3::download_path(dir.path(), &app_snapshot().key);
+    let expected = format!("Job #{} is already downloading {} to {}", first, app_snapshot().key, path.display());
+    assert_eq!(jobs.spawn_download(service(dir.path()), app_snapshot()).unwrap_err().to_string(), expected);
+    assert_eq!(jobs.spawn_restore(service(dir.path()), request.clone()).unwrap_err().to_string(), expected);
+
+    // A key that maps to the same file name is refused too, one in another directory isn't
+    let lookalike = common::snapshot("backups_app-20240101000000.dump", 1024, 1704067200);
+    assert!(jobs.spawn_download(service(dir.path()), lookalike).is_err());
+    let other_dir = tempfile::tempdir().unwrap();
+    jobs.spawn_restore(service(other_dir.path()), request.clone()).unwrap();
+    assert_eq!(jobs.running(), 2);
+
+    wait_for_jobs(&mut jobs).await;
+    jobs.spawn_restore(service(dir.path()), request).unwrap();
+    jobs.shutdown().await;
+}
+
+#[tokio::test]
+async fn test_database_jobs() {
+    // A server that refuses connections, so the jobs fail without holding up the test
+    let unreachable = PostgresConfig { host: Some("127.0.0.1".to_string()), port: Some(1), ..PostgresConfig::default() };
+    let mut jobs = JobManager::new();
+    let rename = jobs.spawn_database_action(unreachable.clone(), &DatabaseAction::Rename("app".to_string()), "app_old".to_string());
+    let drop = jobs.spawn_drop_database(unreachable, "app".to_string());
+    assert_eq!(jobs.get(rename).unwrap().summary(), format!("#{} Rename app to 'app_old' - running", rename));
+    assert!(jobs.cancel(drop).is_err());
+    wait_for_jobs(&mut jobs).await;
+
+    let entry = jobs.get(rename).unwrap().history_entry().unwrap();
+    assert_eq!((entry.kind.as_str(), entry.subject.as_str(), entry.target.as_deref()), ("rename-database", "app", Some("app_old")));
+    assert_eq!(entry.state, HistoryState::Failed);
+    let entry = jobs.get(drop).unwrap().history_entry().unwrap();
+    assert_eq!((entry.kind.as_str(), entry.subject.as_str(), entry.target), ("drop", "app", None));
+    assert!(jobs.jobs().iter().all(|job| job.kind.is_database()));
+}
+
+#[tokio::test]
+async fn test_database_jobs_against_server() {
+    let Some(pg_config) = common::pg_config() else {
+        return;
+    };
+    let name = common::scratch_name("db_jobs");
+    let renamed = format!("{}_renamed", name);
+    let owner = pg_config.username.clone().unwrap();
+    let mut jobs = JobManager::new();
+
+    // One at a time, each working on what the previous one left
+    let mut ids = Vec::new();
+    ids.push(jobs.spawn_database_action(pg_config.clone(), &DatabaseAction::Create, name.clone()));
+    wait_for_jobs(&mut jobs).await;
+    ids.push(jobs.spawn_database_action(pg_config.clone(), &DatabaseAction::Rename(name.clone()), renamed.clone()));
+    wait_for_jobs(&mut jobs).await;
+    ids.push(jobs.spawn_database_action(pg_config.clone(), &DatabaseAction::SetOwner(renamed.clone()), owner.clone()));
+    wait_for_jobs(&mut jobs).await;
+    ids.push(jobs.spawn_drop_database(pg_config.clone(), renamed.clone()));
+    wait_for_jobs(&mut jobs).await;
+
+    let messages: Vec<_> = ids.iter().map(|id| jobs.get(*id).unwrap().status.clone()).collect();
+    assert_eq!(messages, [
+        JobStatus::Succeeded(format!("Created database '{}'", name)),
+        JobStatus::Succeeded(format!("Renamed database '{}' to '{}'", name, renamed)),
+        JobStatus::Succeeded(format!("Database '{}' is now owned by '{}'", renamed, owner)),
+        JobStatus::Succeeded(format!("Dropped database '{}'", renamed)),
+    ]);
+    let client = common::pg_client(&pg_config, None).await;
+    assert!(!postgres_manager::postgres::database_exists(&client, &renamed).await.unwrap());
+}
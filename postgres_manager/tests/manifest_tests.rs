@@ -0,0 +1,38 @@
+use postgres_manager::manifest::SnapshotManifest;
+
+#[test]
+fn test_manifest_key() {
+    assert_eq!(SnapshotManifest::key("backups/app-20240101.dump.zst"), "backups/app-20240101.dump.zst.manifest.json");
+}
+
+#[test]
+fn test_manifest_roundtrip() {
+    let manifest = SnapshotManifest {
+        host: "db.internal".to_string(),
+        port: 5432,
+        database: "app".to_string(),
+        server_version: Some("16.2".to_string()),
+        pg_dump_version: Some("pg_dump (PostgreSQL) 16.2".to_string()),
+        format: "custom".to_string(),
+        compression: "zstd".to_string(),
+        encrypted: true,
+        sha256: "ab".repeat(32),
+        size: 1024,
+        started_at: "2024-01-01T00:00:00+00:00".to_string(),
+        duration_secs: 12.5,
+    };
+
+    let json = manifest.to_json().unwrap();
+    assert_eq!(SnapshotManifest::from_json(&json).unwrap(), manifest);
+}
+
+#[test]
+fn test_manifest_missing_fields() {
+    // Fields added later are missing from older manifests
+    let manifest = SnapshotManifest::from_json(r#"{"database": "app", "format": "plain"}"#).unwrap();
+    assert_eq!(manifest.database, "app");
+    assert_eq!(manifest.format, "plain");
+    assert_eq!(manifest.server_version, None);
+
+    assert!(SnapshotManifest::from_json("not json").is_err());
+}
@@ -0,0 +1,122 @@
+mod common;
+
+use postgres_manager::history::{HistoryEntry, HistoryState};
+use postgres_manager::output::{format_history, format_snapshots, OutputFormat, ProgressEvent};
+use postgres_manager::s3::TransferProgress;
+use postgres_manager::service::ServiceEvent;
+use postgres_manager::ui::models::BackupMetadata;
+
+fn snapshots() -> Vec<BackupMetadata> {
+    vec![
+        common::snapshot("backups/app-20240102000000.dump", 1_500_000, 1704153600),
+        common::snapshot("backups/odd, \"name\".dump", 42, 1704067200),
+    ]
+}
+
+#[test]
+fn test_format_table() {
+    assert_eq!(
+        format_snapshots(&snapshots(), OutputFormat::Table).unwrap(),
+        "KEY                                    SIZE  LAST MODIFIED\n\
+         backups/app-20240102000000.dump     1.50 MB  2024-01-02T00:00:00Z\n\
+         backups/odd, \"name\".dump               42 B  2024-01-01T00:00:00Z\n"
+    );
+}
+
+#[test]
+fn test_format_json() {
+    let json: serde_json::Value = serde_json::from_str(&format_snapshots(&snapshots(), OutputFormat::Json).unwrap()).unwrap();
+    assert_eq!(
+        json,
+        serde_json::json!([
+            {"key": "backups/app-20240102000000.dump", "size": 1500000, "last_modified": "2024-01-02T00:00:00Z"},
+            {"key": "backups/odd, \"name\".dump", "size": 42, "last_modified": "2024-01-01T00:00:00Z"},
+        ])
+    );
+    assert_eq!(format_snapshots(&[], OutputFormat::Json).unwrap(), "[]\n");
+}
+
+#[test]
+fn test_format_csv() {
+    assert_eq!(
+        format_snapshots(&snapshots(), OutputFormat::Csv).unwrap(),
+        "key,size,last_modified\n\
+         backups/app-20240102000000.dump,1500000,2024-01-02T00:00:00Z\n\
+         \"backups/odd, \"\"name\"\".dump\",42,2024-01-01T00:00:00Z\n"
+    );
+}
+
+#[test]
+fn test_format_history() {
+    let entries = vec![
+        HistoryEntry {
+            kind: "restore".to_string(),
+            subject: "backups/app.dump".to_string(),
+            target: Some("app".to_string()),
+            state: HistoryState::Succeeded,
+            started_at: "2024-01-02T00:00:00Z".to_string(),
+            finished_at: "2024-01-02T00:01:30Z".to_string(),
+            duration_secs: 90.0,
+            bytes: Some(1_500_000),
+            message: None,
+            error: None,
+        },
+        HistoryEntry {
+            kind: "dump".to_string(),
+            subject: "crm".to_string(),
+            target: None,
+            state: HistoryState::Failed,
+            started_at: "2024-01-01T00:00:00Z".to_string(),
+            finished_at: "2024-01-01T00:00:02Z".to_string(),
+            duration_secs: 2.5,
+            bytes: None,
+            message: None,
+            error: Some("pg_dump failed, exit code 1".to_string()),
+        },
+    ];
+
+    assert_eq!(
+        format_history(&entries, OutputFormat::Table).unwrap(),
+        "FINISHED              KIND      STATE            SIZE  DURATION  SUBJECT\n\
+         2024-01-02T00:01:30Z  restore   succeeded     1.50 MB     90.0s  backups/app.dump -> app\n\
+         2024-01-01T00:00:02Z  dump      failed                  2.5s  crm: pg_dump failed, exit code 1\n"
+    );
+    assert_eq!(
+        format_history(&entries, OutputFormat::Csv).unwrap(),
+        "finished_at,kind,state,subject,target,bytes,duration_secs,error\n\
+         2024-01-02T00:01:30Z,restore,succeeded,backups/app.dump,app,1500000,90,\n\
+         2024-01-01T00:00:02Z,dump,failed,crm,,,2.5,\"pg_dump failed, exit code 1\"\n"
+    );
+    let json: serde_json::Value = serde_json::from_str(&format_history(&entries, OutputFormat::Json).unwrap()).unwrap();
+    assert_eq!(json[0]["state"], "succeeded");
+    assert_eq!(json[1].get("bytes"), None);
+}
+
+#[test]
+fn test_progress_event_from_service() {
+    let progress = TransferProgress { transferred: 50, total: 100, rate: 2_000_000.0 };
+    let mut event = ProgressEvent::from_service("backups/app.dump", &ServiceEvent::Downloading(progress));
+    event.time = "2024-01-01T00:00:00Z".to_string();
+    assert_eq!(event.to_string(), "2024-01-01T00:00:00Z download backups/app.dump 50% 2 MB/s");
+    assert_eq!(
+        serde_json::to_value(&event).unwrap(),
+        serde_json::json!({
+            "time": "2024-01-01T00:00:00Z",
+            "stage": "download",
+            "key": "backups/app.dump",
+            "progress": 0.5,
+            "rate": 2000000.0,
+        })
+    );
+
+    let event = ProgressEvent::from_service("backups/app.dump", &ServiceEvent::Restoring {
+        fraction: 0.25,
+        current: Some("TABLE users".to_string()),
+        cancellable: false,
+    });
+    assert_eq!((event.stage, event.progress, event.message.as_deref()), ("restore", Some(0.25), Some("TABLE users")));
+
+    let mut event = ProgressEvent::finished(false, "boom".to_string());
+    event.time = "2024-01-01T00:00:00Z".to_string();
+    assert_eq!(event.to_string(), "2024-01-01T00:00:00Z error: boom");
+}
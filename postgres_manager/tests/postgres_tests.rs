@@ -0,0 +1,46 @@
+use postgres_manager::postgres::{clone_database_sql, clone_name, quote_identifier, validate_identifier, MAX_IDENTIFIER_LEN};
+
+#[test]
+fn test_quote_identifier() {
+    assert_eq!(quote_identifier("app").unwrap(), r#""app""#);
+    assert_eq!(quote_identifier("App-Restored").unwrap(), r#""App-Restored""#);
+    assert_eq!(quote_identifier("with space").unwrap(), r#""with space""#);
+}
+
+#[test]
+fn test_quote_identifier_hostile_names() {
+    // Embedded quotes are doubled so the name can never terminate the identifier early
+    assert_eq!(quote_identifier(r#"a"b"#).unwrap(), r#""a""b""#);
+    assert_eq!(
+        quote_identifier(r#"x"; DROP DATABASE postgres; --"#).unwrap(),
+        r#""x""; DROP DATABASE postgres; --""#
+    );
+    assert_eq!(quote_identifier(r#"""#).unwrap(), r#""""""#);
+    assert_eq!(quote_identifier(r"back\slash").unwrap(), r#""back\slash""#);
+}
+
+#[test]
+fn test_validate_identifier_rejects_invalid_names() {
+    assert!(validate_identifier("").is_err());
+    assert!(validate_identifier("nul\0byte").is_err());
+    assert!(validate_identifier(&"a".repeat(MAX_IDENTIFIER_LEN)).is_ok());
+    assert!(validate_identifier(&"a".repeat(MAX_IDENTIFIER_LEN + 1)).is_err());
+    assert!(quote_identifier("nul\0byte").is_err());
+}
+
+#[test]
+fn test_clone_name() {
+    assert_eq!(clone_name("app"), "app-clone");
+    // Too long to clone without PostgreSQL truncating the new name
+    assert!(validate_identifier(&clone_name(&"a".repeat(MAX_IDENTIFIER_LEN))).is_err());
+}
+
+#[test]
+fn test_clone_database_sql_keeps_owner() {
+    // The clone belongs to the source database's owner, not to a role named after the database
+    assert_eq!(
+        clone_database_sql("app", "app_owner").unwrap(),
+        "CREATE DATABASE \"app-clone\" WITH TEMPLATE \"app\" OWNER \"app_owner\";"
+    );
+    assert!(clone_database_sql("app", "").is_err());
+}
@@ -0,0 +1,96 @@
+mod common;
+
+use chrono::{TimeZone, Utc};
+use postgres_manager::retention::{plan, snapshot_series, RetentionPolicy};
+use postgres_manager::ui::models::BackupMetadata;
+
+fn snapshot(name: &str, year: i32, month: u32, day: u32, hour: u32) -> BackupMetadata {
+    let time = Utc.with_ymd_and_hms(year, month, day, hour, 0, 0).unwrap();
+    common::snapshot(&format!("backups/{}-{}.dump", name, time.format("%Y%m%d%H%M%S")), 100, time.timestamp())
+}
+
+fn keys(snapshots: &[BackupMetadata]) -> Vec<&str> {
+    snapshots.iter().map(|s| s.key.as_str()).collect()
+}
+
+#[test]
+fn test_snapshot_series() {
+    assert_eq!(snapshot_series("backups/app-20240101120000.dump"), "backups/app");
+    assert_eq!(snapshot_series("backups/my-app-20240101120000.dump.zst.age"), "backups/my-app");
+    assert_eq!(snapshot_series("backups/manual.dump"), "backups/manual.dump");
+}
+
+#[test]
+fn test_retention_keeps_newest_per_period() {
+    // Two backups a day for ten days
+    let snapshots: Vec<_> = (1..=10)
+        .flat_map(|day| [snapshot("app", 2024, 1, day, 6), snapshot("app", 2024, 1, day, 18)])
+        .collect();
+    let policy = RetentionPolicy { hourly: 2, daily: 3, weekly: 0, monthly: 0 };
+
+    let plan = plan(&snapshots, &policy).unwrap();
+    assert_eq!(
+        keys(&plan.keep),
+        vec![
+            "backups/app-20240110180000.dump",
+            "backups/app-20240110060000.dump",
+            "backups/app-20240109180000.dump",
+            "backups/app-20240108180000.dump",
+        ]
+    );
+    assert_eq!(plan.delete.len(), 16);
+    assert_eq!(plan.delete_size(), 1600);
+}
+
+#[test]
+fn test_retention_weekly_and_monthly() {
+    // Daily backups through January and February 2024
+    let snapshots: Vec<_> = (1..=31)
+        .map(|day| snapshot("app", 2024, 1, day, 0))
+        .chain((1..=29).map(|day| snapshot("app", 2024, 2, day, 0)))
+        .collect();
+    let policy = RetentionPolicy { hourly: 0, daily: 1, weekly: 2, monthly: 2 };
+
+    let plan = plan(&snapshots, &policy).unwrap();
+    assert_eq!(
+        keys(&plan.keep),
+        vec![
+            // Newest daily, and newest of ISO week 9 and month 2024-02
+            "backups/app-20240229000000.dump",
+            // Newest of ISO week 8
+            "backups/app-20240225000000.dump",
+            // Newest of month 2024-01
+            "backups/app-20240131000000.dump",
+        ]
+    );
+}
+
+#[test]
+fn test_retention_per_series_and_sidecars() {
+    let mut snapshots = vec![
+        snapshot("app", 2024, 1, 1, 0),
+        snapshot("app", 2024, 1, 2, 0),
+        snapshot("billing", 2024, 1, 1, 0),
+    ];
+    snapshots.push(BackupMetadata { key: format!("{}.sha256", snapshots[0].key), ..snapshots[0].clone() });
+    snapshots.push(BackupMetadata { key: format!("{}.sha256", snapshots[1].key), ..snapshots[1].clone() });
+    snapshots.push(BackupMetadata { key: format!("{}.manifest.json", snapshots[0].key), ..snapshots[0].clone() });
+    let policy = RetentionPolicy { hourly: 0, daily: 1, weekly: 0, monthly: 0 };
+
+    let plan = plan(&snapshots, &policy).unwrap();
+    assert_eq!(keys(&plan.keep), vec!["backups/app-20240102000000.dump", "backups/billing-20240101000000.dump"]);
+    assert_eq!(
+        plan.delete_keys(),
+        vec![
+            "backups/app-20240101000000.dump".to_string(),
+            "backups/app-20240101000000.dump.sha256".to_string(),
+            "backups/app-20240101000000.dump.manifest.json".to_string(),
+        ]
+    );
+}
+
+#[test]
+fn test_retention_refuses_to_delete_everything() {
+    let policy = RetentionPolicy { hourly: 0, daily: 0, weekly: 0, monthly: 0 };
+    assert!(plan(&[snapshot("app", 2024, 1, 1, 0)], &policy).is_err());
+}
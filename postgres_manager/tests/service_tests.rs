@@ -0,0 +1,190 @@
+mod common;
+
+use common::app_snapshot;
+use postgres_manager::cancel::CancelToken;
+use postgres_manager::service::{pg_connection_config, validate_pg_config, RestoreRequest, SnapshotService};
+use postgres_manager::ui::models::{BackupMetadata, KeyAction, PostgresConfig, S3Config};
+
+fn pg_config() -> PostgresConfig {
+    PostgresConfig {
+        host: Some("db.internal".to_string()),
+        port: Some(5433),
+        username: Some("postgres".to_string()),
+        password: Some("secret".to_string()),
+        use_ssl: false,
+        verify_ssl: false,
+        root_cert_path: None,
+        db_name: Some("app".to_string()),
+    }
+}
+
+/// A service whose S3 endpoint refuses connections
+fn service() -> SnapshotService {
+    let client = postgres_manager::s3::build_client(&S3Config {
+        bucket: "bucket".to_string(),
+        region: "us-east-1".to_string(),
+        prefix: String::new(),
+        endpoint_url: "http://127.0.0.1:1".to_string(),
+        access_key_id: "key".to_string(),
+        secret_access_key: "secret".to_string(),
+        path_style: true,
+        error_message: None,
+    });
+    SnapshotService::new(client, "bucket".to_string(), pg_config())
+}
+
+#[test]
+fn test_validate_pg_config() {
+    assert!(validate_pg_config(&pg_config()).is_ok());
+    let missing_host = PostgresConfig { host: Some(String::new()), ..pg_config() };
+    assert_eq!(validate_pg_config(&missing_host).unwrap_err().to_string(), "PostgreSQL host is required");
+    let missing_user = PostgresConfig { username: None, ..pg_config() };
+    assert_eq!(validate_pg_config(&missing_user).unwrap_err().to_string(), "PostgreSQL username is required");
+}
+
+#[test]
+fn test_pg_connection_config() {
+    let config = pg_connection_config(&pg_config(), Some("restored"));
+    assert_eq!(config.get_ports(), &[5433]);
+    assert_eq!(config.get_user(), Some("postgres"));
+    assert_eq!(config.get_dbname(), Some("restored"));
+    assert_eq!(pg_connection_config(&pg_config(), None).get_dbname(), None);
+}
+
+#[tokio::test]
+async fn test_copy_rejects_same_key() {
+    let err = service().copy_snapshot(&app_snapshot(), &app_snapshot().key, KeyAction::Copy).await.unwrap_err();
+    assert_eq!(err.to_string(), "Enter a new key for backups/app-20240101000000.dump");
+}
+
+#[tokio::test]
+async fn test_copy_keeps_encoding_suffix() {
+    for new_key in ["backups/app.dump.age", "backups/app.dump.zst", "backups/app.dump.gz"] {
+        let err = service().copy_snapshot(&app_snapshot(), new_key, KeyAction::Rename).await.unwrap_err();
+        assert_eq!(err.to_string(), format!("{} must keep the .age/.zst/.gz suffix of backups/app-20240101000000.dump", new_key));
+    }
+
+    let encrypted = BackupMetadata { key: "backups/app.dump.zst.age".to_string(), ..app_snapshot() };
+    for new_key in ["backups/app.dump.zst", "backups/app.dump.age", "backups/app.dump.gz.age"] {
+        assert!(service().copy_snapshot(&encrypted, new_key, KeyAction::Copy).await.is_err(), "{}", new_key);
+    }
+}
+
+/// A prefix in the live test bucket, unique to one test
+struct LiveBucket {
+    client: aws_sdk_s3::Client,
+    bucket: String,
+    prefix: String,
+}
+
+impl LiveBucket {
+    /// Upload `keys` under the prefix, each holding its own name, and return a service against the bucket
+    async fn with_objects(test: &str, keys: &[&str]) -> Option<(Self, SnapshotService)> {
+        let config = common::s3_config()?;
+        let client = postgres_manager::s3::build_client(&config);
+        let prefix = common::scratch_key(test).trim_end_matches(".dump").to_string();
+        let live = Self { client: client.clone(), bucket: config.bucket.clone(), prefix };
+        for key in keys {
+            live.client.put_object()
+                .bucket(&live.bucket)
+                .key(live.key(key))
+                .body(aws_sdk_s3::primitives::ByteStream::from(key.as_bytes().to_vec()))
+                .send()
+                .await
+                .unwrap();
+        }
+        Some((live, SnapshotService::new(client, config.bucket, pg_config())))
+    }
+
+    fn key(&self, key: &str) -> String {
+        format!("{}/{}", self.prefix, key)
+    }
+
+    fn snapshot(&self, key: &str) -> BackupMetadata {
+        BackupMetadata { key: self.key(key), ..app_snapshot() }
+    }
+
+    /// Keys under the prefix, relative to it, with the body of each
+    async fn objects(&self) -> Vec<(String, String)> {
+        let resp = self.client.list_objects_v2().bucket(&self.bucket).prefix(self.key("")).send().await.unwrap();
+        let mut objects = Vec::new();
+        for object in resp.contents() {
+            let key = object.key().unwrap();
+            let body = self.client.get_object().bucket(&self.bucket).key(key).send().await.unwrap().body.collect().await.unwrap();
+            objects.push((key[self.prefix.len() + 1..].to_string(), String::from_utf8(body.to_vec()).unwrap()));
+        }
+        objects
+    }
+
+    async fn keys(&self) -> Vec<String> {
+        self.objects().await.into_iter().map(|(key, _)| key).collect()
+    }
+}
+
+#[tokio::test]
+async fn test_copy_and_rename_snapshot_with_sidecars() {
+    let Some((live, service)) = LiveBucket::with_objects("copy", &["app.dump", "app.dump.sha256", "app.dump.manifest.json"]).await else {
+        return;
+    };
+
+    service.copy_snapshot(&live.snapshot("app.dump"), &live.key("copy.dump"), KeyAction::Copy).await.unwrap();
+    let owned = |pairs: &[(&str, &str)]| pairs.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect::<Vec<_>>();
+    assert_eq!(live.objects().await, owned(&[
+        ("app.dump", "app.dump"),
+        ("app.dump.manifest.json", "app.dump.manifest.json"),
+        ("app.dump.sha256", "app.dump.sha256"),
+        ("copy.dump", "app.dump"),
+        ("copy.dump.manifest.json", "app.dump.manifest.json"),
+        ("copy.dump.sha256", "app.dump.sha256"),
+    ]));
+
+    // An existing key is never overwritten
+    let err = service.copy_snapshot(&live.snapshot("app.dump"), &live.key("copy.dump"), KeyAction::Rename).await.unwrap_err();
+    assert_eq!(err.to_string(), format!("{} already exists", live.key("copy.dump")));
+
+    // The original and its sidecars are only gone once all of them are copied
+    service.copy_snapshot(&live.snapshot("app.dump"), &live.key("renamed.dump"), KeyAction::Rename).await.unwrap();
+    assert_eq!(live.keys().await, [
+        "copy.dump", "copy.dump.manifest.json", "copy.dump.sha256",
+        "renamed.dump", "renamed.dump.manifest.json", "renamed.dump.sha256",
+    ]);
+
+    service.delete_snapshot(&live.snapshot("copy.dump")).await.unwrap();
+    service.delete_snapshot(&live.snapshot("renamed.dump")).await.unwrap();
+    assert!(live.keys().await.is_empty());
+}
+
+#[tokio::test]
+async fn test_failed_rename_keeps_original() {
+    // Only a sidecar is left, so copying the snapshot itself fails
+    let Some((live, service)) = LiveBucket::with_objects("rename_fails", &["app.dump.sha256"]).await else {
+        return;
+    };
+
+    assert!(service.copy_snapshot(&live.snapshot("app.dump"), &live.key("renamed.dump"), KeyAction::Rename).await.is_err());
+    assert_eq!(live.keys().await, ["app.dump.sha256"]);
+
+    // Deleting a snapshot doesn't need every sidecar to exist
+    service.delete_snapshot(&live.snapshot("app.dump")).await.unwrap();
+    assert!(live.keys().await.is_empty());
+}
+
+#[tokio::test]
+async fn test_restore_reports_download_failure() {
+    let request = RestoreRequest { snapshot: app_snapshot(), mode: Default::default(), target: "app".to_string() };
+    let (events_tx, mut events) = tokio::sync::mpsc::unbounded_channel();
+    let dir = tempfile::tempdir().unwrap();
+    let mut service = service();
+    service.download_options.max_retries = 0;
+    service.download_dir = dir.path().to_path_buf();
+
+    let err = service.restore(&request, &events_tx, &CancelToken::new()).await.unwrap_err();
+    assert!(err.to_string().starts_with("Failed to download backup"), "{:#}", err);
+    drop(events_tx);
+
+    let mut received = Vec::new();
+    while let Some(event) = events.recv().await {
+        received.push(event);
+    }
+    assert_eq!(received.first(), Some(&postgres_manager::service::ServiceEvent::Downloading(Default::default())));
+}
@@ -0,0 +1,43 @@
+mod common;
+
+use common::snapshot;
+use postgres_manager::sort::{SnapshotSort, SortField};
+
+fn sorted_keys(sort: &str) -> Vec<String> {
+    let mut snapshots = vec![
+        snapshot("b.dump", 300, 100),
+        snapshot("c.dump", 100, 300),
+        snapshot("a.dump", 200, 200),
+        snapshot("d.dump", 200, 200),
+    ];
+    sort.parse::<SnapshotSort>().unwrap().apply(&mut snapshots);
+    snapshots.into_iter().map(|s| s.key).collect()
+}
+
+#[test]
+fn test_parse_sort() {
+    assert_eq!("modified".parse::<SnapshotSort>().unwrap(), SnapshotSort::default());
+    assert_eq!("size".parse::<SnapshotSort>().unwrap(), SnapshotSort { field: SortField::Size, descending: true });
+    assert_eq!("key".parse::<SnapshotSort>().unwrap(), SnapshotSort { field: SortField::Key, descending: false });
+    assert_eq!("Key:DESC".parse::<SnapshotSort>().unwrap(), SnapshotSort { field: SortField::Key, descending: true });
+    assert!("owner".parse::<SnapshotSort>().is_err());
+    assert!("size:up".parse::<SnapshotSort>().is_err());
+    assert_eq!(SnapshotSort::default().to_string(), "modified:desc");
+}
+
+#[test]
+fn test_apply_sort() {
+    assert_eq!(sorted_keys("modified"), vec!["c.dump", "d.dump", "a.dump", "b.dump"]);
+    assert_eq!(sorted_keys("modified:asc"), vec!["b.dump", "a.dump", "d.dump", "c.dump"]);
+    assert_eq!(sorted_keys("size"), vec!["b.dump", "d.dump", "a.dump", "c.dump"]);
+    assert_eq!(sorted_keys("key"), vec!["a.dump", "b.dump", "c.dump", "d.dump"]);
+}
+
+#[test]
+fn test_cycle_sort() {
+    let sort = SnapshotSort::default().cycle_field();
+    assert_eq!(sort, SnapshotSort { field: SortField::Size, descending: true });
+    let sort = sort.reversed().cycle_field();
+    assert_eq!(sort, SnapshotSort { field: SortField::Key, descending: false });
+    assert_eq!(sort.cycle_field(), SnapshotSort::default());
+}
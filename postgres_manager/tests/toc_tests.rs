@@ -0,0 +1,95 @@
+use postgres_manager::toc::{parse_toc, parse_verbose_line, table_count, TocEntry, TocTracker};
+
+const LISTING: &str = r#";
+; Archive created at 2024-03-01 12:00:00 UTC
+;     dbname: app
+;
+; Selected TOC Entries:
+;
+215; 1259 16386 TABLE public users postgres
+216; 1259 16385 SEQUENCE public users_id_seq postgres
+3345; 0 16386 TABLE DATA public users postgres
+3352; 0 0 SEQUENCE SET public users_id_seq postgres
+3196; 2606 16391 CONSTRAINT public users users_pkey postgres
+3200; 2606 16400 FK CONSTRAINT public orders orders_user_id_fkey postgres
+2; 3079 16384 EXTENSION - pgcrypto
+"#;
+
+#[test]
+fn test_parse_toc() {
+    let entries = parse_toc(LISTING);
+    assert_eq!(entries.len(), 7);
+    assert_eq!(entries[0], TocEntry { desc: "TABLE".to_string(), name: "public.users".to_string() });
+    assert_eq!(entries[2], TocEntry { desc: "TABLE DATA".to_string(), name: "public.users".to_string() });
+    assert_eq!(entries[3], TocEntry { desc: "SEQUENCE SET".to_string(), name: "public.users_id_seq".to_string() });
+    assert_eq!(entries[4], TocEntry { desc: "CONSTRAINT".to_string(), name: "public.users users_pkey".to_string() });
+    assert_eq!(entries[5].desc, "FK CONSTRAINT");
+    assert_eq!(entries[6], TocEntry { desc: "EXTENSION".to_string(), name: "pgcrypto".to_string() });
+}
+
+#[test]
+fn test_table_count() {
+    // Data, sequences and constraints of a table don't count as tables of their own
+    assert_eq!(table_count(&parse_toc(LISTING)), 1);
+    assert_eq!(table_count(&[]), 0);
+}
+
+#[test]
+fn test_parse_verbose_line() {
+    assert_eq!(
+        parse_verbose_line(r#"pg_restore: creating TABLE "public.users""#),
+        Some(("TABLE".to_string(), "public.users".to_string()))
+    );
+    assert_eq!(
+        parse_verbose_line(r#"pg_restore: processing data for table "public.users""#),
+        Some(("TABLE DATA".to_string(), "public.users".to_string()))
+    );
+    assert_eq!(
+        parse_verbose_line(r#"pg_restore: creating FK CONSTRAINT "public.orders orders_user_id_fkey""#),
+        Some(("FK CONSTRAINT".to_string(), "public.orders orders_user_id_fkey".to_string()))
+    );
+    assert_eq!(parse_verbose_line("pg_restore: connecting to database for restore"), None);
+    assert_eq!(parse_verbose_line("pg_restore: error: could not execute query"), None);
+}
+
+#[test]
+fn test_toc_tracker_progress() {
+    let mut tracker = TocTracker::new(parse_toc(LISTING));
+    assert_eq!(tracker.progress().fraction(), 0.0);
+
+    assert!(tracker.observe(r#"pg_restore: creating TABLE "public.users""#));
+    assert!(tracker.observe(r#"pg_restore: processing data for table "public.users""#));
+    assert!(!tracker.observe("pg_restore: connecting to database for restore"));
+
+    let progress = tracker.progress();
+    assert_eq!(progress.completed, 2);
+    assert_eq!(progress.total, 7);
+    assert_eq!(progress.current.as_deref(), Some("public.users"));
+}
+
+#[test]
+fn test_toc_tracker_falls_back_to_type() {
+    let entry = |desc: &str, name: &str| TocEntry { desc: desc.to_string(), name: name.to_string() };
+    let mut tracker = TocTracker::new(vec![entry("INDEX", "public.a_idx"), entry("INDEX", "public.b_idx")]);
+
+    // An unknown name takes the first pending entry of its type, the exact one then falls back to the next
+    assert!(tracker.observe(r#"pg_restore: creating INDEX "public.other_idx""#));
+    assert!(tracker.observe(r#"pg_restore: creating INDEX "public.a_idx""#));
+    assert_eq!(tracker.progress().completed, 2);
+    assert!(tracker.observe(r#"pg_restore: creating INDEX "public.b_idx""#));
+    assert_eq!(tracker.progress().completed, 2);
+}
+
+#[test]
+fn test_toc_tracker_many_entries() {
+    // Partitioned schemas easily have tens of thousands of entries
+    let entries: Vec<TocEntry> = (0..50_000)
+        .map(|i| TocEntry { desc: "TABLE DATA".to_string(), name: format!("public.part_{}", i) })
+        .collect();
+    let mut tracker = TocTracker::new(entries);
+    for i in (0..50_000).rev() {
+        tracker.observe(&format!(r#"pg_restore: processing data for table "public.part_{}""#, i));
+    }
+    assert_eq!(tracker.progress().completed, 50_000);
+    assert_eq!(tracker.progress().fraction(), 1.0);
+}